@@ -0,0 +1,260 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+};
+
+use anyhow::Result;
+
+use super::{
+    page::PayloadLimits,
+    tree::{PageConsumer, PageSupplier, Pager, SharedPage},
+};
+
+/// How much the page cache is allowed to hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CacheLimit {
+    Pages(usize),
+    Bytes(usize),
+}
+
+impl Default for CacheLimit {
+    fn default() -> Self {
+        // same as sqlite's default cache_size of -2000 (KiB)
+        Self::Bytes(2000 * 1024)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "page cache: {} hits, {} misses, {} evictions",
+            self.hits, self.misses, self.evictions
+        )
+    }
+}
+
+struct CacheEntry {
+    page: SharedPage,
+    last_used: u64,
+}
+
+/// Bounded LRU cache sitting in front of another supplier, pages are handed out as shared
+/// buffers so they stay valid even after being evicted
+pub(crate) struct PageCache<S> {
+    inner: S,
+    capacity: usize,
+    entries: HashMap<usize, CacheEntry>,
+    /// page numbers by last use, the first one is the next to go
+    lru: BTreeMap<u64, usize>,
+    tick: u64,
+    current: Option<(usize, SharedPage)>,
+    stats: CacheStats,
+}
+
+impl<S: PageSupplier<Error = anyhow::Error>> PageCache<S> {
+    pub(crate) fn new(inner: S, page_size: usize, limit: CacheLimit) -> Self {
+        let capacity = match limit {
+            CacheLimit::Pages(n) => n,
+            CacheLimit::Bytes(n) => n / page_size,
+        };
+        Self {
+            inner,
+            // we need at least the page we are currently on
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            current: None,
+            stats: CacheStats::default(),
+        }
+    }
+
+    fn get(&mut self, page_idx: usize) -> Result<SharedPage> {
+        self.tick += 1;
+        if let Some(entry) = self.entries.get_mut(&page_idx) {
+            self.stats.hits += 1;
+            self.lru.remove(&entry.last_used);
+            entry.last_used = self.tick;
+            self.lru.insert(self.tick, page_idx);
+            return Ok(entry.page.clone());
+        }
+        self.stats.misses += 1;
+        let page = self.inner.shared_page(page_idx)?;
+        if self.entries.len() >= self.capacity {
+            if let Some((_, evicted)) = self.lru.pop_first() {
+                self.entries.remove(&evicted);
+                self.stats.evictions += 1;
+            }
+        }
+        self.entries.insert(
+            page_idx,
+            CacheEntry {
+                page: page.clone(),
+                last_used: self.tick,
+            },
+        );
+        self.lru.insert(self.tick, page_idx);
+        Ok(page)
+    }
+}
+
+impl<S: PageSupplier<Error = anyhow::Error>> PageSupplier for PageCache<S> {
+    type Error = anyhow::Error;
+
+    fn move_to_page(&mut self, page_idx: usize) -> Result<()> {
+        let page = self.get(page_idx)?;
+        self.current = Some((page_idx, page));
+        Ok(())
+    }
+
+    fn read_page(&mut self, page_idx: usize) -> Result<&[u8]> {
+        self.move_to_page(page_idx)?;
+        let offset = if page_idx == 1 { 100 } else { 0 };
+        Ok(&self.page()[offset..])
+    }
+
+    fn page(&self) -> &[u8] {
+        self.current.as_ref().map_or(&[], |(_, p)| p)
+    }
+
+    fn payload_limits(&self) -> PayloadLimits {
+        self.inner.payload_limits()
+    }
+
+    fn shared_page(&mut self, page_idx: usize) -> Result<SharedPage> {
+        self.get(page_idx)
+    }
+
+    fn db_size(&self) -> Option<usize> {
+        self.inner.db_size()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.stats)
+    }
+}
+
+impl<S: Pager> PageConsumer for PageCache<S> {
+    type Error = anyhow::Error;
+
+    /// Writes through to `inner`, keeping the cached copy (if any) up to date
+    fn write_page(&mut self, page_idx: usize, page: &[u8]) -> Result<()> {
+        self.inner.write_page(page_idx, page)?;
+        let page = SharedPage::from(page);
+        if let Some(entry) = self.entries.get_mut(&page_idx) {
+            entry.page = page.clone();
+        }
+        if let Some((current, current_page)) = &mut self.current {
+            if *current == page_idx {
+                *current_page = page;
+            }
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn truncate(&mut self, pages: usize) -> Result<()> {
+        self.inner.truncate(pages)?;
+        let lru = &mut self.lru;
+        self.entries.retain(|&idx, entry| {
+            if idx > pages {
+                lru.remove(&entry.last_used);
+            }
+            idx <= pages
+        });
+        if self.current.as_ref().is_some_and(|(idx, _)| *idx > pages) {
+            self.current = None;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+
+    use super::*;
+    use crate::database::{header::Header, pager::FilePager};
+
+    fn cache(limit: CacheLimit) -> Result<PageCache<FilePager>> {
+        let file = File::open("fixtures/index.db")?;
+        let header = Header::new(File::open("fixtures/index.db")?)?;
+        let page_size = header.page_size as usize;
+        let pager = FilePager::new(file, page_size, header.payload_limits());
+        Ok(PageCache::new(pager, page_size, limit))
+    }
+
+    #[test]
+    fn test_lru() -> Result<()> {
+        let mut cache = cache(CacheLimit::Pages(2))?;
+        cache.read_page(2)?;
+        cache.read_page(3)?;
+        cache.read_page(2)?;
+        // 3 is the least recently used one
+        cache.read_page(4)?;
+        cache.read_page(2)?;
+        cache.read_page(3)?;
+        assert_eq!(
+            cache.cache_stats(),
+            Some(CacheStats {
+                hits: 2,
+                misses: 4,
+                evictions: 2
+            })
+        );
+        // 512 bytes pages
+        let mut cache = cache_with_bytes(1024)?;
+        cache.read_page(2)?;
+        cache.read_page(3)?;
+        cache.read_page(4)?;
+        cache.read_page(2)?;
+        assert_eq!(cache.cache_stats().map(|s| s.evictions), Some(2));
+        Ok(())
+    }
+
+    fn cache_with_bytes(bytes: usize) -> Result<PageCache<FilePager>> {
+        cache(CacheLimit::Bytes(bytes))
+    }
+
+    #[test]
+    fn test_shared_pages() -> Result<()> {
+        let mut cache = cache(CacheLimit::Pages(1))?;
+        let first = cache.shared_page(1)?;
+        let second = cache.shared_page(2)?;
+        // the first page has been evicted but whoever holds it can keep reading it
+        assert_eq!(&first[..16], b"SQLite format 3\0");
+        assert_eq!(second[0], 5);
+        assert_eq!(second.as_ptr(), cache.shared_page(2)?.as_ptr());
+        assert_eq!(cache.read_page(1)?.len(), 512 - 100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tiny_cache() -> Result<()> {
+        use crate::database::{DbAccess, OpenOptions};
+        let options = OpenOptions {
+            cache: CacheLimit::Pages(1),
+            ..Default::default()
+        };
+        let mut db = DbAccess::open(File::open("fixtures/index.db")?, &options)?;
+        let rows = db
+            .run_query("SELECT id FROM people WHERE age = 50")
+            .swap_remove(0)
+            .rows;
+        assert_eq!(rows.len(), (1..=2000).filter(|i| i % 97 == 50).count());
+        let stats = db.cache_stats().unwrap();
+        assert!(stats.evictions > 0 && stats.evictions == stats.misses - 1);
+        Ok(())
+    }
+}
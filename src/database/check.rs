@@ -0,0 +1,771 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use crate::sql::syntax::DbValue;
+
+use super::{
+    freelist::{Freelist, FreelistError},
+    header::{BTreeHeader, BTreePageType, TextEncoding},
+    ptrmap::{PointerMap, PtrmapEntry},
+    record::Record,
+    tree::{compare_index_keys, Cell, PageSupplier},
+};
+
+/// Something wrong found while checking the database file
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Problem {
+    /// `page` is referenced by `parent` but does not exist
+    OutOfRange {
+        page: usize,
+        parent: usize,
+    },
+    /// `page` is referenced by `parent` but something else already uses it
+    UsedTwice {
+        page: usize,
+        parent: usize,
+    },
+    /// `page` is neither part of a btree nor of the freelist
+    NeverUsed {
+        page: usize,
+    },
+    Unreadable {
+        page: usize,
+        reason: String,
+    },
+    /// the btree rooted at `root` mixes table and index pages
+    WrongPageType {
+        page: usize,
+        root: usize,
+    },
+    /// the leaves below `page` are not all at the same depth
+    DepthMismatch {
+        page: usize,
+    },
+    /// the key of `cell` is out of order with the rest of the page or with its parent
+    KeyOutOfOrder {
+        page: usize,
+        cell: usize,
+    },
+    CellOutOfBounds {
+        page: usize,
+        cell: usize,
+    },
+    CellOverlap {
+        page: usize,
+        cell: usize,
+    },
+    Freeblock {
+        page: usize,
+        offset: usize,
+        reason: &'static str,
+    },
+    Fragments {
+        page: usize,
+        header: u8,
+        found: usize,
+    },
+    Overflow {
+        page: usize,
+        cell: usize,
+        reason: &'static str,
+    },
+    /// the record of `cell` cannot be decoded or its header disagrees with the payload size
+    Record {
+        page: usize,
+        cell: usize,
+        reason: String,
+    },
+    Freelist(FreelistError),
+    /// the number of pages in the database is not known, so pages past its end and those never
+    /// used could not be looked for
+    UnknownSize,
+    /// the pointer map entry of `page` does not say what it is used for
+    PointerMap {
+        page: usize,
+        expected: PtrmapEntry,
+        found: Option<PtrmapEntry>,
+    },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::OutOfRange { page, parent } => {
+                write!(f, "Page {parent}: child page {page} is out of range")
+            }
+            Problem::UsedTwice { page, parent } => {
+                write!(f, "Page {parent}: page {page} is referenced more than once")
+            }
+            Problem::NeverUsed { page } => write!(f, "Page {page} is never used"),
+            Problem::Unreadable { page, reason } => write!(f, "Page {page}: {reason}"),
+            Problem::WrongPageType { page, root } => {
+                write!(
+                    f,
+                    "Page {page}: wrong page type for the btree rooted at {root}"
+                )
+            }
+            Problem::DepthMismatch { page } => write!(f, "Page {page}: child page depth differs"),
+            Problem::KeyOutOfOrder { page, cell } => {
+                write!(f, "Page {page}: key of cell {cell} is out of order")
+            }
+            Problem::CellOutOfBounds { page, cell } => {
+                write!(
+                    f,
+                    "Page {page}: cell {cell} extends past the end of the page"
+                )
+            }
+            Problem::CellOverlap { page, cell } => {
+                write!(f, "Page {page}: cell {cell} overlaps another cell")
+            }
+            Problem::Freeblock {
+                page,
+                offset,
+                reason,
+            } => write!(f, "Page {page}: freeblock at {offset} {reason}"),
+            Problem::Fragments {
+                page,
+                header,
+                found,
+            } => write!(
+                f,
+                "Page {page}: fragmentation of {found} bytes reported as {header}"
+            ),
+            Problem::Overflow { page, cell, reason } => {
+                write!(f, "Page {page}: overflow chain of cell {cell} {reason}")
+            }
+            Problem::Record { page, cell, reason } => {
+                write!(f, "Page {page}: record of cell {cell} {reason}")
+            }
+            Problem::Freelist(e) => write!(f, "Freelist: {e}"),
+            Problem::UnknownSize => write!(
+                f,
+                "Database size unknown, pages out of range or never used were not looked for"
+            ),
+            Problem::PointerMap {
+                page,
+                expected,
+                found: Some(found),
+            } => write!(
+                f,
+                "Page {page}: pointer map entry says {found}, expected {expected}"
+            ),
+            Problem::PointerMap {
+                page,
+                expected,
+                found: None,
+            } => write!(
+                f,
+                "Page {page}: invalid pointer map entry, expected {expected}"
+            ),
+        }
+    }
+}
+
+/// What the keys of a btree are and how they are ordered
+pub(crate) enum TreeKind {
+    Table,
+    /// sort order (true for ascending) of the indexed columns, the rowid (and any column past
+    /// the listed ones) is always ascending
+    Index(Vec<bool>),
+}
+
+#[derive(Debug, Clone)]
+enum Key {
+    Rowid(i64),
+    Record(Vec<DbValue>),
+}
+
+/// Bytes of a page taken by something (a cell or a freeblock)
+struct Extent {
+    start: usize,
+    end: usize,
+    cell: Option<usize>,
+}
+
+/// Walks btrees and the freelist keeping track of every page it meets, collecting everything that
+/// looks wrong along the way
+pub(crate) struct Checker<'a, T> {
+    supplier: &'a mut T,
+    /// number of pages in the database, zero when unknown
+    size: usize,
+    encoding: TextEncoding,
+    used: HashSet<usize>,
+    /// what the pages met so far are used for, as the pointer map should tell
+    entries: HashMap<usize, PtrmapEntry>,
+    problems: Vec<Problem>,
+}
+
+impl<'a, T: PageSupplier> Checker<'a, T> {
+    pub(crate) fn new(supplier: &'a mut T, size: usize) -> Self {
+        Self {
+            encoding: supplier.text_encoding(),
+            supplier,
+            size,
+            used: HashSet::new(),
+            entries: HashMap::new(),
+            problems: Vec::new(),
+        }
+    }
+
+    /// Marks `page` as used, returns whether it is fine to go and look at it
+    fn visit(&mut self, page: usize, parent: usize) -> bool {
+        if page == 0 || (self.size != 0 && page > self.size) {
+            self.problems.push(Problem::OutOfRange { page, parent });
+            false
+        } else if !self.used.insert(page) {
+            self.problems.push(Problem::UsedTwice { page, parent });
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Marks `page` as used like [`Checker::visit`], recording what for
+    fn visit_as(&mut self, page: usize, parent: usize, entry: PtrmapEntry) -> bool {
+        let visited = self.visit(page, parent);
+        if visited {
+            self.entries.insert(page, entry);
+        }
+        visited
+    }
+
+    /// Marks pages that belong to the database without being part of any btree, like the pointer
+    /// map pages of auto-vacuum databases
+    pub(crate) fn reserve(&mut self, page: usize) {
+        self.visit(page, 0);
+    }
+
+    /// Compares the pointer map of an auto-vacuum database against what the pages met so far
+    /// are used for
+    pub(crate) fn check_pointer_map(&mut self, map: PointerMap) {
+        for map_page in map.map_pages(self.size) {
+            let Ok(data) = self.supplier.shared_page(map_page) else {
+                self.problems.push(Problem::Unreadable {
+                    page: map_page,
+                    reason: "could not be read".to_string(),
+                });
+                continue;
+            };
+            for page in map.covered(map_page, self.size) {
+                let Some(&expected) = self.entries.get(&page) else {
+                    continue;
+                };
+                let found = map.read(&data, page);
+                if found != Some(expected) {
+                    self.problems.push(Problem::PointerMap {
+                        page,
+                        expected,
+                        found,
+                    });
+                }
+            }
+        }
+    }
+
+    pub(crate) fn check_tree(&mut self, root: usize, kind: &TreeKind) {
+        self.check_page(root, 0, root, kind, (None, None));
+    }
+
+    pub(crate) fn check_freelist(&mut self, first_trunk: usize, expected: usize) {
+        match Freelist::read(self.supplier, first_trunk, expected, self.size) {
+            Ok(freelist) => {
+                let mut parent = 0;
+                for &trunk in &freelist.trunks {
+                    self.visit_as(trunk, parent, PtrmapEntry::Free);
+                    parent = trunk;
+                }
+                for &leaf in &freelist.leaves {
+                    self.visit_as(leaf, first_trunk, PtrmapEntry::Free);
+                }
+            }
+            Err(e) => self.problems.push(Problem::Freelist(e)),
+        }
+    }
+
+    /// Reports the pages nobody referenced and returns everything found so far
+    pub(crate) fn finish(mut self) -> Vec<Problem> {
+        if self.size == 0 {
+            self.problems.push(Problem::UnknownSize);
+        }
+        for page in 1..=self.size {
+            if !self.used.contains(&page) {
+                self.problems.push(Problem::NeverUsed { page });
+            }
+        }
+        self.problems
+    }
+
+    fn compare(&self, kind: &TreeKind, a: &Key, b: &Key) -> Ordering {
+        match (kind, a, b) {
+            (_, Key::Rowid(a), Key::Rowid(b)) => a.cmp(b),
+            (TreeKind::Index(asc), Key::Record(a), Key::Record(b)) => {
+                compare_index_keys(a, b, asc, self.encoding)
+            }
+            _ => Ordering::Equal,
+        }
+    }
+
+    /// Checks `page` and the subtree below it, whose keys must fall within `bounds` (lower bound
+    /// excluded, upper bound included for tables and excluded for indices). Returns the depth of
+    /// the subtree
+    fn check_page(
+        &mut self,
+        page: usize,
+        parent: usize,
+        root: usize,
+        kind: &TreeKind,
+        bounds: (Option<Key>, Option<Key>),
+    ) -> Option<usize> {
+        let entry = match parent {
+            0 => PtrmapEntry::Root,
+            parent => PtrmapEntry::Child(parent as u32),
+        };
+        if !self.visit_as(page, parent, entry) {
+            return None;
+        }
+        let data = match self.supplier.shared_page(page) {
+            Ok(data) => data,
+            Err(_) => {
+                self.problems.push(Problem::Unreadable {
+                    page,
+                    reason: "could not be read".to_string(),
+                });
+                return None;
+            }
+        };
+        let usable_size = self.supplier.payload_limits().usable_size;
+        let offset = if page == 1 { 100 } else { 0 };
+        let header = match BTreeHeader::new(&data[offset..]) {
+            Ok(header) => header,
+            Err(e) => {
+                self.problems.push(Problem::Unreadable {
+                    page,
+                    reason: e.to_string(),
+                });
+                return None;
+            }
+        };
+        let typ = header.page_type;
+        let is_table = matches!(typ, BTreePageType::InteriorTable | BTreePageType::LeafTable);
+        if is_table != matches!(kind, TreeKind::Table) {
+            self.problems.push(Problem::WrongPageType { page, root });
+            return None;
+        }
+        let header_size = if header.right_ptr.is_some() { 12 } else { 8 };
+        let pointers_start = offset + header_size;
+        let pointers_end = pointers_start + 2 * header.cell_count as usize;
+        let content_start = header.cell_start as usize;
+        if pointers_end > content_start.min(usable_size) {
+            self.problems.push(Problem::Unreadable {
+                page,
+                reason: "cell pointer array overlaps the cell content area".to_string(),
+            });
+            return None;
+        }
+
+        let limits = self.supplier.payload_limits();
+        let mut extents = Vec::new();
+        let mut cells = Vec::new();
+        for (i, ptr) in data[pointers_start..pointers_end]
+            .chunks_exact(2)
+            .enumerate()
+        {
+            let ptr = u16::from_be_bytes([ptr[0], ptr[1]]) as usize;
+            if ptr < content_start || ptr >= usable_size {
+                self.problems
+                    .push(Problem::CellOutOfBounds { page, cell: i });
+                continue;
+            }
+            let mut r = &data[ptr..usable_size];
+            let Ok(cell) = Cell::read(&typ, &mut r, &limits) else {
+                self.problems
+                    .push(Problem::CellOutOfBounds { page, cell: i });
+                continue;
+            };
+            // cells always take at least 4 bytes, so that they can become freeblocks
+            let size = (usable_size - ptr - r.len()).max(4);
+            if ptr + size > usable_size {
+                self.problems
+                    .push(Problem::CellOutOfBounds { page, cell: i });
+                continue;
+            }
+            extents.push(Extent {
+                start: ptr,
+                end: ptr + size,
+                cell: Some(i),
+            });
+            cells.push((i, cell));
+        }
+        self.check_freeblocks(page, &data, &header, usable_size, &mut extents);
+        self.check_space(page, &header, usable_size, extents);
+
+        let mut keys = Vec::new();
+        for (i, cell) in &mut cells {
+            let key = match typ {
+                BTreePageType::InteriorTable | BTreePageType::LeafTable => Key::Rowid(cell.rowid),
+                _ => Key::Record(Vec::new()),
+            };
+            if typ != BTreePageType::InteriorTable {
+                self.check_payload(page, *i, cell);
+            }
+            let key = match key {
+                Key::Record(_) => match Record::read_row(cell.payload.as_slice(), self.encoding) {
+                    Ok(record) => Key::Record(record.into_iter().map(DbValue::from).collect()),
+                    Err(_) => continue,
+                },
+                key => key,
+            };
+            keys.push((*i, key));
+        }
+        self.check_order(page, kind, &keys, &bounds);
+
+        if header.right_ptr.is_none() {
+            return Some(0);
+        }
+        // every cell holds the left child, the rightmost pointer in the header gets the rest
+        let mut lower = bounds.0.clone();
+        let mut depth = None;
+        let children = cells
+            .iter()
+            .map(|(i, cell)| {
+                let key = keys.iter().find(|(k, _)| k == i).map(|(_, k)| k.clone());
+                (cell.left_child as u32 as usize, key)
+            })
+            .chain([(header.right_ptr.unwrap_or(0) as usize, bounds.1.clone())])
+            .collect::<Vec<_>>();
+        for (child, upper) in children {
+            let child_depth = self.check_page(child, page, root, kind, (lower, upper.clone()));
+            match (depth, child_depth) {
+                (None, d) => depth = d,
+                (Some(a), Some(b)) if a != b => {
+                    self.problems.push(Problem::DepthMismatch { page });
+                }
+                _ => {}
+            }
+            lower = upper;
+        }
+        depth.map(|d| d + 1)
+    }
+
+    fn check_order(
+        &mut self,
+        page: usize,
+        kind: &TreeKind,
+        keys: &[(usize, Key)],
+        bounds: &(Option<Key>, Option<Key>),
+    ) {
+        let is_table = matches!(kind, TreeKind::Table);
+        let mut prev = bounds.0.as_ref();
+        for (i, key) in keys {
+            let after_prev = prev.is_none_or(|p| self.compare(kind, p, key).is_lt());
+            let before_upper = bounds
+                .1
+                .as_ref()
+                .is_none_or(|u| match self.compare(kind, key, u) {
+                    Ordering::Less => true,
+                    Ordering::Equal => is_table,
+                    Ordering::Greater => false,
+                });
+            if !after_prev || !before_upper {
+                self.problems
+                    .push(Problem::KeyOutOfOrder { page, cell: *i });
+            }
+            prev = Some(key);
+        }
+    }
+
+    /// Follows the overflow chain of `cell`, completing its payload, and checks that the record
+    /// it holds is consistent
+    fn check_payload(&mut self, page: usize, i: usize, cell: &mut Cell) {
+        let usable_size = self.supplier.payload_limits().usable_size;
+        let mut next = cell.first_overflow.take();
+        let mut parent = page;
+        while let Some(overflow) = next {
+            if cell.payload.len() >= cell.payload_size {
+                self.problems.push(Problem::Overflow {
+                    page,
+                    cell: i,
+                    reason: "is longer than the payload",
+                });
+                return;
+            }
+            let entry = match parent == page {
+                true => PtrmapEntry::FirstOverflow(page as u32),
+                false => PtrmapEntry::Overflow(parent as u32),
+            };
+            if !self.visit_as(overflow, parent, entry) {
+                return;
+            }
+            let Ok(data) = self.supplier.read_page(overflow) else {
+                self.problems.push(Problem::Unreadable {
+                    page: overflow,
+                    reason: "could not be read".to_string(),
+                });
+                return;
+            };
+            let missing = cell.payload_size - cell.payload.len();
+            let content = &data[4..usable_size];
+            cell.payload
+                .extend_from_slice(&content[..missing.min(content.len())]);
+            next = match u32::from_be_bytes([data[0], data[1], data[2], data[3]]) {
+                0 => None,
+                n => Some(n as usize),
+            };
+            parent = overflow;
+        }
+        if cell.payload.len() != cell.payload_size {
+            self.problems.push(Problem::Overflow {
+                page,
+                cell: i,
+                reason: "ends before the payload does",
+            });
+            return;
+        }
+        let reason = match Record::expected_size(&cell.payload) {
+            Ok(size) if size != cell.payload.len() => format!(
+                "describes {size} bytes but the payload holds {}",
+                cell.payload.len()
+            ),
+            Ok(_) => match Record::read_row(cell.payload.as_slice(), self.encoding) {
+                Ok(_) => return,
+                Err(e) => format!("cannot be decoded: {e}"),
+            },
+            Err(e) => format!("has an invalid header: {e}"),
+        };
+        self.problems.push(Problem::Record {
+            page,
+            cell: i,
+            reason,
+        });
+    }
+
+    fn check_freeblocks(
+        &mut self,
+        page: usize,
+        data: &[u8],
+        header: &BTreeHeader,
+        usable_size: usize,
+        extents: &mut Vec<Extent>,
+    ) {
+        let mut offset = header.freeblock_start as usize;
+        let mut found = Vec::new();
+        while offset != 0 {
+            let problem = |reason| Problem::Freeblock {
+                page,
+                offset,
+                reason,
+            };
+            if offset < header.cell_start as usize || offset + 4 > usable_size {
+                self.problems
+                    .push(problem("is outside of the cell content area"));
+                break;
+            }
+            let next = u16::from_be_bytes([data[offset], data[offset + 1]]) as usize;
+            let size = u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize;
+            if size < 4 || offset + size > usable_size {
+                self.problems.push(problem("has an invalid size"));
+                break;
+            }
+            found.push(Extent {
+                start: offset,
+                end: offset + size,
+                cell: None,
+            });
+            if next != 0 && next <= offset + size {
+                self.problems
+                    .push(problem("is followed by one that is not past its end"));
+                break;
+            }
+            offset = next;
+        }
+        extents.extend(found);
+    }
+
+    /// Looks for overlapping cells and freeblocks, and counts the fragmented bytes left between
+    /// them
+    fn check_space(
+        &mut self,
+        page: usize,
+        header: &BTreeHeader,
+        usable_size: usize,
+        mut extents: Vec<Extent>,
+    ) {
+        extents.sort_by_key(|e| e.start);
+        let mut end = header.cell_start as usize;
+        let mut fragments = 0;
+        let mut overlaps = false;
+        for extent in &extents {
+            if extent.start < end {
+                overlaps = true;
+                match extent.cell {
+                    Some(cell) => self.problems.push(Problem::CellOverlap { page, cell }),
+                    None => self.problems.push(Problem::Freeblock {
+                        page,
+                        offset: extent.start,
+                        reason: "overlaps a cell",
+                    }),
+                }
+            } else {
+                fragments += extent.start - end;
+            }
+            end = end.max(extent.end);
+        }
+        fragments += usable_size.saturating_sub(end);
+        if !overlaps && fragments != header.fragments as usize {
+            self.problems.push(Problem::Fragments {
+                page,
+                header: header.fragments,
+                found: fragments,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, fs::File};
+
+    use super::*;
+    use crate::{
+        database::{varint::Varint, DbAccess},
+        sql::syntax::DbValue,
+    };
+
+    fn check(path: &str) -> anyhow::Result<Vec<Problem>> {
+        DbAccess::new(File::open(path)?)?.integrity_check()
+    }
+
+    /// Checks a copy of `fixture` whose page `page` went through `f`
+    fn check_corrupted(
+        fixture: &str,
+        page: usize,
+        f: impl FnOnce(&mut [u8]),
+    ) -> anyhow::Result<Vec<Problem>> {
+        let mut data = fs::read(fixture)?;
+        f(&mut data[(page - 1) * 512..page * 512]);
+        let path = std::env::temp_dir().join(format!("check_test_{}.db", std::process::id()));
+        fs::write(&path, &data)?;
+        let problems = DbAccess::new(File::open(&path)?)?.integrity_check();
+        fs::remove_file(&path)?;
+        problems
+    }
+
+    fn pointers(page: &[u8]) -> Vec<usize> {
+        let header = BTreeHeader::new(page).unwrap();
+        let start = if header.right_ptr.is_some() { 12 } else { 8 };
+        (0..header.cell_count as usize)
+            .map(|i| u16::from_be_bytes([page[start + 2 * i], page[start + 2 * i + 1]]) as usize)
+            .collect()
+    }
+
+    #[test]
+    fn test_healthy() -> anyhow::Result<()> {
+        for path in [
+            "fixtures/index.db",
+            "fixtures/deleted.db",
+            "fixtures/overflow.db",
+            "fixtures/utf16le.db",
+            "fixtures/utf16be.db",
+            "fixtures/reserved.db",
+            "fixtures/stale.db",
+            "sample.db",
+        ] {
+            assert_eq!(check(path)?, [], "{path}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_size() -> anyhow::Result<()> {
+        // the header of stale.db does not vouch for its size, which is taken from the file
+        let mut data = fs::read("fixtures/stale.db")?;
+        data.resize(data.len() + 512, 0);
+        let path = std::env::temp_dir().join(format!("stale_check_{}.db", std::process::id()));
+        fs::write(&path, &data)?;
+        let problems = check(path.to_str().unwrap());
+        fs::remove_file(&path)?;
+        assert_eq!(problems?, [Problem::NeverUsed { page: 6 }]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pragma() -> anyhow::Result<()> {
+        let mut db = DbAccess::new(File::open("fixtures/index.db")?)?;
+        let rows = db.run_query("PRAGMA integrity_check").swap_remove(0).rows;
+        let rows: Vec<_> = rows.into_iter().flat_map(|r| r.row).collect();
+        assert_eq!(rows, [DbValue::Text("ok".to_owned())]);
+        assert!(db.execute("PRAGMA page_size").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted() -> anyhow::Result<()> {
+        // page 3 of deleted.db is a leaf of the table
+        let problems = check_corrupted("fixtures/deleted.db", 3, |page| {
+            let ptrs = pointers(page);
+            page[8..10].copy_from_slice(&(ptrs[1] as u16).to_be_bytes());
+            page[10..12].copy_from_slice(&(ptrs[0] as u16).to_be_bytes());
+        })?;
+        assert_eq!(problems, [Problem::KeyOutOfOrder { page: 3, cell: 1 }]);
+
+        let problems = check_corrupted("fixtures/deleted.db", 3, |page| {
+            let ptrs = pointers(page);
+            page[10..12].copy_from_slice(&(ptrs[0] as u16).to_be_bytes());
+        })?;
+        assert!(problems.contains(&Problem::CellOverlap { page: 3, cell: 1 }));
+
+        let problems = check_corrupted("fixtures/deleted.db", 3, |page| page[7] += 3)?;
+        assert!(matches!(problems[..], [Problem::Fragments { page: 3, .. }]));
+
+        // sqlite_schema itself, the b-tree header of page 1 follows the database header
+        let problems = check_corrupted("fixtures/deleted.db", 1, |page| page[100] = 7)?;
+        assert!(problems.contains(&Problem::Unreadable {
+            page: 1,
+            reason: "sqlite_schema could not be read".to_owned()
+        }));
+
+        // shorten the text of the first row by one byte in its record header
+        let problems = check_corrupted("fixtures/deleted.db", 3, |page| {
+            let mut cell = &page[pointers(page)[0]..];
+            Varint::read(&mut cell).unwrap();
+            Varint::read(&mut cell).unwrap();
+            let offset = 512 - cell.len() + 2;
+            page[offset] -= 2;
+        })?;
+        assert!(matches!(
+            problems[..],
+            [Problem::Record {
+                page: 3,
+                cell: 0,
+                ..
+            }]
+        ));
+
+        // the root of the people table in index.db is an interior page with a single cell, point
+        // its child to the right-most one
+        let problems = check_corrupted("fixtures/index.db", 2, |page| {
+            let ptr = pointers(page)[0];
+            let right = page[8..12].to_vec();
+            page[ptr..ptr + 4].copy_from_slice(&right);
+        })?;
+        assert!(problems
+            .iter()
+            .any(|p| matches!(p, Problem::UsedTwice { parent: 2, .. })));
+        assert!(problems
+            .iter()
+            .any(|p| matches!(p, Problem::NeverUsed { .. })));
+
+        // a trunk claiming more leaves than it has
+        let problems = check_corrupted("fixtures/deleted.db", 127, |page| page[7] -= 1)?;
+        assert!(
+            problems.contains(&Problem::Freelist(FreelistError::CountMismatch {
+                header: 43,
+                found: 42
+            }))
+        );
+        Ok(())
+    }
+}
@@ -35,7 +35,7 @@ impl Precompile for Expr {
                 let right = right.precompile();
                 let operation: fn(DbValue, DbValue) -> DbValue = match operator {
                     Operator::Plus => |a, b| a + b,
-                    Operator::Minus => |a, b| a + b,
+                    Operator::Minus => |a, b| a - b,
                     Operator::Equals => |l, r| DbValue::Bool(l == r),
                     Operator::Notequals => |l, r| {
                         println!("Checking {l:?} against {r:?}");
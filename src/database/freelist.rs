@@ -0,0 +1,127 @@
+use std::collections::HashSet;
+
+use thiserror::Error;
+
+use super::tree::PageSupplier;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub(crate) enum FreelistError {
+    #[error("could not read freelist page {0}")]
+    ReadError(usize),
+    #[error("freelist page {page} is out of range (database has {size} pages)")]
+    OutOfRange { page: usize, size: usize },
+    #[error("freelist page {0} is listed more than once")]
+    Duplicate(usize),
+    #[error("freelist trunk page {page} claims {count} leaves")]
+    InvalidLeafCount { page: usize, count: usize },
+    #[error("header says there are {header} free pages but the freelist holds {found}")]
+    CountMismatch { header: usize, found: usize },
+}
+
+/// Pages of the database that are not in use, see section 1.5 of the file format docs
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Freelist {
+    /// trunk pages in the order they are chained
+    pub trunks: Vec<usize>,
+    pub leaves: Vec<usize>,
+}
+
+fn be_u32(b: &[u8]) -> usize {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize
+}
+
+impl Freelist {
+    /// Walks the trunk chain starting at `first_trunk`, checking that it holds exactly `expected`
+    /// pages. `size` is the number of pages in the database, when known (zero otherwise)
+    pub(crate) fn read(
+        supplier: &mut impl PageSupplier,
+        first_trunk: usize,
+        expected: usize,
+        size: usize,
+    ) -> Result<Self, FreelistError> {
+        let usable_size = supplier.payload_limits().usable_size;
+        let mut freelist = Self::default();
+        let mut seen = HashSet::new();
+        let mut check = |page: usize| {
+            if page < 2 || (size != 0 && page > size) {
+                Err(FreelistError::OutOfRange { page, size })
+            } else if !seen.insert(page) {
+                Err(FreelistError::Duplicate(page))
+            } else {
+                Ok(())
+            }
+        };
+        let mut trunk = first_trunk;
+        while trunk != 0 {
+            check(trunk)?;
+            let page = supplier
+                .read_page(trunk)
+                .map_err(|_| FreelistError::ReadError(trunk))?;
+            let next = be_u32(page);
+            let count = be_u32(&page[4..]);
+            if count > (usable_size - 8) / 4 {
+                return Err(FreelistError::InvalidLeafCount { page: trunk, count });
+            }
+            let leaves = page[8..8 + count * 4].chunks_exact(4).map(be_u32);
+            freelist.trunks.push(trunk);
+            freelist.leaves.extend(leaves);
+            trunk = next;
+        }
+        for &leaf in &freelist.leaves {
+            check(leaf)?;
+        }
+        if freelist.page_count() != expected {
+            return Err(FreelistError::CountMismatch {
+                header: expected,
+                found: freelist.page_count(),
+            });
+        }
+        Ok(freelist)
+    }
+
+    /// Total number of free pages, trunks included
+    pub(crate) fn page_count(&self) -> usize {
+        self.trunks.len() + self.leaves.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+
+    use super::*;
+    use crate::database::DbAccess;
+
+    #[test]
+    fn test_freelist() -> anyhow::Result<()> {
+        // see tree::test for how deleted.db came to be
+        let mut db = DbAccess::new(File::open("fixtures/deleted.db")?)?;
+        let freelist = db.freelist()?;
+        assert_eq!(freelist.trunks, [127]);
+        assert_eq!(freelist.leaves.len(), 42);
+        assert_eq!(freelist.leaves[..3], [43, 44, 45]);
+        assert_eq!(freelist.page_count(), db.header.total_freelist as usize);
+
+        assert_eq!(
+            Freelist::read(&mut db, 127, 40, 127),
+            Err(FreelistError::CountMismatch {
+                header: 40,
+                found: 43
+            })
+        );
+        // 127 is the last page of the file
+        assert_eq!(
+            Freelist::read(&mut db, 127, 43, 100),
+            Err(FreelistError::OutOfRange {
+                page: 127,
+                size: 100
+            })
+        );
+        // a table page read as a trunk points to garbage
+        assert!(Freelist::read(&mut db, 2, 43, 127).is_err());
+
+        let mut db = DbAccess::new(File::open("fixtures/index.db")?)?;
+        assert_eq!(db.freelist()?, Freelist::default());
+        Ok(())
+    }
+}
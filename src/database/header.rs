@@ -1,20 +1,27 @@
 use std::io::{self, Read};
 use thiserror::Error;
 
+use super::page::PayloadLimits;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum RWMode {
     Journal,
     WAL,
+    /// written by a newer version of sqlite, the file can be read but not written
     None,
 }
-impl From<&u8> for RWMode {
-    fn from(value: &u8) -> Self {
-        match value {
+impl TryFrom<u8> for RWMode {
+    type Error = HeaderError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
             1 => Self::Journal,
             2 => Self::WAL,
-            _ => Self::None,
-        }
+            _ => return Err(HeaderError::UnsupportedVersion(value)),
+        })
     }
 }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum SchemaFormat {
     Format1,
     Format2,
@@ -22,38 +29,44 @@ pub(crate) enum SchemaFormat {
     Format4,
 }
 
-impl From<u32> for SchemaFormat {
-    fn from(value: u32) -> Self {
-        match value {
+impl TryFrom<u32> for SchemaFormat {
+    type Error = HeaderError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        Ok(match value {
             1 => Self::Format1,
             2 => Self::Format2,
             3 => Self::Format3,
             4 => Self::Format4,
-            _ => panic!("Avrei dovuto mettere un try_from"),
-        }
+            _ => return Err(HeaderError::SchemaFormatError(value)),
+        })
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum TextEncoding {
     UTF8 = 1,
     UTF16le = 2,
     UTF16be = 3,
 }
-impl From<u32> for TextEncoding {
-    fn from(value: u32) -> Self {
-        match value {
+impl TryFrom<u32> for TextEncoding {
+    type Error = HeaderError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        Ok(match value {
             1 => Self::UTF8,
             2 => Self::UTF16le,
             3 => Self::UTF16be,
-            _ => panic!("should have used tryfrom"),
-        }
+            _ => return Err(HeaderError::TextEncodingError(value)),
+        })
     }
 }
 pub(crate) struct Header {
     pub(crate) page_size: u32,
     pub(crate) write_version: RWMode,
     pub(crate) read_version: RWMode,
-    pub(crate) reserved_size: RWMode,
+    /// bytes at the end of every page used by extensions, not part of the b-tree
+    pub(crate) reserved_size: u8,
     pub(crate) max_embedded_fraction: u8,
     pub(crate) min_embedded_fraction: u8,
     pub(crate) leaf_fraction: u8,
@@ -70,45 +83,75 @@ pub(crate) struct Header {
     pub(crate) incremental_vacuum: u32,
     pub(crate) version_valid_for: u32,
     pub(crate) sqlite_version: u32,
-    application_id: u32,
+    pub(crate) application_id: u32,
 }
 
+/// Version of sqlite recorded in the databases we create, whose file format we follow
+const SQLITE_VERSION_NUMBER: u32 = 3051002;
+
 #[derive(Error, Debug)]
 pub(crate) enum HeaderError {
     #[error("file read error")]
     ReadError(#[from] io::Error),
-    #[error("invalid file")]
+    #[error("file is not a database (invalid magic header string)")]
     InvalidFile,
-    #[error("fraction inconsistent")]
-    InvalidFractionError,
-    #[error("vacuum mode inconsistent")]
+    #[error("database header is truncated ({0} of 100 bytes)")]
+    Truncated(usize),
+    #[error("invalid page size {0} (must be a power of two between 512 and 65536)")]
+    PageSizeError(u32),
+    #[error("unsupported file format version {0} (expected 1 for legacy or 2 for WAL)")]
+    UnsupportedVersion(u8),
+    #[error("invalid payload fractions {0:?} (expected [64, 32, 32])")]
+    InvalidFractionError([u8; 3]),
+    #[error("incremental vacuum is set but auto-vacuum is off")]
     VacuumModeError,
+    #[error("unsupported schema format number {0}")]
+    SchemaFormatError(u32),
+    #[error("invalid text encoding {0} (expected 1 for UTF-8, 2 for UTF-16le or 3 for UTF-16be)")]
+    TextEncodingError(u32),
+    #[error(
+        "{reserved} reserved bytes leave less than 480 usable bytes in {page_size} bytes pages"
+    )]
+    InvalidReservedSize { reserved: u8, page_size: u32 },
     #[error("invalid btree page type value {0}")]
     BTreePageTypeError(u8),
 }
 
 impl Header {
-    pub(crate) fn new(mut r: impl Read) -> Result<Self, HeaderError> {
-        let mut buf: [u8; 16] = [0; 16];
-        r.read_exact(&mut buf)?;
-        if &buf != b"SQLite format 3\0" {
+    /// Decodes the 100 bytes database header, failing on anything a sqlite database could not
+    /// have written
+    pub(crate) fn new(r: impl Read) -> Result<Self, HeaderError> {
+        let mut header = Vec::with_capacity(100);
+        r.take(100).read_to_end(&mut header)?;
+        if !header.starts_with(b"SQLite format 3\0") {
             return Err(HeaderError::InvalidFile);
         }
+        if header.len() < 100 {
+            return Err(HeaderError::Truncated(header.len()));
+        }
+        let mut r = &header[16..];
 
         let mut b = [0; 2];
         r.read_exact(&mut b)?;
         let page_size = u16::from_be_bytes(b);
-        let write_ver = &0;
-        // FIXME: I don't think this works
-        let read_ver = &0;
-        r.read_exact(&mut [*write_ver, *read_ver])?;
-        let _padding = &0;
-        r.read_exact(&mut [*_padding])?;
+        // 1 stands for 65536, which does not fit in two bytes
+        if !(page_size == 1 || (512..=32768).contains(&page_size) && page_size.is_power_of_two()) {
+            return Err(HeaderError::PageSizeError(page_size.into()));
+        }
+        let mut versions = [0; 2];
+        r.read_exact(&mut versions)?;
+        let [write_ver, read_ver] = versions;
+        // a newer write version only means we should not write to the file, a newer read
+        // version means we cannot make sense of it at all
+        let read_version = RWMode::try_from(read_ver)?;
+        let write_version = RWMode::try_from(write_ver).unwrap_or(RWMode::None);
+        let mut reserved = [0];
+        r.read_exact(&mut reserved)?;
+        let [reserved_size] = reserved;
         let mut c = [0; 3];
         r.read_exact(&mut c)?;
         if c != [64, 32, 32] {
-            let header = Err(HeaderError::InvalidFractionError);
-            return header;
+            return Err(HeaderError::InvalidFractionError(c));
         }
         //weird trick to make it unmut
         let c = c;
@@ -124,13 +167,20 @@ impl Header {
         r.read_exact(&mut b)?;
         let schema_cookie = u32::from_be_bytes(b);
         r.read_exact(&mut b)?;
-        let schema_format: SchemaFormat = u32::from_be_bytes(b).into();
+        // a database with an empty schema has not picked a format or an encoding yet
+        let schema_format = match u32::from_be_bytes(b) {
+            0 => SchemaFormat::Format4,
+            n => n.try_into()?,
+        };
         r.read_exact(&mut b)?;
         let suggested_cache = u32::from_be_bytes(b);
         r.read_exact(&mut b)?;
         let largest_root_btree = u32::from_be_bytes(b);
         r.read_exact(&mut b)?;
-        let text_encoding: TextEncoding = u32::from_be_bytes(b).into();
+        let text_encoding = match u32::from_be_bytes(b) {
+            0 => TextEncoding::UTF8,
+            n => n.try_into()?,
+        };
         r.read_exact(&mut b)?;
         let user_version = u32::from_be_bytes(b);
         r.read_exact(&mut b)?;
@@ -146,16 +196,23 @@ impl Header {
         r.read_exact(&mut b)?;
         let sqlite_version = u32::from_be_bytes(b);
 
+        //weird magic number stuff
+        let page_size = if sqlite_version >= 3007001 && page_size == 1 {
+            65536
+        } else {
+            page_size as u32
+        };
+        if page_size.saturating_sub(reserved_size as u32) < 480 {
+            return Err(HeaderError::InvalidReservedSize {
+                reserved: reserved_size,
+                page_size,
+            });
+        }
         Ok(Header {
-            //weird magic number stuff
-            page_size: if sqlite_version >= 3007001 && page_size == 1 {
-                65536
-            } else {
-                page_size as u32
-            },
-            write_version: write_ver.into(),
-            read_version: read_ver.into(),
-            reserved_size: RWMode::None,
+            page_size,
+            write_version,
+            read_version,
+            reserved_size,
             max_embedded_fraction: c[0],
             min_embedded_fraction: c[1],
             leaf_fraction: c[2],
@@ -179,8 +236,101 @@ impl Header {
             sqlite_version,
         })
     }
+
+    /// Header of a new database with an empty schema, which takes nothing but the first page
+    pub(crate) fn empty(page_size: u32, text_encoding: TextEncoding) -> Result<Self, HeaderError> {
+        if !((512..=65536).contains(&page_size) && page_size.is_power_of_two()) {
+            return Err(HeaderError::PageSizeError(page_size));
+        }
+        Ok(Header {
+            page_size,
+            write_version: RWMode::Journal,
+            read_version: RWMode::Journal,
+            reserved_size: 0,
+            max_embedded_fraction: 64,
+            min_embedded_fraction: 32,
+            leaf_fraction: 32,
+            file_change_counter: 1,
+            size_in_pages: 1,
+            first_freelist_page: 0,
+            total_freelist: 0,
+            schema_cookie: 0,
+            schema_format_number: SchemaFormat::Format4,
+            cache_size: 0,
+            largest_root_btree_page: 0,
+            text_encoding,
+            user_version: 0,
+            incremental_vacuum: 0,
+            application_id: 0,
+            // the size in the header is only trusted when this matches the change counter
+            version_valid_for: 1,
+            sqlite_version: SQLITE_VERSION_NUMBER,
+        })
+    }
+
+    /// Encodes the header into the first 100 bytes of `page`, the inverse of [`Header::new`]
+    pub(crate) fn write(&self, page: &mut [u8]) {
+        let rw_mode = |mode: RWMode| match mode {
+            RWMode::Journal => 1,
+            RWMode::WAL => 2,
+            RWMode::None => unreachable!("we never write files meant for a newer sqlite"),
+        };
+        let schema_format = match self.schema_format_number {
+            SchemaFormat::Format1 => 1,
+            SchemaFormat::Format2 => 2,
+            SchemaFormat::Format3 => 3,
+            SchemaFormat::Format4 => 4,
+        };
+        let header = &mut page[..100];
+        header.fill(0);
+        header[..16].copy_from_slice(b"SQLite format 3\0");
+        // 65536 does not fit, it is stored as 1
+        header[16..18].copy_from_slice(
+            &(self.page_size as u16 | (self.page_size >> 16) as u16).to_be_bytes(),
+        );
+        header[18] = rw_mode(self.write_version);
+        header[19] = rw_mode(self.read_version);
+        header[20] = self.reserved_size;
+        header[21] = self.max_embedded_fraction;
+        header[22] = self.min_embedded_fraction;
+        header[23] = self.leaf_fraction;
+        let fields = [
+            (24, self.file_change_counter),
+            (28, self.size_in_pages),
+            (32, self.first_freelist_page),
+            (36, self.total_freelist),
+            (40, self.schema_cookie),
+            (44, schema_format),
+            (48, self.cache_size),
+            (52, self.largest_root_btree_page),
+            (56, self.text_encoding as u32),
+            (60, self.user_version),
+            (64, self.incremental_vacuum),
+            (68, self.application_id),
+            (92, self.version_valid_for),
+            (96, self.sqlite_version),
+        ];
+        for (offset, value) in fields {
+            header[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    /// Bytes of each page available to the b-tree, the page size minus the reserved space
+    pub(crate) fn usable_size(&self) -> usize {
+        self.page_size as usize - self.reserved_size as usize
+    }
+
+    /// Local/overflow payload thresholds for this database
+    pub(crate) fn payload_limits(&self) -> PayloadLimits {
+        PayloadLimits::new(
+            self.usable_size(),
+            self.max_embedded_fraction,
+            self.min_embedded_fraction,
+            self.leaf_fraction,
+        )
+    }
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum BTreePageType {
     InteriorIndex,
     InteriorTable,
@@ -215,6 +365,24 @@ pub(crate) struct BTreeHeader {
     pub(crate) right_ptr: Option<u32>,
 }
 impl BTreeHeader {
+    /// Writes the header at the start of `page`, the inverse of [`BTreeHeader::new`]
+    pub(crate) fn write(&self, page: &mut [u8]) {
+        page[0] = match self.page_type {
+            BTreePageType::InteriorIndex => 2,
+            BTreePageType::InteriorTable => 5,
+            BTreePageType::LeafIndex => 10,
+            BTreePageType::LeafTable => 13,
+        };
+        page[1..3].copy_from_slice(&self.freeblock_start.to_be_bytes());
+        page[3..5].copy_from_slice(&self.cell_count.to_be_bytes());
+        // 65536 wraps around to 0 as it should
+        page[5..7].copy_from_slice(&(self.cell_start as u16).to_be_bytes());
+        page[7] = self.fragments;
+        if let Some(right_ptr) = self.right_ptr {
+            page[8..12].copy_from_slice(&right_ptr.to_be_bytes());
+        }
+    }
+
     pub(crate) fn new(mut r: impl Read) -> anyhow::Result<Self> {
         let mut single_byte = [0];
         let mut two_bytes = [0; 2];
@@ -241,7 +409,6 @@ impl BTreeHeader {
             }
             BTreePageType::LeafIndex | BTreePageType::LeafTable => None,
         };
-        r.read_exact(&mut four_bytes)?;
         Ok(Self {
             page_type,
             freeblock_start,
@@ -252,3 +419,58 @@ impl BTreeHeader {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    fn header_with(f: impl FnOnce(&mut Vec<u8>)) -> Result<Header, HeaderError> {
+        let mut header = fs::read("fixtures/index.db").unwrap()[..100].to_vec();
+        f(&mut header);
+        Header::new(header.as_slice())
+    }
+
+    #[test]
+    fn test_header_errors() {
+        let header = header_with(|_| {}).unwrap();
+        assert_eq!(header.page_size, 512);
+        assert_eq!(header.read_version, RWMode::Journal);
+        assert_eq!(header.text_encoding, TextEncoding::UTF8);
+
+        assert!(matches!(
+            header_with(|h| h[0] = b's'),
+            Err(HeaderError::InvalidFile)
+        ));
+        assert!(matches!(
+            header_with(|h| h.truncate(60)),
+            Err(HeaderError::Truncated(60))
+        ));
+        assert!(matches!(
+            header_with(|h| h[16..18].copy_from_slice(&1000u16.to_be_bytes())),
+            Err(HeaderError::PageSizeError(1000))
+        ));
+        assert!(matches!(
+            header_with(|h| h[19] = 3),
+            Err(HeaderError::UnsupportedVersion(3))
+        ));
+        // a newer write version is fine as long as we only read
+        assert_eq!(
+            header_with(|h| h[18] = 3).map(|h| h.write_version).ok(),
+            Some(RWMode::None)
+        );
+        assert!(matches!(
+            header_with(|h| h[22] = 63),
+            Err(HeaderError::InvalidFractionError([64, 63, 32]))
+        ));
+        assert!(matches!(
+            header_with(|h| h[47] = 5),
+            Err(HeaderError::SchemaFormatError(5))
+        ));
+        assert!(matches!(
+            header_with(|h| h[59] = 4),
+            Err(HeaderError::TextEncodingError(4))
+        ));
+    }
+}
@@ -0,0 +1,785 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Write,
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+
+use super::{
+    cache::CacheStats,
+    page::PayloadLimits,
+    tree::{PageConsumer, PageSupplier, Pager, SharedPage},
+};
+
+const JOURNAL_MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+const JOURNAL_HEADER_SIZE: usize = 28;
+/// Room taken by the header of the journals we write, records start right after it
+const SECTOR_SIZE: usize = 512;
+
+fn be_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+/// Checksum of a journal record: the nonce plus every 200th byte of the page, from the end
+fn checksum(nonce: u32, content: &[u8]) -> u32 {
+    (1..)
+        .map(|i| content.len() as isize - 200 * i)
+        .take_while(|&i| i > 0)
+        .fold(nonce, |sum, i| sum.wrapping_add(content[i as usize] as u32))
+}
+
+/// Pre-transaction images of the pages touched by an interrupted transaction, as found in a hot
+/// rollback journal (section 4.3 of the file format docs)
+pub(crate) struct RollbackJournal {
+    journal: File,
+    /// page number => offset of the original content of the page in the journal
+    pages: HashMap<usize, u64>,
+    /// size of the database in pages before the transaction started
+    db_size: usize,
+    page_size: usize,
+}
+
+impl RollbackJournal {
+    /// Parses `journal`, returns None when it is not hot (empty or with a zeroed header, which
+    /// is how a committed transaction leaves it) and there is nothing to roll back
+    pub(crate) fn read(journal: File, page_size: usize) -> Result<Option<Self>> {
+        let journal_size = journal.metadata()?.len();
+        let record_size = (4 + page_size + 4) as u64;
+        let mut pages = HashMap::new();
+        let mut db_size = None;
+        let mut header = [0; JOURNAL_HEADER_SIZE];
+        let mut record = vec![0; record_size as usize];
+        let mut offset = 0;
+        // a transaction can write several headers, each followed by its own page records
+        'segments: while offset + JOURNAL_HEADER_SIZE as u64 <= journal_size {
+            journal.read_exact_at(&mut header, offset)?;
+            if header[..8] != JOURNAL_MAGIC {
+                break;
+            }
+            let records = be_u32(&header[8..]);
+            let nonce = be_u32(&header[12..]);
+            db_size.get_or_insert(be_u32(&header[16..]) as usize);
+            let sector_size = be_u32(&header[20..]) as u64;
+            let journal_page_size = be_u32(&header[24..]) as usize;
+            if journal_page_size != 0 && journal_page_size != page_size {
+                bail!("journal page size {journal_page_size} does not match the database one");
+            }
+            if sector_size < JOURNAL_HEADER_SIZE as u64 {
+                bail!("invalid journal sector size {sector_size}");
+            }
+            offset += sector_size;
+            let records = match records {
+                // the count was never written, every complete record counts
+                u32::MAX => (journal_size - offset) / record_size,
+                n => n as u64,
+            };
+            for _ in 0..records {
+                if offset + record_size > journal_size {
+                    break 'segments;
+                }
+                journal.read_exact_at(&mut record, offset)?;
+                let page_number = be_u32(&record) as usize;
+                let content = &record[4..4 + page_size];
+                if checksum(nonce, content) != be_u32(&record[4 + page_size..]) {
+                    // playback stops at the first record that did not make it to disk in full
+                    break 'segments;
+                }
+                // only the first image of a page is the one from before the transaction
+                pages.entry(page_number).or_insert(offset + 4);
+                offset += record_size;
+            }
+            offset = offset.div_ceil(sector_size) * sector_size;
+        }
+        Ok(db_size.map(|db_size| Self {
+            journal,
+            pages,
+            db_size,
+            page_size,
+        }))
+    }
+
+    /// Number of pages the journal holds an original image of
+    pub(crate) fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Plays the journal back onto `dbfile`, leaving it as it was before the transaction
+    pub(crate) fn restore(&self, dbfile: &File) -> Result<()> {
+        let mut page = vec![0; self.page_size];
+        for (&page_number, &offset) in &self.pages {
+            if page_number > self.db_size {
+                continue;
+            }
+            self.journal.read_exact_at(&mut page, offset)?;
+            dbfile.write_all_at(&page, ((page_number - 1) * self.page_size) as u64)?;
+        }
+        dbfile.set_len((self.db_size * self.page_size) as u64)?;
+        dbfile.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Serves the pre-transaction version of the pages found in a hot journal, without touching the
+/// database file
+pub(crate) struct JournalReader<S> {
+    inner: S,
+    journal: RollbackJournal,
+    page: Vec<u8>,
+    /// whether `page` holds the current page, or it has to be asked to `inner`
+    from_journal: bool,
+}
+
+impl<S: PageSupplier<Error = anyhow::Error>> JournalReader<S> {
+    pub(crate) fn new(inner: S, journal: RollbackJournal) -> Self {
+        Self {
+            inner,
+            page: vec![0; journal.page_size],
+            journal,
+            from_journal: false,
+        }
+    }
+}
+
+impl<S: PageSupplier<Error = anyhow::Error>> PageSupplier for JournalReader<S> {
+    type Error = anyhow::Error;
+
+    fn move_to_page(&mut self, page_idx: usize) -> Result<()> {
+        if page_idx > self.journal.db_size {
+            bail!("page {page_idx} did not exist before the interrupted transaction");
+        }
+        match self.journal.pages.get(&page_idx) {
+            Some(&offset) => {
+                self.journal.journal.read_exact_at(&mut self.page, offset)?;
+                self.from_journal = true;
+            }
+            None => {
+                self.inner.move_to_page(page_idx)?;
+                self.from_journal = false;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_page(&mut self, page_idx: usize) -> Result<&[u8]> {
+        self.move_to_page(page_idx)?;
+        let offset = if page_idx == 1 { 100 } else { 0 };
+        Ok(&self.page()[offset..])
+    }
+
+    fn page(&self) -> &[u8] {
+        if self.from_journal {
+            &self.page
+        } else {
+            self.inner.page()
+        }
+    }
+
+    fn payload_limits(&self) -> PayloadLimits {
+        self.inner.payload_limits()
+    }
+
+    fn shared_page(&mut self, page_idx: usize) -> Result<SharedPage> {
+        if self.journal.pages.contains_key(&page_idx) {
+            self.move_to_page(page_idx)?;
+            Ok(SharedPage::from(self.page.as_slice()))
+        } else {
+            self.inner.shared_page(page_idx)
+        }
+    }
+
+    fn db_size(&self) -> Option<usize> {
+        Some(self.journal.db_size)
+    }
+}
+
+impl<S> PageConsumer for JournalReader<S> {
+    type Error = anyhow::Error;
+
+    fn write_page(&mut self, _page_idx: usize, _page: &[u8]) -> Result<()> {
+        bail!("the database has a hot journal, restore it before writing")
+    }
+}
+
+/// Keeps the pages written during a transaction in memory, where reads find them before the
+/// ones in `inner`. On commit the original content of those pages goes to a rollback journal,
+/// which reaches the disk before the database is touched and is deleted once the database has
+/// reached it too: whenever the process dies, it leaves either the database as it was before the
+/// transaction or a hot journal that brings it back there.
+/// The next open rolls such a transaction back by reading through the journal with a
+/// [`JournalReader`], the file itself is only restored when [`OpenOptions::restore_journal`]
+/// asks for it, and until then every write is refused
+///
+/// [`OpenOptions::restore_journal`]: super::OpenOptions::restore_journal
+pub(crate) struct JournalWriter<S> {
+    inner: S,
+    /// where the journal goes, None when the database was not opened from a path
+    path: Option<PathBuf>,
+    page_size: usize,
+    /// size of the database in pages when the transaction started, None outside of them
+    db_size: Option<usize>,
+    /// size the database is cut down to on commit, the pages past it are dropped
+    truncate_to: Option<usize>,
+    dirty: HashMap<usize, SharedPage>,
+    /// content the pages written by the current statement had before it, None for the ones that
+    /// were not dirty yet
+    statement: Option<HashMap<usize, Option<SharedPage>>>,
+    /// page last moved to, which `page` returns from `dirty` when it is there
+    current: Option<usize>,
+    /// writes and syncs left before commit fails as if the process had died on the next one
+    #[cfg(test)]
+    pub(crate) crash_after: Option<usize>,
+    /// whether the process really dies there instead
+    #[cfg(test)]
+    pub(crate) abort_on_crash: bool,
+}
+
+impl<S: Pager> JournalWriter<S> {
+    pub(crate) fn new(inner: S, path: Option<PathBuf>, page_size: usize) -> Self {
+        Self {
+            inner,
+            path,
+            page_size,
+            db_size: None,
+            truncate_to: None,
+            dirty: HashMap::new(),
+            statement: None,
+            current: None,
+            #[cfg(test)]
+            crash_after: None,
+            #[cfg(test)]
+            abort_on_crash: false,
+        }
+    }
+
+    pub(crate) fn in_transaction(&self) -> bool {
+        self.db_size.is_some()
+    }
+
+    /// Starts a transaction on a database of `db_size` pages
+    pub(crate) fn begin(&mut self, db_size: usize) -> Result<()> {
+        if self.in_transaction() {
+            bail!("cannot start a transaction within a transaction");
+        }
+        let Some(path) = &self.path else {
+            bail!("the database was not opened from a path, there is nowhere to put its journal");
+        };
+        if let Ok(journal) = File::open(path) {
+            if RollbackJournal::read(journal, self.page_size)?.is_some() {
+                bail!("the database has a hot journal, open it again to restore it before writing");
+            }
+            // left by a transaction that died before writing anything that matters
+            fs::remove_file(path)?;
+        }
+        self.db_size = Some(db_size);
+        Ok(())
+    }
+
+    /// Forgets every page written since the transaction started
+    pub(crate) fn rollback(&mut self) -> Result<()> {
+        if !self.in_transaction() {
+            bail!("cannot rollback - no transaction is active");
+        }
+        self.forget_current()?;
+        self.db_size = None;
+        self.truncate_to = None;
+        self.dirty.clear();
+        self.statement = None;
+        Ok(())
+    }
+
+    /// Starts keeping track of the pages the next statement writes, to be able to undo it alone
+    pub(crate) fn begin_statement(&mut self) {
+        self.statement = Some(HashMap::new());
+    }
+
+    pub(crate) fn end_statement(&mut self) {
+        self.statement = None;
+    }
+
+    /// Puts the pages written since [`JournalWriter::begin_statement`] back as they were
+    pub(crate) fn undo_statement(&mut self) -> Result<()> {
+        self.forget_current()?;
+        for (idx, page) in self.statement.take().unwrap_or_default() {
+            match page {
+                Some(page) => self.dirty.insert(idx, page),
+                None => self.dirty.remove(&idx),
+            };
+        }
+        Ok(())
+    }
+
+    /// Writes the transaction to the database: the journal first, then the pages themselves,
+    /// syncing both before the journal is deleted. The transaction is over even when it fails,
+    /// which leaves a hot journal behind as soon as the database has been touched
+    pub(crate) fn commit(&mut self) -> Result<()> {
+        let Some(db_size) = self.db_size else {
+            bail!("cannot commit - no transaction is active");
+        };
+        let result = self.write_transaction(db_size);
+        self.forget_current()?;
+        self.db_size = None;
+        self.truncate_to = None;
+        self.dirty.clear();
+        self.statement = None;
+        result
+    }
+
+    fn write_transaction(&mut self, db_size: usize) -> Result<()> {
+        let path = self.path.clone().context("no journal path")?;
+        let size = self.truncate_to;
+        let mut pages: Vec<_> = self
+            .dirty
+            .keys()
+            .copied()
+            .filter(|&i| size.is_none_or(|size| i <= size))
+            .collect();
+        pages.sort_unstable();
+        if !pages.is_empty() || size.is_some_and(|size| size < db_size) {
+            // pages past the end of the database are gone once the file gets its size back,
+            // while the ones truncated away have to be saved too
+            let originals: Vec<_> = pages
+                .iter()
+                .copied()
+                .filter(|&i| i <= db_size)
+                .chain(size.into_iter().flat_map(|size| size + 1..=db_size))
+                .collect();
+            self.write_journal(&path, db_size, &originals)?;
+            for idx in pages {
+                self.crash_point()?;
+                self.inner.write_page(idx, &self.dirty[&idx])?;
+            }
+            if let Some(size) = size {
+                self.crash_point()?;
+                self.inner.truncate(size)?;
+            }
+            self.crash_point()?;
+            self.inner.sync()?;
+            self.crash_point()?;
+            fs::remove_file(&path)?;
+            sync_dir(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Saves the current content of `pages` in a journal at `path`, and waits for it to be on
+    /// disk
+    fn write_journal(&mut self, path: &Path, db_size: usize, pages: &[usize]) -> Result<()> {
+        let mut journal = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let nonce = SystemTime::now().duration_since(UNIX_EPOCH)?.subsec_nanos();
+        let page_size = self.page_size;
+        let mut header = vec![0; SECTOR_SIZE];
+        header[..8].copy_from_slice(&JOURNAL_MAGIC);
+        // the record count stays at 0 until the records are on disk, see below
+        for (i, value) in [0, nonce as usize, db_size, SECTOR_SIZE, page_size]
+            .into_iter()
+            .enumerate()
+        {
+            header[8 + 4 * i..12 + 4 * i].copy_from_slice(&(value as u32).to_be_bytes());
+        }
+        self.crash_point()?;
+        journal.write_all(&header)?;
+        for &idx in pages {
+            self.inner.move_to_page(idx)?;
+            let content = self.inner.page();
+            let mut record = Vec::with_capacity(page_size + 8);
+            record.extend_from_slice(&(idx as u32).to_be_bytes());
+            record.extend_from_slice(content);
+            record.extend_from_slice(&checksum(nonce, content).to_be_bytes());
+            self.crash_point()?;
+            journal.write_all(&record)?;
+        }
+        self.crash_point()?;
+        journal.sync_all()?;
+        // a record count that reaches the disk before the records would have garbage played back
+        // over the database, so it is written only now and synced on its own
+        self.crash_point()?;
+        journal.write_all_at(&(pages.len() as u32).to_be_bytes(), 8)?;
+        self.crash_point()?;
+        journal.sync_all()?;
+        // so is the entry of the journal in its directory
+        sync_dir(path)
+    }
+
+    /// Moves `inner` to the current page when it is about to stop being a dirty one
+    fn forget_current(&mut self) -> Result<()> {
+        match self.current {
+            Some(idx) if self.dirty.contains_key(&idx) => self.inner.move_to_page(idx),
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(test)]
+    fn crash_point(&mut self) -> Result<()> {
+        match &mut self.crash_after {
+            Some(0) if self.abort_on_crash => std::process::abort(),
+            Some(0) => bail!("simulated crash"),
+            Some(n) => *n -= 1,
+            None => {}
+        }
+        Ok(())
+    }
+
+    #[cfg(not(test))]
+    fn crash_point(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Syncs the directory holding `path`, which makes creating or deleting it durable
+fn sync_dir(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+impl<S: Pager> PageSupplier for JournalWriter<S> {
+    type Error = anyhow::Error;
+
+    fn move_to_page(&mut self, page_idx: usize) -> Result<()> {
+        if !self.dirty.contains_key(&page_idx) {
+            self.inner.move_to_page(page_idx)?;
+        }
+        self.current = Some(page_idx);
+        Ok(())
+    }
+
+    fn read_page(&mut self, page_idx: usize) -> Result<&[u8]> {
+        self.move_to_page(page_idx)?;
+        let offset = if page_idx == 1 { 100 } else { 0 };
+        Ok(&self.page()[offset..])
+    }
+
+    fn page(&self) -> &[u8] {
+        match self.current.and_then(|idx| self.dirty.get(&idx)) {
+            Some(page) => page,
+            None => self.inner.page(),
+        }
+    }
+
+    fn payload_limits(&self) -> PayloadLimits {
+        self.inner.payload_limits()
+    }
+
+    fn shared_page(&mut self, page_idx: usize) -> Result<SharedPage> {
+        match self.dirty.get(&page_idx) {
+            Some(page) => Ok(page.clone()),
+            None => self.inner.shared_page(page_idx),
+        }
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache_stats()
+    }
+
+    /// Size of the database as it is on disk, pages written by the current transaction aside
+    fn db_size(&self) -> Option<usize> {
+        self.inner.db_size()
+    }
+}
+
+impl<S: Pager> PageConsumer for JournalWriter<S> {
+    type Error = anyhow::Error;
+
+    fn write_page(&mut self, page_idx: usize, page: &[u8]) -> Result<()> {
+        if !self.in_transaction() {
+            bail!("pages can only be written within a transaction");
+        }
+        let old = self.dirty.insert(page_idx, SharedPage::from(page));
+        if let Some(statement) = &mut self.statement {
+            statement.entry(page_idx).or_insert(old);
+        }
+        Ok(())
+    }
+
+    /// Only takes effect on commit, and is not undone along with the statement that asked for it
+    fn truncate(&mut self, pages: usize) -> Result<()> {
+        if !self.in_transaction() {
+            bail!("the database can only be truncated within a transaction");
+        }
+        self.truncate_to = Some(pages);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        database::{DbAccess, OpenOptions},
+        sql::syntax::DbValue,
+    };
+
+    // journal.db was copied in the middle of a transaction that changed every row of
+    // t(id INTEGER PRIMARY KEY, v TEXT) from 'before id' to 'after id' and added ids 301..=600,
+    // with a cache small enough to have some of those changes already spilled to the file
+    fn values(db: &mut DbAccess) -> Vec<DbValue> {
+        db.run_query("SELECT v FROM t")
+            .swap_remove(0)
+            .rows
+            .into_iter()
+            .flat_map(|r| r.row)
+            .collect()
+    }
+
+    fn before() -> Vec<DbValue> {
+        (1..=300)
+            .map(|i| DbValue::Text(format!("before {i}")))
+            .collect()
+    }
+
+    #[test]
+    fn test_hot_journal() -> Result<()> {
+        let journal = RollbackJournal::read(File::open("fixtures/journal.db-journal")?, 512)?;
+        assert_eq!(journal.as_ref().map(|j| j.page_count()), Some(11));
+
+        let mut db = DbAccess::open_path("fixtures/journal.db", &OpenOptions::default())?;
+        assert_eq!(values(&mut db), before());
+        assert_eq!(
+            db.notices(),
+            ["hot journal found, reading 11 pages as they were before the last transaction"]
+        );
+        // the file alone is a mix of the two versions
+        let mut db = DbAccess::new(File::open("fixtures/journal.db")?)?;
+        assert_ne!(values(&mut db), before());
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_journal() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("journal_test_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("journal.db");
+        fs::copy("fixtures/journal.db", &path)?;
+        fs::copy(
+            "fixtures/journal.db-journal",
+            dir.join("journal.db-journal"),
+        )?;
+        let options = OpenOptions {
+            restore_journal: true,
+            ..Default::default()
+        };
+        let mut db = DbAccess::open_path(&path, &options)?;
+        assert_eq!(values(&mut db), before());
+        assert_eq!(db.notices(), ["restored 11 pages from the hot journal"]);
+        assert!(!dir.join("journal.db-journal").exists());
+        assert_eq!(fs::metadata(&path)?.len(), 13 * 512);
+        let mut db = DbAccess::new(File::open(&path)?)?;
+        assert_eq!(values(&mut db), before());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    /// Changes every kind of page of reserved.db in a single transaction
+    fn crash_transaction() -> String {
+        let values: Vec<_> = (0..40)
+            .map(|i| format!("('{i} {}')", "z".repeat(i * 7)))
+            .collect();
+        format!(
+            "BEGIN; INSERT INTO t (body) VALUES {}; UPDATE t SET body = 'moved' WHERE id < 10;
+            DELETE FROM t WHERE id > 20 AND id < 40; COMMIT",
+            values.join(", ")
+        )
+    }
+
+    #[test]
+    fn test_crash_during_commit() -> Result<()> {
+        // see tree::test for reserved.db: t(id INTEGER PRIMARY KEY, body TEXT) indexed on body
+        let dir = std::env::temp_dir().join(format!("crash_test_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("crash.db");
+        let journal = dir.join("crash.db-journal");
+        let rows = |db: &mut DbAccess| -> Result<Vec<Vec<DbValue>>> {
+            let table = db.execute("SELECT id, body FROM t")?.swap_remove(0);
+            Ok(table.rows.into_iter().map(|r| r.row).collect())
+        };
+        let transaction = crash_transaction();
+        fs::copy("fixtures/reserved.db", &path)?;
+        let before = rows(&mut DbAccess::open_path(&path, &OpenOptions::default())?)?;
+
+        // every write and sync of the commit in turn is where the process dies
+        let mut crashes = 0;
+        let after = loop {
+            fs::copy("fixtures/reserved.db", &path)?;
+            let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+            db.pager.crash_after = Some(crashes);
+            if db.execute(&transaction).is_ok() {
+                break rows(&mut db)?;
+            }
+            drop(db);
+
+            // until the journal is gone, the transaction never happened
+            let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+            assert_eq!(rows(&mut db)?, before, "crash {crashes}");
+            let options = OpenOptions {
+                restore_journal: true,
+                ..Default::default()
+            };
+            let mut db = DbAccess::open_path(&path, &options)?;
+            assert_eq!(db.integrity_check()?, [], "crash {crashes}");
+            assert_eq!(rows(&mut db)?, before, "crash {crashes}");
+            // and whatever the crash left behind does not get in the way
+            db.execute("INSERT INTO t (body) VALUES ('next')")?;
+            assert!(!journal.exists());
+            crashes += 1;
+        };
+        // header, records, sync, record count, sync, the pages, sync and the deletion of the
+        // journal
+        assert!(crashes > 10);
+        assert!(!journal.exists());
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(db.integrity_check()?, []);
+        assert_eq!(rows(&mut db)?, after);
+        assert_ne!(after, before);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    /// Runs [`crash_transaction`] on the database at `$CRASH_TEST_DB`, with the process aborting
+    /// at commit step `$CRASH_TEST_AFTER`. Only meant to be run by [`test_killed_during_commit`]
+    #[test]
+    #[ignore]
+    fn crash_child() -> Result<()> {
+        let (Some(path), Some(after)) = (
+            std::env::var_os("CRASH_TEST_DB"),
+            std::env::var("CRASH_TEST_AFTER").ok(),
+        ) else {
+            return Ok(());
+        };
+        let mut db = DbAccess::open_path(path, &OpenOptions::default())?;
+        db.pager.crash_after = Some(after.parse()?);
+        db.pager.abort_on_crash = true;
+        db.execute(&crash_transaction())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_killed_during_commit() -> Result<()> {
+        // like test_crash_during_commit, but the process writing the transaction really dies
+        let dir = std::env::temp_dir().join(format!("killed_test_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("killed.db");
+        let rows = |db: &mut DbAccess| -> Result<Vec<Vec<DbValue>>> {
+            let table = db.execute("SELECT id, body FROM t")?.swap_remove(0);
+            Ok(table.rows.into_iter().map(|r| r.row).collect())
+        };
+        let before = rows(&mut DbAccess::new(File::open("fixtures/reserved.db")?)?)?;
+        let options = OpenOptions {
+            restore_journal: true,
+            ..Default::default()
+        };
+        for crashes in 0.. {
+            fs::copy("fixtures/reserved.db", &path)?;
+            let status = std::process::Command::new(std::env::current_exe()?)
+                .args([
+                    "database::journal::test::crash_child",
+                    "--exact",
+                    "--ignored",
+                ])
+                .env("CRASH_TEST_DB", &path)
+                .env("CRASH_TEST_AFTER", crashes.to_string())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .status()?;
+            if status.success() {
+                let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+                assert_eq!(db.integrity_check()?, []);
+                assert_ne!(rows(&mut db)?, before);
+                assert!(crashes > 10);
+                break;
+            }
+            // reading through the hot journal, then playing it back
+            let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+            assert_eq!(rows(&mut db)?, before, "crash {crashes}");
+            let mut db = DbAccess::open_path(&path, &options)?;
+            assert_eq!(db.integrity_check()?, [], "crash {crashes}");
+            assert_eq!(rows(&mut db)?, before, "crash {crashes}");
+        }
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_crash_during_vacuum() -> Result<()> {
+        // deleted.db is t(id INTEGER PRIMARY KEY, val TEXT) with plenty of free pages, which
+        // VACUUM cuts off the end of the file: a crash has to bring them back too
+        let dir = std::env::temp_dir().join(format!("vacuum_crash_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("crash.db");
+        let rows = |db: &mut DbAccess| -> Result<Vec<Vec<DbValue>>> {
+            let table = db.execute("SELECT id, val FROM t")?.swap_remove(0);
+            Ok(table.rows.into_iter().map(|r| r.row).collect())
+        };
+        let size = fs::metadata("fixtures/deleted.db")?.len();
+        let before = rows(&mut DbAccess::new(File::open("fixtures/deleted.db")?)?)?;
+        let options = OpenOptions {
+            restore_journal: true,
+            ..Default::default()
+        };
+        for crashes in 0.. {
+            fs::copy("fixtures/deleted.db", &path)?;
+            let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+            db.pager.crash_after = Some(crashes);
+            if db.execute("VACUUM").is_ok() {
+                assert!(fs::metadata(&path)?.len() < size);
+                break;
+            }
+            drop(db);
+            let mut db = DbAccess::open_path(&path, &options)?;
+            assert_eq!(fs::metadata(&path)?.len(), size, "crash {crashes}");
+            assert_eq!(db.integrity_check()?, [], "crash {crashes}");
+            assert_eq!(db.header.total_freelist, 43, "crash {crashes}");
+            assert_eq!(rows(&mut db)?, before, "crash {crashes}");
+        }
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(db.integrity_check()?, []);
+        assert_eq!(rows(&mut db)?, before);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("rollback_test_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("rollback.db");
+        fs::copy("fixtures/reserved.db", &path)?;
+        let original = fs::read(&path)?;
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        let count = |db: &mut DbAccess| db.execute("SELECT id FROM t").map(|t| t[0].rows.len());
+        let rows = count(&mut db)?;
+        db.execute("BEGIN; INSERT INTO t (body) VALUES ('a'), ('b'); DELETE FROM t WHERE id < 5")?;
+        assert_eq!(count(&mut db)?, rows - 4 + 2);
+        // nothing reaches the file before COMMIT
+        assert_eq!(fs::read(&path)?, original);
+        // a failed statement is undone alone, the transaction goes on
+        assert!(db
+            .execute("INSERT INTO t VALUES (10, 'c'), (10, 'd')")
+            .is_err());
+        assert!(db.execute("UPDATE t SET id = 3 WHERE id = 6").is_ok());
+        db.execute("ROLLBACK")?;
+        assert_eq!(count(&mut db)?, rows);
+        assert_eq!(fs::read(&path)?, original);
+        assert!(db.execute("COMMIT").is_err());
+
+        db.execute("BEGIN; INSERT INTO t (body) VALUES ('a'); END")?;
+        assert_eq!(count(&mut db)?, rows + 1);
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(count(&mut db)?, rows + 1);
+        assert_eq!(db.integrity_check()?, []);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
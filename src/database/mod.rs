@@ -1,77 +1,350 @@
-use std::{collections::HashMap, fs::File, os::unix::fs::FileExt};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    iter, mem,
+    ops::{Bound, ControlFlow},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    slice,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
-    database::query::{QueryFilter, QuerySelect, Row, Table},
+    database::query::{
+        is_rowid_alias, ColumnRange, QueryFilter, QuerySelect, Row, RowSink, Table, ROWID_NAMES,
+    },
     sql::{
-        self, parse_sql,
+        self, parse_sql, parse_sql_with_text,
         syntax::{
             ColType, ColumnConstraint, ColumnDefinition, CreateStatement, DbValue, Expr,
-            SelectStatement, Statement, Visit,
+            IndexedColumn, SelectStatement, Statement,
         },
     },
 };
 
 use self::{
-    header::Header,
+    cache::{CacheLimit, CacheStats, PageCache},
+    check::{Checker, Problem, TreeKind},
+    expression::Precompile,
+    freelist::Freelist,
+    header::{BTreeHeader, BTreePageType, Header, RWMode, TextEncoding},
+    journal::{JournalReader, JournalWriter, RollbackJournal},
+    page::PayloadLimits,
+    pager::{FilePager, MmapPager},
+    ptrmap::PointerMap,
+    query::RowCursor,
     record::Record,
-    tree::{BTreeTableReader, PageSupplier},
+    tree::{
+        compare_index_keys, BTreeTableReader, IndexCursor, PageConsumer, PageSupplier, Pager,
+        TableCursor,
+    },
+    wal::WalReader,
+    writer::{BTreeWriter, Key, Neighbours},
 };
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use itertools::Itertools;
 
+pub(crate) mod cache;
+pub(crate) mod check;
 mod expression;
+pub(crate) mod freelist;
 pub(crate) mod header;
+mod journal;
 mod page;
-mod query;
+mod pager;
+mod ptrmap;
+pub(crate) mod query;
 mod record;
 mod schema;
 mod tree;
 mod varint;
+mod wal;
+mod writer;
+
+/// Knobs for [`DbAccess::open_path`]
+#[derive(Debug, Default, Clone)]
+pub(crate) struct OpenOptions {
+    pub cache: CacheLimit,
+    /// Read pages straight from a memory mapping of the file instead of going through the cache
+    pub mmap: bool,
+    /// Play a hot rollback journal back onto the database file instead of just reading through it
+    pub restore_journal: bool,
+}
 
 /// Struct (deal with it functional bros jk ily) that handles interaction with the on disk database
 /// file
 pub(crate) struct DbAccess {
     pub header: Header,
-    // NOTE: Non so se sia meglio magari usare solo un Read, però tanto io su un file devo scrivere
-    dbfile: File,
-    page: Vec<u8>,
-    /// Holds the start offset of a specific page, only
-    /// useful for the first page start
-    start_offset: usize,
+    pager: JournalWriter<Box<dyn Pager>>,
+    /// things worth telling the user about how the database was opened, like parts of the wal
+    /// that were left out
+    notices: Vec<String>,
 }
 
 impl DbAccess {
     /// Create a new db
+    #[cfg(test)]
     pub(crate) fn new(dbfile: File) -> Result<Self> {
+        Self::open(dbfile, &OpenOptions::default())
+    }
+
+    /// Opens `dbfile` alone, without looking for a wal or a hot journal next to it
+    #[cfg(test)]
+    pub(crate) fn open(dbfile: File, options: &OpenOptions) -> Result<Self> {
+        Self::open_with(dbfile, None, None, None, options)
+    }
+
+    /// Opens the database at `path`, together with its `-wal` file when the database is in WAL
+    /// mode and there is one. A hot `-journal` left behind by an interrupted transaction is used
+    /// to show the database as it was before it, and only played back onto the file when
+    /// [`OpenOptions::restore_journal`] is set. Until then the database cannot be written, since
+    /// transactions keep their own `-journal` next to it
+    pub(crate) fn open_path(path: impl AsRef<Path>, options: &OpenOptions) -> Result<Self> {
+        let path = path.as_ref();
+        let sibling = |suffix: &str| {
+            let mut sibling = path.as_os_str().to_owned();
+            sibling.push(suffix);
+            PathBuf::from(sibling)
+        };
+        // fall back to reading when the file cannot be written, statements that modify the
+        // database will fail then
+        let dbfile = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .or_else(|_| File::open(path))?;
+        let header = Self::read_header(&dbfile)?;
+        let page_size = header.page_size as usize;
+        let mut journal = match (header.read_version, File::open(sibling("-journal"))) {
+            (RWMode::WAL, _) | (_, Err(_)) => None,
+            (_, Ok(journal)) => RollbackJournal::read(journal, page_size)?,
+        };
+        let mut restored = None;
+        if let (true, Some(hot)) = (options.restore_journal, &journal) {
+            hot.restore(&fs::OpenOptions::new().write(true).open(path)?)?;
+            fs::remove_file(sibling("-journal"))?;
+            restored = Some(format!(
+                "restored {} pages from the hot journal",
+                hot.page_count()
+            ));
+            journal = None;
+        }
+        let mut db = Self::open_with(
+            dbfile,
+            File::open(sibling("-wal")).ok(),
+            journal,
+            Some(sibling("-journal")),
+            options,
+        )?;
+        db.notices.splice(0..0, restored);
+        Ok(db)
+    }
+
+    /// Creates a database at `path`, which must not exist yet, holding nothing but an empty
+    /// sqlite_schema on its only page, and opens it
+    pub(crate) fn create(
+        path: impl AsRef<Path>,
+        page_size: u32,
+        text_encoding: TextEncoding,
+        options: &OpenOptions,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let header = Header::empty(page_size, text_encoding)?;
+        let dbfile = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        dbfile.write_all_at(&empty_database(&header), 0)?;
+        dbfile.sync_all()?;
+        Self::open_path(path, options)
+    }
+
+    /// Copies the database into a new file at `path`, which must not exist yet. Every b-tree
+    /// listed in sqlite_schema is rebuilt there from its entries, on pages filled one after the
+    /// other, so that the copy has no free pages and its schema points to the new root pages
+    pub(crate) fn vacuum_into(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        // the cursors stop at the first page they cannot read, which would go unnoticed
+        if let Some(problem) = self.integrity_check()?.first() {
+            bail!("database disk image is malformed: {problem}");
+        }
+        let page_size = self.header.page_size as usize;
+        let mut header = Header::empty(self.header.page_size, self.header.text_encoding)?;
+        header.reserved_size = self.header.reserved_size;
+        header.user_version = self.header.user_version;
+        header.application_id = self.header.application_id;
+        header.schema_cookie = self.header.schema_cookie.wrapping_add(1);
+        let (encoding, format) = (header.text_encoding, header.schema_format_number);
+        let dbfile = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .with_context(|| format!("cannot create {}", path.as_ref().display()))?;
+        dbfile.write_all_at(&empty_database(&header), 0)?;
+        // nobody else sees the new file before it is complete, there is nothing to journal
+        let mut target = FilePager::new(dbfile, page_size, header.payload_limits());
+        let schema = self.read_schema()?;
+        let mut writer = BTreeWriter::new(&mut target, &mut header);
+        for (rowid, mut row) in (1..).zip(schema) {
+            // views and triggers have no b-tree
+            if let Some(&Record::Integer(root @ 1..)) = row.get(3) {
+                let root = root as usize;
+                let new_root = match self.btree_header(root)?.page_type {
+                    BTreePageType::LeafTable | BTreePageType::InteriorTable => {
+                        let mut cursor = TableCursor::new(self, root);
+                        let rows = iter::from_fn(|| {
+                            cursor.next()?;
+                            let DbValue::Integer(rowid) = *cursor.rowid() else {
+                                return Some(Err(anyhow!("row without a rowid at page {root}")));
+                            };
+                            let payload = cursor
+                                .payload()
+                                .map_err(|_| anyhow!("could not read row {rowid} at page {root}"));
+                            Some(payload.map(|payload| (Some(rowid), payload.to_vec())))
+                        });
+                        writer.build(BTreePageType::LeafTable, rows)?
+                    }
+                    BTreePageType::LeafIndex | BTreePageType::InteriorIndex => {
+                        let mut cursor = IndexCursor::new(self, root);
+                        let entries = iter::from_fn(|| {
+                            cursor.next()?;
+                            let entry: Vec<Record> =
+                                cursor.record().iter().cloned().map(Record::from).collect();
+                            Some(Ok((None, Record::write_row(&entry, encoding, format))))
+                        });
+                        writer.build(BTreePageType::LeafIndex, entries)?
+                    }
+                };
+                row[3] = Record::Integer(new_root as i64);
+            }
+            writer.insert(
+                1,
+                &Key::Rowid(rowid),
+                &Record::write_row(&row, encoding, format),
+            )?;
+        }
+        // page 1 holds the header as it was when sqlite_schema was last written
+        target.move_to_page(1)?;
+        let mut page = target.page().to_vec();
+        header.write(&mut page);
+        target.write_page(1, &page)?;
+        target.sync()
+    }
+
+    /// Rebuilds the database with [`DbAccess::vacuum_into`] in a temporary file, whose pages
+    /// are then written over the database in a transaction that also cuts the file down to size
+    fn vacuum(&mut self) -> Result<()> {
+        self.check_writable()?;
+        if self.pager.in_transaction() {
+            bail!("cannot VACUUM from within a transaction");
+        }
+        if self.header.largest_root_btree_page != 0 {
+            // the copy would come back without its pointer map
+            bail!("VACUUM of an auto-vacuum database is not supported");
+        }
+        let nonce = SystemTime::now().duration_since(UNIX_EPOCH)?.subsec_nanos();
+        let path = std::env::temp_dir().join(format!("vacuum_{}_{nonce}.db", std::process::id()));
+        let result = self.vacuum_into(&path).and_then(|()| {
+            let compact = File::open(&path)?;
+            let size = Self::read_header(&compact)?.size_in_pages as usize;
+            let change_counter = self.header.file_change_counter;
+            self.write(|db| {
+                let page_size = db.header.page_size as usize;
+                let mut page = vec![0; page_size];
+                for idx in 1..=size {
+                    compact.read_exact_at(&mut page, ((idx - 1) * page_size) as u64)?;
+                    db.pager.write_page(idx, &page)?;
+                }
+                db.pager.truncate(size)?;
+                db.reload_header()?;
+                // the copy started counting afresh, but other connections must see a change
+                db.header.file_change_counter = change_counter;
+                db.write_header()
+            })
+        });
+        // the copy is of no use anymore, whether it made it back into the database or not
+        let _ = fs::remove_file(&path);
+        result
+    }
+
+    fn read_header(dbfile: &File) -> Result<Header> {
         let mut buf = [0; 100];
-        dbfile.read_exact_at(&mut buf, 0)?;
-        let header = Header::new(buf.as_slice())?;
+        // let Header::new tell a truncated header apart from a file that is not a database
+        let len = dbfile.metadata()?.len().min(100) as usize;
+        dbfile.read_exact_at(&mut buf[..len], 0)?;
+        Ok(Header::new(&buf[..len])?)
+    }
+
+    fn open_with(
+        dbfile: File,
+        wal: Option<File>,
+        journal: Option<RollbackJournal>,
+        journal_path: Option<PathBuf>,
+        options: &OpenOptions,
+    ) -> Result<Self> {
+        let mut header = Self::read_header(&dbfile)?;
         let page_size = header.page_size as usize;
+        let limits = header.payload_limits();
+        let mut notices = Vec::new();
+        let mut pager: Box<dyn Pager> = match options.mmap {
+            true => match MmapPager::new(&dbfile, page_size, limits) {
+                Ok(pager) => Box::new(pager),
+                Err(e) => {
+                    notices.push(format!(
+                        "could not map the database file, falling back to pread: {e}"
+                    ));
+                    Box::new(FilePager::new(dbfile, page_size, limits))
+                }
+            },
+            false => Box::new(FilePager::new(dbfile, page_size, limits)),
+        };
+        if let (RWMode::WAL, Some(wal)) = (header.read_version, wal) {
+            let wal = WalReader::new(pager, wal, page_size)?;
+            notices.extend(wal.notice());
+            pager = Box::new(wal);
+            // the wal may hold a newer first page too
+            pager.move_to_page(1)?;
+            header = Header::new(pager.page())?;
+        }
+        if let Some(journal) = journal {
+            notices.push(format!(
+                "hot journal found, reading {} pages as they were before the last transaction",
+                journal.page_count()
+            ));
+            pager = Box::new(JournalReader::new(pager, journal));
+            pager.move_to_page(1)?;
+            header = Header::new(pager.page())?;
+        }
+        fill_in_size(&mut header, &pager);
+        if !options.mmap {
+            pager = Box::new(PageCache::new(pager, page_size, options.cache));
+        }
+        let pager = JournalWriter::new(pager, journal_path, page_size);
         Ok(Self {
             header,
-            dbfile,
-            page: vec![0; page_size],
-            start_offset: 0,
+            pager,
+            notices,
         })
     }
 
-    /// Reads required page into memory for later analysis
-    fn seek_page(&mut self, page_number: usize) -> Result<()> {
-        let page_idx = (page_number as u64 - 1) * self.header.page_size as u64;
-        self.dbfile.read_exact_at(&mut self.page, page_idx)?;
-        self.start_offset = if page_number == 1 { 100 } else { 0 };
-        Ok(())
+    /// What is worth telling the user about how the database was opened
+    pub(crate) fn notices(&self) -> &[String] {
+        &self.notices
+    }
+
+    /// Hit and miss counters of the page cache
+    pub(crate) fn cache_stats(&self) -> Option<CacheStats> {
+        self.pager.cache_stats()
     }
 
     /// reads the header of a btree page
-    fn btree_header(&mut self) -> Result<header::BTreeHeader> {
-        header::BTreeHeader::new(&self.page[self.start_offset..])
+    fn btree_header(&mut self, page_number: usize) -> Result<header::BTreeHeader> {
+        header::BTreeHeader::new(self.pager.read_page(page_number)?)
             .context("could not read btree page header")
     }
 
     fn load_schema(&mut self) -> Result<()> {
-        self.seek_page(1)?;
-        let schema_header = self.btree_header()?;
+        let schema_header = self.btree_header(1)?;
 
         todo!()
     }
@@ -90,12 +363,96 @@ impl DbAccess {
             .and_then(|mut v| {
                 v.iter_mut()
                     .map(|(_rowid, row)| {
-                        Record::read_row(row.as_slice()).map_err(|e| anyhow::anyhow!("{e:?}"))
+                        Record::read_row(row.as_slice(), self.header.text_encoding)
+                            .map_err(|e| anyhow::anyhow!("{e:?}"))
                     })
                     .collect()
             })
     }
 
+    /// Walks the freelist, making sure it agrees with the header
+    pub(crate) fn freelist(&mut self) -> Result<Freelist> {
+        let first = self.header.first_freelist_page as usize;
+        let total = self.header.total_freelist as usize;
+        let size = self.header.size_in_pages as usize;
+        Ok(Freelist::read(self, first, total, size)?)
+    }
+
+    /// Walks every btree listed in sqlite_schema along with the freelist, collecting whatever
+    /// does not add up
+    pub(crate) fn integrity_check(&mut self) -> Result<Vec<Problem>> {
+        let size = self.header.size_in_pages as usize;
+        let first_freelist = self.header.first_freelist_page as usize;
+        let total_freelist = self.header.total_freelist as usize;
+        let pointer_map = (self.header.largest_root_btree_page != 0)
+            .then(|| PointerMap::new(self.payload_limits().usable_size));
+        let (schema, unreadable) = match self.read_schema() {
+            Ok(schema) => (schema, None),
+            Err(_) => (
+                Vec::new(),
+                Some(Problem::Unreadable {
+                    page: 1,
+                    reason: "sqlite_schema could not be read".to_owned(),
+                }),
+            ),
+        };
+        let mut checker = Checker::new(self, size);
+        if let Some(map) = pointer_map {
+            for page in map.map_pages(size) {
+                checker.reserve(page);
+            }
+        }
+        checker.check_tree(1, &TreeKind::Table);
+        for row in schema {
+            let (Some(Record::String(typ)), Some(Record::Integer(root))) =
+                (row.first(), row.get(3))
+            else {
+                continue;
+            };
+            if *root <= 0 {
+                // views and triggers have no btree
+                continue;
+            }
+            let kind = if typ == "index" {
+                let asc = match row.get(4) {
+                    Some(Record::String(sql)) => match parse_sql(sql).unwrap_or_default().pop() {
+                        Some(Statement::Create(CreateStatement::Index { cols, .. })) => {
+                            cols.iter().map(|c| c.asc).collect()
+                        }
+                        _ => Vec::new(),
+                    },
+                    // automatic indices are always ascending
+                    _ => Vec::new(),
+                };
+                TreeKind::Index(asc)
+            } else {
+                match row.get(4) {
+                    Some(Record::String(sql)) => match parse_sql(sql).unwrap_or_default().pop() {
+                        Some(Statement::Create(CreateStatement::Table {
+                            cols,
+                            primary_key,
+                            without_rowid: true,
+                            ..
+                        })) => TreeKind::Index(
+                            table_key(&cols, &primary_key)
+                                .iter()
+                                .map(|c| c.asc)
+                                .collect(),
+                        ),
+                        _ => TreeKind::Table,
+                    },
+                    _ => TreeKind::Table,
+                }
+            };
+            checker.check_tree(*root as usize, &kind);
+        }
+        checker.check_freelist(first_freelist, total_freelist);
+        if let Some(map) = pointer_map {
+            checker.check_pointer_map(map);
+        }
+        Ok(unreadable.into_iter().chain(checker.finish()).collect())
+    }
+
     pub(crate) fn number_of_tables(&mut self) -> Result<usize> {
         self.read_schema().map(|v| v.len())
     }
@@ -110,15 +467,848 @@ impl DbAccess {
             .collect()
     }
     pub(crate) fn run_query(&mut self, query: &str) -> Vec<Table> {
-        let statements = parse_sql(query);
+        self.execute(query).unwrap_or_else(|e| panic!("{e:#}"))
+    }
+
+    /// Runs every statement in `sql`, returning the results of the queries among them. Unlike
+    /// [`DbAccess::run_query`], statements that modify the database report their failures
+    pub(crate) fn execute(&mut self, sql: &str) -> Result<Vec<Table>> {
+        let mut tables = Vec::new();
+        self.execute_with(sql, &mut tables)?;
+        Ok(tables)
+    }
+
+    /// Runs every statement in `sql` like [`DbAccess::execute`], handing the rows of the queries
+    /// to `sink` as they are read instead of collecting them
+    pub(crate) fn execute_with(&mut self, sql: &str, sink: &mut dyn RowSink) -> Result<()> {
+        for (stmt, text) in &parse_sql_with_text(sql)? {
+            match stmt {
+                Statement::Begin => self.pager.begin(self.header.size_in_pages as usize)?,
+                Statement::Commit => {
+                    if let Err(e) = self.pager.commit() {
+                        self.reload_header()?;
+                        return Err(e);
+                    }
+                }
+                Statement::Rollback => {
+                    self.pager.rollback()?;
+                    self.reload_header()?;
+                }
+                Statement::Create(CreateStatement::Table {
+                    name,
+                    cols,
+                    primary_key,
+                    without_rowid,
+                }) => {
+                    self.write(|db| db.create_table(name, cols, primary_key, *without_rowid, text))?
+                }
+                Statement::Create(CreateStatement::Index { .. }) => {
+                    bail!("CREATE INDEX is not supported")
+                }
+                Statement::Insert {
+                    table,
+                    columns,
+                    values,
+                } => self.write(|db| db.insert(table, columns, values))?,
+                Statement::Delete { table, filter } => {
+                    self.write(|db| db.delete(table, filter.as_ref()))?
+                }
+                Statement::Update {
+                    table,
+                    assignments,
+                    filter,
+                } => self.write(|db| db.update(table, assignments, filter.as_ref()))?,
+                Statement::Vacuum { into: Some(path) } => self.vacuum_into(path)?,
+                Statement::Vacuum { into: None } => self.vacuum()?,
+                Statement::Select(select) => self.select(select, sink)?,
+                Statement::Pragma { name, value } => self.pragma(name, value.as_ref(), sink)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a PRAGMA statement, only integrity_check is known: like sqlite, it stops after the
+    /// given number of problems (100 by default) and gives a single "ok" row when there are none
+    fn pragma(&mut self, name: &str, value: Option<&Expr>, sink: &mut dyn RowSink) -> Result<()> {
+        if !name.eq_ignore_ascii_case("integrity_check") {
+            bail!("PRAGMA {name} is not supported");
+        }
+        let max = match value {
+            Some(Expr::Literal {
+                value: DbValue::Integer(n),
+            }) if *n > 0 => *n as usize,
+            _ => 100,
+        };
+        let mut rows: Vec<_> = self
+            .integrity_check()?
+            .iter()
+            .take(max)
+            .map(|p| Row::new(DbValue::Null, vec![DbValue::Text(p.to_string())]))
+            .collect();
+        if rows.is_empty() {
+            rows.push(Row::new(
+                DbValue::Null,
+                vec![DbValue::Text("ok".to_owned())],
+            ));
+        }
+        let column = ColumnDefinition {
+            name: "integrity_check".to_owned(),
+            position: 0,
+            typ: sql::syntax::ColType::TEXT,
+            constraint: vec![],
+        };
+        let result = Table::new(&[column], rows);
+        sink.columns(&result.columns);
+        for row in result.rows {
+            if sink.row(row).is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a statement that modifies the database in a transaction of its own, unless one was
+    /// started with BEGIN: then a failure only undoes the statement, and the rest is up to the
+    /// COMMIT or ROLLBACK that ends it
+    fn write(&mut self, run: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        let autocommit = !self.pager.in_transaction();
+        if autocommit {
+            self.pager.begin(self.header.size_in_pages as usize)?;
+        }
+        self.pager.begin_statement();
+        let result = run(self).and_then(|()| {
+            self.pager.end_statement();
+            match autocommit {
+                true => self.pager.commit(),
+                false => Ok(()),
+            }
+        });
+        if result.is_err() {
+            if self.pager.in_transaction() {
+                match autocommit {
+                    true => self.pager.rollback()?,
+                    false => self.pager.undo_statement()?,
+                }
+            }
+            self.reload_header()?;
+        }
+        result
+    }
+
+    /// Reads the header again, once the pages written since it was last read are thrown away
+    fn reload_header(&mut self) -> Result<()> {
+        self.pager.move_to_page(1)?;
+        self.header = Header::new(self.pager.page())?;
+        fill_in_size(&mut self.header, &self.pager);
+        Ok(())
+    }
+
+    /// Makes sure the database is one we know how to modify without breaking it
+    fn check_writable(&self) -> Result<()> {
+        if self.header.write_version == RWMode::None {
+            bail!("the database was written by a newer version of sqlite, it can only be read");
+        }
+        if self.header.read_version == RWMode::WAL {
+            bail!("writing to a database in WAL mode is not supported");
+        }
+        if self.header.size_in_pages == 0 {
+            // new pages would be handed out over the ones in use
+            bail!("the size of the database is unknown, it can only be read");
+        }
+        Ok(())
+    }
+
+    /// Root pages of every b-tree in the database, sqlite_schema's included
+    fn roots(&mut self) -> Result<Vec<usize>> {
+        let schema = self.read_schema()?;
+        let roots = schema.iter().filter_map(|row| match row.get(3) {
+            Some(&Record::Integer(root @ 1..)) => Some(root as usize),
+            _ => None,
+        });
+        Ok(iter::once(1).chain(roots).collect())
+    }
+
+    /// Bumps the change counter and writes the header back to page 1, once a statement is done
+    /// modifying the database
+    fn write_header(&mut self) -> Result<()> {
+        if self.header.largest_root_btree_page != 0 {
+            // pages the statement took, freed or moved around need their entry to say so
+            let roots = self.roots()?;
+            BTreeWriter::new(&mut self.pager, &mut self.header).update_pointer_map(&roots)?;
+        }
+        self.header.file_change_counter = self.header.file_change_counter.wrapping_add(1);
+        // tells sqlite the page count in the header can be trusted
+        self.header.version_valid_for = self.header.file_change_counter;
+        self.pager.move_to_page(1)?;
+        let mut page = self.pager.page().to_vec();
+        self.header.write(&mut page);
+        self.pager.write_page(1, &page)
+    }
+
+    /// Creates the table `name`, along with the indices behind its UNIQUE and PRIMARY KEY
+    /// constraints, and lists them in sqlite_schema. `sql` is the text of the statement, which
+    /// is stored as is but for the leading keywords. WITHOUT ROWID tables keep their rows in an
+    /// index btree, ordered by `primary_key` or by the column declared PRIMARY KEY
+    fn create_table(
+        &mut self,
+        name: &str,
+        cols: &[ColumnDefinition],
+        primary_key: &[IndexedColumn],
+        without_rowid: bool,
+        sql: &str,
+    ) -> Result<()> {
+        self.check_writable()?;
+        if name.to_ascii_lowercase().starts_with("sqlite_") {
+            bail!("object name reserved for internal use: {name}");
+        }
+        let taken = self.read_schema()?.iter().any(|row| match row.get(1) {
+            Some(Record::String(other)) => other.eq_ignore_ascii_case(name),
+            _ => false,
+        });
+        if taken {
+            bail!("table {name} already exists");
+        }
+        for (i, col) in cols.iter().enumerate() {
+            if cols[..i]
+                .iter()
+                .any(|c| c.name.eq_ignore_ascii_case(&col.name))
+            {
+                bail!("duplicate column name: {}", col.name);
+            }
+            if col
+                .constraint
+                .iter()
+                .any(|c| matches!(c, ColumnConstraint::Pk { autoinc: true, .. }))
+            {
+                // it would need sqlite_sequence to keep track of the largest rowid ever used
+                bail!("AUTOINCREMENT is not supported");
+            }
+        }
+        let has_pk = |c: &&ColumnDefinition| {
+            c.constraint
+                .iter()
+                .any(|c| matches!(c, ColumnConstraint::Pk { .. }))
+        };
+        if cols.iter().filter(has_pk).count() + usize::from(!primary_key.is_empty()) > 1 {
+            bail!("table \"{name}\" has more than one primary key");
+        }
+        for key in primary_key {
+            if !cols.iter().any(|c| c.name == key.name) {
+                bail!("no such column: {}", key.name);
+            }
+        }
+        if without_rowid && table_key(cols, primary_key).is_empty() {
+            bail!("PRIMARY KEY missing on table {name}");
+        }
+        // same rules get_index_defs reads them back with
+        let automatic = automatic_indices(cols, primary_key, without_rowid);
+        // sqlite keeps the text as it was written, but for the keywords in front of the name
+        let mut rest = sql.trim_start();
+        for keyword in ["CREATE", "TABLE"] {
+            match rest.get(..keyword.len()) {
+                Some(word) if word.eq_ignore_ascii_case(keyword) => {
+                    rest = rest[keyword.len()..].trim_start()
+                }
+                _ => bail!("{sql} is not a CREATE TABLE statement"),
+            }
+        }
+        let sql = format!("CREATE TABLE {rest}");
+
+        let encoding = self.header.text_encoding;
+        let format = self.header.schema_format_number;
+        let roots = self.roots()?;
+        let mut writer = BTreeWriter::new(&mut self.pager, &mut self.header);
+        let mut rowid = writer.last_rowid(1)?.unwrap_or(0);
+        let table_type = match without_rowid {
+            true => BTreePageType::LeafIndex,
+            false => BTreePageType::LeafTable,
+        };
+        let root = writer.create(table_type, &roots)?;
+        let mut rows = vec![[
+            Record::String("table".to_owned()),
+            Record::String(name.to_owned()),
+            Record::String(name.to_owned()),
+            Record::Integer(root as i64),
+            Record::String(sql),
+        ]];
+        for n in (1..=automatic.len()).filter(|n| automatic[n - 1].is_some()) {
+            let root = writer.create(BTreePageType::LeafIndex, &roots)?;
+            rows.push([
+                Record::String("index".to_owned()),
+                Record::String(format!("sqlite_autoindex_{name}_{n}")),
+                Record::String(name.to_owned()),
+                Record::Integer(root as i64),
+                Record::Null,
+            ]);
+        }
+        for row in rows {
+            rowid += 1;
+            writer.insert(
+                1,
+                &Key::Rowid(rowid),
+                &Record::write_row(&row, encoding, format),
+            )?;
+        }
+        // tells every connection with the old schema cached to read it again
+        self.header.schema_cookie = self.header.schema_cookie.wrapping_add(1);
+        self.write_header()
+    }
+
+    /// Root page and columns of `table`, making sure it is one we can modify
+    fn writable_table(&mut self, table: &str) -> Result<(usize, Vec<ColumnDefinition>)> {
+        self.check_writable()?;
+        let is_table = |row: &Vec<Record>| match (row.first(), row.get(1)) {
+            (Some(Record::String(typ)), Some(Record::String(name))) => {
+                typ == "table" && name == table
+            }
+            _ => false,
+        };
+        if table.starts_with("sqlite_") {
+            bail!("table {table} may not be modified");
+        }
+        if !self.read_schema()?.iter().any(is_table) {
+            bail!("no such table: {table}");
+        }
+        let (rootpage, sql) = self.get_table_def(table);
+        match parse_sql(&sql).unwrap_or_default().pop() {
+            Some(Statement::Create(CreateStatement::Table {
+                without_rowid: true,
+                ..
+            })) => bail!("cannot modify {table}: WITHOUT ROWID tables are read only"),
+            Some(Statement::Create(CreateStatement::Table { cols, .. })) => {
+                Ok((rootpage as usize, cols))
+            }
+            _ => bail!("could not parse the definition of {table}"),
+        }
+    }
+
+    /// How to build the entries of each index on `table`, whose columns are `cols`
+    fn index_layouts(
+        &mut self,
+        table: &str,
+        cols: &[ColumnDefinition],
+    ) -> Result<Vec<IndexLayout>> {
+        let defs = self.get_index_defs(table)?;
+        let total = self
+            .read_schema()?
+            .iter()
+            .filter(|row| match (row.first(), row.get(2)) {
+                (Some(Record::String(typ)), Some(Record::String(name))) => {
+                    typ == "index" && name == table
+                }
+                _ => false,
+            })
+            .count();
+        if defs.len() != total {
+            bail!("{table} has an index that cannot be kept up to date, like a partial one");
+        }
+        defs.into_iter()
+            .map(|(root, indexed, unique)| {
+                let positions = indexed
+                    .iter()
+                    .map(|c| {
+                        cols.iter()
+                            .position(|col| col.name == c.name)
+                            .with_context(|| format!("no column {} in {table}", c.name))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let asc = indexed.iter().map(|c| c.asc).collect();
+                Ok(IndexLayout {
+                    root,
+                    positions,
+                    asc,
+                    unique,
+                })
+            })
+            .collect()
+    }
+
+    /// Inserts the rows in `values` into `table`, all of them are checked against the constraints
+    /// of the table before the first one is written
+    fn insert(&mut self, table: &str, columns: &[String], values: &[Vec<Expr>]) -> Result<()> {
+        let (rootpage, cols) = self.writable_table(table)?;
+        let targets = match columns.is_empty() {
+            true => (0..cols.len()).collect(),
+            false => columns
+                .iter()
+                .map(|name| {
+                    cols.iter()
+                        .position(|c| &c.name == name)
+                        .with_context(|| format!("table {table} has no column named {name}"))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+        let alias = cols.iter().position(is_rowid_alias);
+        let rowid_name = alias.map_or("rowid", |i| cols[i].name.as_str());
+        let indices = self.index_layouts(table, &cols)?;
+        let encoding = self.header.text_encoding;
+        let format = self.header.schema_format_number;
+        let mut last_rowid = BTreeWriter::new(&mut self.pager, &mut self.header)
+            .last_rowid(rootpage)?
+            .unwrap_or(0);
+
+        let mut rows: Vec<(i64, Vec<DbValue>, Vec<Vec<DbValue>>)> = Vec::new();
+        for exprs in values {
+            if exprs.len() != targets.len() {
+                bail!("{} values for {} columns", exprs.len(), targets.len());
+            }
+            let mut row = vec![DbValue::Null; cols.len()];
+            for (&i, expr) in targets.iter().zip(exprs) {
+                let value = expr.clone().precompile()(&HashMap::<String, DbValue>::new());
+                row[i] = with_affinity(value, &cols[i].typ);
+            }
+            // the INTEGER PRIMARY KEY is the rowid, the record only holds a NULL in its place
+            let rowid = match alias.map(|i| mem::replace(&mut row[i], DbValue::Null)) {
+                Some(DbValue::Integer(rowid)) => rowid,
+                None | Some(DbValue::Null) => last_rowid
+                    .checked_add(1)
+                    .context("database or disk is full")?,
+                Some(_) => bail!("datatype mismatch"),
+            };
+            last_rowid = last_rowid.max(rowid);
+            for (i, col) in cols.iter().enumerate() {
+                let not_null = col
+                    .constraint
+                    .iter()
+                    .any(|c| matches!(c, ColumnConstraint::NotNull(_)));
+                if not_null && Some(i) != alias && row[i] == DbValue::Null {
+                    bail!("NOT NULL constraint failed: {table}.{}", col.name);
+                }
+            }
+            let duplicate = rows.iter().any(|(r, ..)| *r == rowid)
+                || BTreeTableReader {}
+                    .find_by_rowid(rootpage, rowid, self)
+                    .map_err(|_| anyhow::anyhow!("could not look up rowid {rowid}"))?
+                    .is_some();
+            if duplicate {
+                bail!("UNIQUE constraint failed: {table}.{rowid_name}");
+            }
+            let mut entries = Vec::with_capacity(indices.len());
+            for (n, index) in indices.iter().enumerate() {
+                let entry = index.entry(&row, rowid, alias);
+                let key = &entry[..index.positions.len()];
+                // NULLs are all different from each other, so they never conflict
+                if index.unique && !key.contains(&DbValue::Null) {
+                    let same = |other: &[DbValue]| {
+                        other.len() >= key.len()
+                            && compare_index_keys(&other[..key.len()], key, &index.asc, encoding)
+                                .is_eq()
+                    };
+                    let Neighbours { before, after } =
+                        BTreeWriter::new(&mut self.pager, &mut self.header)
+                            .neighbours(index.root, &Key::Index(&entry, &index.asc))?;
+                    let conflict = before.is_some_and(|b| same(&b))
+                        || after.is_some_and(|a| same(&a))
+                        || rows.iter().any(|(.., e)| same(&e[n]));
+                    if conflict {
+                        let names = index
+                            .positions
+                            .iter()
+                            .map(|&i| format!("{table}.{}", cols[i].name))
+                            .join(", ");
+                        bail!("UNIQUE constraint failed: {names}");
+                    }
+                }
+                entries.push(entry);
+            }
+            rows.push((rowid, row, entries));
+        }
+
+        let mut writer = BTreeWriter::new(&mut self.pager, &mut self.header);
+        for (rowid, row, entries) in rows {
+            let record: Vec<_> = row.into_iter().map(Record::from).collect();
+            writer.insert(
+                rootpage,
+                &Key::Rowid(rowid),
+                &Record::write_row(&record, encoding, format),
+            )?;
+            for (index, entry) in indices.iter().zip(entries) {
+                let record: Vec<_> = entry.iter().cloned().map(Record::from).collect();
+                writer.insert(
+                    index.root,
+                    &Key::Index(&entry, &index.asc),
+                    &Record::write_row(&record, encoding, format),
+                )?;
+            }
+        }
+        self.write_header()
+    }
+
+    /// Deletes the rows of `table` that pass `filter` (all of them when there is none), along
+    /// with their index entries
+    fn delete(&mut self, table: &str, filter: Option<&Expr>) -> Result<()> {
+        let (rootpage, cols) = self.writable_table(table)?;
+        let indices = self.index_layouts(table, &cols)?;
+        let alias = cols.iter().position(is_rowid_alias);
+        let (_, _, rows) = self.filtered_table(table, filter, None)?;
+        let mut writer = BTreeWriter::new(&mut self.pager, &mut self.header);
+        for row in rows.rows {
+            let DbValue::Integer(rowid) = row.id else {
+                bail!("invalid rowid {:?}", row.id);
+            };
+            for index in &indices {
+                let entry = index.entry(&row.row, rowid, alias);
+                if !writer.delete(index.root, &Key::Index(&entry, &index.asc))? {
+                    bail!("index at page {} has no entry for row {rowid}", index.root);
+                }
+            }
+            writer.delete(rootpage, &Key::Rowid(rowid))?;
+        }
+        self.write_header()
+    }
+
+    /// Gives the rows of `table` that pass `filter` (all of them when there is none) the values
+    /// in `assignments`, computed from their old values. Like for inserts, the constraints are
+    /// checked on every row before the first one is written
+    fn update(
+        &mut self,
+        table: &str,
+        assignments: &[(String, Expr)],
+        filter: Option<&Expr>,
+    ) -> Result<()> {
+        let (rootpage, cols) = self.writable_table(table)?;
+        let targets = assignments
+            .iter()
+            .map(|(name, expr)| {
+                let i = cols
+                    .iter()
+                    .position(|c| &c.name == name)
+                    .with_context(|| format!("table {table} has no column named {name}"))?;
+                Ok((i, expr.clone().precompile()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let alias = cols.iter().position(is_rowid_alias);
+        let rowid_name = alias.map_or("rowid", |i| cols[i].name.as_str());
+        let indices = self.index_layouts(table, &cols)?;
+        let encoding = self.header.text_encoding;
+        let format = self.header.schema_format_number;
+        let (_, _, found) = self.filtered_table(table, filter, None)?;
+        let updated = found
+            .rows
+            .iter()
+            .map(|row| match row.id {
+                DbValue::Integer(rowid) => Ok(rowid),
+                _ => bail!("invalid rowid {:?}", row.id),
+            })
+            .collect::<Result<HashSet<_>>>()?;
+
+        let mut changes: Vec<RowChange> = Vec::new();
+        for row in found.rows {
+            let DbValue::Integer(old_rowid) = row.id else {
+                unreachable!("rowids were checked above");
+            };
+            let mut old = row.row;
+            if let Some(i) = alias {
+                old[i] = DbValue::Integer(old_rowid);
+            }
+            let values: HashMap<String, DbValue> = cols
+                .iter()
+                .map(|c| c.name.clone())
+                .zip(old.iter().cloned())
+                .collect();
+            let mut new = old.clone();
+            for (i, expr) in &targets {
+                new[*i] = with_affinity(expr(&values), &cols[*i].typ);
+            }
+            let rowid = match alias.map(|i| mem::replace(&mut new[i], DbValue::Null)) {
+                None => old_rowid,
+                Some(DbValue::Integer(rowid)) => rowid,
+                Some(_) => bail!("datatype mismatch"),
+            };
+            for (i, col) in cols.iter().enumerate() {
+                let not_null = col
+                    .constraint
+                    .iter()
+                    .any(|c| matches!(c, ColumnConstraint::NotNull(_)));
+                if not_null && Some(i) != alias && new[i] == DbValue::Null {
+                    bail!("NOT NULL constraint failed: {table}.{}", col.name);
+                }
+            }
+            // the rows being updated are checked against their new values, not the old ones
+            let duplicate = changes.iter().any(|c| c.rowid == rowid)
+                || (rowid != old_rowid
+                    && !updated.contains(&rowid)
+                    && BTreeTableReader {}
+                        .find_by_rowid(rootpage, rowid, self)
+                        .map_err(|_| anyhow::anyhow!("could not look up rowid {rowid}"))?
+                        .is_some());
+            if duplicate {
+                bail!("UNIQUE constraint failed: {table}.{rowid_name}");
+            }
+            let old_entries: Vec<_> = indices
+                .iter()
+                .map(|index| index.entry(&old, old_rowid, alias))
+                .collect();
+            let mut entries = Vec::with_capacity(indices.len());
+            for (n, index) in indices.iter().enumerate() {
+                let entry = index.entry(&new, rowid, alias);
+                let key = &entry[..index.positions.len()];
+                if index.unique && !key.contains(&DbValue::Null) {
+                    let same = |other: &[DbValue]| {
+                        other.len() >= key.len()
+                            && compare_index_keys(&other[..key.len()], key, &index.asc, encoding)
+                                .is_eq()
+                    };
+                    // entries of the rows being updated are going away
+                    let stays = |other: &Vec<DbValue>| match other.last() {
+                        Some(DbValue::Integer(r)) => !updated.contains(r),
+                        _ => true,
+                    };
+                    let Neighbours { before, after } =
+                        BTreeWriter::new(&mut self.pager, &mut self.header)
+                            .neighbours(index.root, &Key::Index(&entry, &index.asc))?;
+                    let conflict = before.is_some_and(|b| stays(&b) && same(&b))
+                        || after.is_some_and(|a| stays(&a) && same(&a))
+                        || changes.iter().any(|c| same(&c.entries[n]));
+                    if conflict {
+                        let names = index
+                            .positions
+                            .iter()
+                            .map(|&i| format!("{table}.{}", cols[i].name))
+                            .join(", ");
+                        bail!("UNIQUE constraint failed: {names}");
+                    }
+                }
+                entries.push(entry);
+            }
+            changes.push(RowChange {
+                old_rowid,
+                old_entries,
+                rowid,
+                row: new,
+                entries,
+            });
+        }
+
+        let mut writer = BTreeWriter::new(&mut self.pager, &mut self.header);
+        // everything that moves is taken out first, so that rows can trade places
+        for change in &changes {
+            for ((index, old), new) in indices.iter().zip(&change.old_entries).zip(&change.entries)
+            {
+                if old != new && !writer.delete(index.root, &Key::Index(old, &index.asc))? {
+                    bail!(
+                        "index at page {} has no entry for row {}",
+                        index.root,
+                        change.old_rowid
+                    );
+                }
+            }
+            if change.rowid != change.old_rowid {
+                writer.delete(rootpage, &Key::Rowid(change.old_rowid))?;
+            }
+        }
+        for RowChange {
+            old_rowid,
+            old_entries,
+            rowid,
+            row,
+            entries,
+        } in changes
+        {
+            let record: Vec<_> = row.into_iter().map(Record::from).collect();
+            let payload = Record::write_row(&record, encoding, format);
+            if rowid != old_rowid {
+                writer.insert(rootpage, &Key::Rowid(rowid), &payload)?;
+            } else if !writer.update(rootpage, rowid, &payload)? {
+                bail!("table {table} has no row {rowid}");
+            }
+            for ((index, old), entry) in indices.iter().zip(old_entries).zip(entries) {
+                if old != entry {
+                    let record: Vec<_> = entry.iter().cloned().map(Record::from).collect();
+                    writer.insert(
+                        index.root,
+                        &Key::Index(&entry, &index.asc),
+                        &Record::write_row(&record, encoding, format),
+                    )?;
+                }
+            }
+        }
+        self.write_header()
+    }
+
+    /// Root page and columns of `from`, and how its rows are kept
+    fn table_layout(&mut self, from: &str) -> TableLayout {
+        let (rootpage, table_def) = self.get_table_def(from);
+        let rootpage = rootpage as usize;
+        match parse_sql(&table_def).unwrap_or_default().pop() {
+            Some(Statement::Create(CreateStatement::Table {
+                cols,
+                primary_key,
+                without_rowid,
+                ..
+            })) => {
+                if !without_rowid {
+                    return TableLayout {
+                        rootpage,
+                        columns: cols,
+                        key: None,
+                    };
+                }
+                let key = table_key(&cols, &primary_key);
+                // there is no rowid for an INTEGER PRIMARY KEY to stand for
+                let columns = cols
+                    .into_iter()
+                    .map(|mut c| {
+                        c.constraint
+                            .retain(|c| !matches!(c, ColumnConstraint::Pk { .. }));
+                        c
+                    })
+                    .collect();
+                TableLayout {
+                    rootpage,
+                    columns,
+                    key: Some(key),
+                }
+            }
+            _ => unreachable!("we should be in a create statement at this point of our lives"),
+        }
+    }
+
+    /// Rows of `from` that pass `filter`, along with the root page and columns of the table.
+    /// Only the columns named in `wanted` and those the filter needs are decoded, or all of them
+    /// when `wanted` is None
+    fn filtered_table(
+        &mut self,
+        from: &str,
+        filter: Option<&Expr>,
+        wanted: Option<&[String]>,
+    ) -> Result<(usize, Vec<ColumnDefinition>, Table)> {
+        let layout = self.table_layout(from);
         let mut rows = Vec::new();
-        for stmt in &statements {
-            let qr = self.visit_statement(stmt);
-            if let QueryStep::QueryResult(res) = qr {
-                rows.push(res)
+        self.scan_rows(from, &layout, filter, wanted, &mut |row| {
+            rows.push(row);
+            ControlFlow::Continue(())
+        })?;
+        let table = Table::new(&layout.columns, rows);
+        Ok((layout.rootpage, layout.columns, table))
+    }
+
+    /// Hands the rows of `from` that pass `filter` to `emit` one at a time, as they are read,
+    /// until it breaks. The rowid or an index are used to skip the rows that cannot match when
+    /// the filter allows it, and only the columns named in `wanted` and those the filter needs
+    /// are decoded, or all of them when `wanted` is None
+    fn scan_rows(
+        &mut self,
+        from: &str,
+        layout: &TableLayout,
+        filter: Option<&Expr>,
+        wanted: Option<&[String]>,
+        emit: &mut dyn FnMut(Row) -> ControlFlow<()>,
+    ) -> Result<()> {
+        let TableLayout {
+            rootpage,
+            columns,
+            key,
+        } = layout;
+        let rootpage = *rootpage;
+        let query_filter = filter.map(|f| QueryFilter::new(f.clone()));
+        if filter.is_some() {
+            eprintln!("Found filter {filter:?}");
+        }
+        let cols: Vec<&ColumnDefinition> = columns.iter().collect();
+        let mut filtered = |row: Row| match &query_filter {
+            Some(f) if !f.matches(&cols, &row) => ControlFlow::Continue(()),
+            _ => emit(row),
+        };
+        if let Some(key) = key {
+            self.without_rowid_scan(rootpage, columns, key, filter, &mut filtered);
+            return Ok(());
+        }
+        let wanted: Option<Vec<usize>> = wanted.map(|names| {
+            let filtered = query_filter.as_ref().map_or(&[][..], |f| f.columns());
+            (0..columns.len())
+                .filter(|&i| names.iter().chain(filtered).any(|n| *n == columns[i].name))
+                .collect()
+        });
+        let (width, wanted) = (columns.len(), wanted.as_deref());
+        if let Some((start, end)) = filter.and_then(|f| rowid_range(columns, f)) {
+            let mut cursor = TableCursor::range(self, rootpage, start, end);
+            while cursor.next().is_some() {
+                if filtered(cursor.row(width, wanted)).is_break() {
+                    break;
+                }
             }
+            return Ok(());
         }
-        rows
+        // sqlite_schema has no indices, and looking for them would bring us back here
+        let rowids = match filter.filter(|_| rootpage != 1) {
+            Some(f) => self.index_rowids(from, f)?,
+            None => None,
+        };
+        let mut cursor = TableCursor::new(self, rootpage);
+        match rowids {
+            Some(rowids) => {
+                for rowid in rowids {
+                    cursor.seek(rowid);
+                    let found =
+                        cursor.next().is_some() && cursor.rowid() == &DbValue::Integer(rowid);
+                    if found && filtered(cursor.row(width, wanted)).is_break() {
+                        break;
+                    }
+                }
+            }
+            None => {
+                while cursor.next().is_some() {
+                    if filtered(cursor.row(width, wanted)).is_break() {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a query, handing each row of the result to `sink` as soon as it is computed: neither
+    /// the table nor the result are ever held in memory, and the scan stops once LIMIT is reached
+    fn select(&mut self, select: &SelectStatement, sink: &mut dyn RowSink) -> Result<()> {
+        let SelectStatement {
+            from,
+            fields,
+            filter,
+            limit,
+            offset,
+        } = select;
+        let limit = limit.as_ref().map(row_count).transpose()?.flatten();
+        let mut skip = offset
+            .as_ref()
+            .map(row_count)
+            .transpose()?
+            .flatten()
+            .unwrap_or(0);
+        let layout = self.table_layout(from);
+        //SELECT *
+        let projection = match fields.is_empty() {
+            true => QuerySelect::new(
+                layout
+                    .columns
+                    .iter()
+                    .map(|c| Expr::Identifier {
+                        value: c.name.clone(),
+                    })
+                    .collect(),
+            ),
+            // PERF: I should really avoid cloning come on
+            false => QuerySelect::new(fields.to_vec()),
+        };
+        sink.columns(&projection.output_columns());
+        if limit == Some(0) {
+            return Ok(());
+        }
+        let wanted = (!fields.is_empty()).then(|| projection.columns());
+        let cols: Vec<&ColumnDefinition> = layout.columns.iter().collect();
+        let mut returned = 0;
+        self.scan_rows(from, &layout, filter.as_ref(), wanted, &mut |row| {
+            if skip > 0 {
+                skip -= 1;
+                return ControlFlow::Continue(());
+            }
+            sink.row(projection.project(&cols, &row))?;
+            returned += 1;
+            match limit {
+                Some(limit) if returned >= limit => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        })
     }
 
     fn get_table_def(&mut self, from: &str) -> (i32, String) {
@@ -145,108 +1335,889 @@ impl DbAccess {
             }
         }
     }
+
+    /// Root pages, indexed columns and uniqueness of the indices built on `table`. Those whose
+    /// definition does not parse, like partial indices, are left out: they may not hold every row
+    fn get_index_defs(&mut self, table: &str) -> Result<Vec<(usize, Vec<IndexedColumn>, bool)>> {
+        fn text(row: &[Record], i: usize) -> Option<&str> {
+            match row.get(i) {
+                Some(Record::String(s)) => Some(s),
+                _ => None,
+            }
+        }
+        let schema = self.read_schema()?;
+        let indices: Vec<_> = schema
+            .iter()
+            .filter(|row| text(row, 0) == Some("index") && text(row, 2) == Some(table))
+            .collect();
+        // automatic indices (UNIQUE and PRIMARY KEY constraints) have no sql, they are numbered
+        // after the constraints in the order they appear in the table definition
+        let mut automatic = Vec::new();
+        if indices
+            .iter()
+            .any(|row| matches!(row.get(4), Some(Record::Null)))
+        {
+            let sql = schema
+                .iter()
+                .find(|row| text(row, 0) == Some("table") && text(row, 1) == Some(table))
+                .and_then(|row| text(row, 4))
+                .with_context(|| format!("no definition for table {table}"))?;
+            if let Some(Statement::Create(CreateStatement::Table {
+                cols,
+                primary_key,
+                without_rowid,
+                ..
+            })) = parse_sql(sql).unwrap_or_default().pop()
+            {
+                automatic = automatic_indices(&cols, &primary_key, without_rowid);
+            }
+        }
+        Ok(indices
+            .into_iter()
+            .filter_map(|row| match (text(row, 1), row.get(3), text(row, 4)) {
+                (_, Some(Record::Integer(rootpage)), Some(sql)) => {
+                    match parse_sql(sql).unwrap_or_default().pop() {
+                        Some(Statement::Create(CreateStatement::Index {
+                            cols, unique, ..
+                        })) => Some((*rootpage as usize, cols, unique)),
+                        _ => None,
+                    }
+                }
+                (Some(name), Some(Record::Integer(rootpage)), None) => {
+                    let n: usize = name
+                        .strip_prefix(&format!("sqlite_autoindex_{table}_"))?
+                        .parse()
+                        .ok()?;
+                    let cols = automatic.get(n.checked_sub(1)?)?.clone()?;
+                    Some((*rootpage as usize, cols, true))
+                }
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Hands the rows of the WITHOUT ROWID table at `rootpage` to `emit` until it breaks, its
+    /// entries hold the columns of `key` followed by the others in the order they are declared.
+    /// Only the part of the btree the first key column is restricted to by `filter` is visited
+    fn without_rowid_scan(
+        &mut self,
+        rootpage: usize,
+        columns: &[ColumnDefinition],
+        key: &[IndexedColumn],
+        filter: Option<&Expr>,
+        emit: &mut dyn FnMut(Row) -> ControlFlow<()>,
+    ) {
+        let keyed: Vec<_> = key
+            .iter()
+            .filter_map(|k| columns.iter().position(|c| c.name == k.name))
+            .collect();
+        let order: Vec<_> = keyed
+            .iter()
+            .copied()
+            .chain((0..columns.len()).filter(|i| !keyed.contains(i)))
+            .collect();
+        let encoding = self.header.text_encoding;
+        let range = key
+            .first()
+            .filter(|k| k.asc)
+            .zip(filter)
+            .and_then(|(k, f)| ColumnRange::from_filter(f, &k.name))
+            .filter(|range| seekable(range, encoding));
+        let mut cursor = match range {
+            Some(range) => IndexCursor::range(
+                self,
+                rootpage,
+                range.start.as_ref().map(slice::from_ref),
+                range.end.as_ref().map(slice::from_ref),
+            ),
+            None => IndexCursor::new(self, rootpage),
+        };
+        while cursor.next().is_some() {
+            let mut row = vec![DbValue::Null; columns.len()];
+            for (&pos, value) in order.iter().zip(cursor.record()) {
+                row[pos] = value.clone();
+            }
+            if emit(Row::new(DbValue::Null, row)).is_break() {
+                break;
+            }
+        }
+    }
+
+    /// Looks for an index whose leading column is restricted by `filter` and uses it to find
+    /// the rowids of the matching rows, returns None when no index fits and the table has to be
+    /// scanned. The rowids are collected before the table is read, as both walks need `self`
+    fn index_rowids(&mut self, table: &str, filter: &Expr) -> Result<Option<Vec<i64>>> {
+        let encoding = self.header.text_encoding;
+        let Some((index_root, range)) = self
+            .get_index_defs(table)?
+            .into_iter()
+            .filter(|(_, cols, _)| cols.first().is_some_and(|c| c.asc))
+            .find_map(|(root, cols, _)| {
+                Some((root, ColumnRange::from_filter(filter, &cols[0].name)?))
+            })
+            .filter(|(_, range)| seekable(range, encoding))
+        else {
+            return Ok(None);
+        };
+        // NULLs sort first in the index but never satisfy a comparison
+        let start = match range.start {
+            Bound::Unbounded => Bound::Excluded(DbValue::Null),
+            start => start,
+        };
+        let mut cursor = IndexCursor::range(
+            self,
+            index_root,
+            start.as_ref().map(slice::from_ref),
+            range.end.as_ref().map(slice::from_ref),
+        );
+        let mut rowids = Vec::new();
+        while cursor.next().is_some() {
+            if let DbValue::Integer(rowid) = cursor.rowid() {
+                rowids.push(*rowid);
+            }
+        }
+        Ok(Some(rowids))
+    }
 }
+
+/// Range of rowids `filter` restricts a table with `columns` to, through the rowid or its INTEGER
+/// PRIMARY KEY alias, so that only that part of the table btree is visited. None when there are
+/// no such constraints
+fn rowid_range(columns: &[ColumnDefinition], filter: &Expr) -> Option<(Bound<i64>, Bound<i64>)> {
+    let names = ROWID_NAMES
+        .into_iter()
+        .filter(|name| columns.iter().all(|c| &c.name != name))
+        .chain(
+            columns
+                .iter()
+                .filter(|c| is_rowid_alias(c))
+                .map(|c| c.name.as_str()),
+        );
+    let range = names
+        .filter_map(|name| ColumnRange::from_filter(filter, name))
+        .reduce(ColumnRange::intersect)?;
+    let to_rowid = |bound: Bound<DbValue>| match bound {
+        Bound::Included(DbValue::Integer(rowid)) => Some(Bound::Included(rowid)),
+        Bound::Excluded(DbValue::Integer(rowid)) => Some(Bound::Excluded(rowid)),
+        Bound::Unbounded => Some(Bound::Unbounded),
+        // rowids are integers, anything else is left to the filter
+        _ => None,
+    };
+    Some((to_rowid(range.start)?, to_rowid(range.end)?))
+}
+
 impl PageSupplier for DbAccess {
     type Error = anyhow::Error;
     fn move_to_page(&mut self, page_idx: usize) -> anyhow::Result<()> {
-        self.seek_page(page_idx)
+        self.pager.move_to_page(page_idx)
     }
 
     fn read_page(&mut self, page_idx: usize) -> anyhow::Result<&[u8]> {
-        self.move_to_page(page_idx)?;
-        Ok(&self.page[self.start_offset..])
+        self.pager.read_page(page_idx)
     }
 
     fn page(&self) -> &[u8] {
-        &self.page
+        self.pager.page()
+    }
+
+    fn payload_limits(&self) -> PayloadLimits {
+        self.pager.payload_limits()
+    }
+
+    fn text_encoding(&self) -> TextEncoding {
+        self.header.text_encoding
+    }
+
+    fn shared_page(&mut self, page_idx: usize) -> anyhow::Result<tree::SharedPage> {
+        self.pager.shared_page(page_idx)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.pager.cache_stats()
     }
 }
 
-// NOTE: I'm not super sure abot this
-enum QueryStep {
-    FilterStep(Box<dyn Fn(&HashMap<String, DbValue>) -> DbValue>),
-    QueryResult(Table),
-    ExecuteResult,
+/// The size of the database recorded in `header` only counts when the header says it is up to
+/// date, [`Header::new`] leaves it at 0 otherwise. Like sqlite, go by the size of the file then, or
+/// by the one of the last commit in the wal
+fn fill_in_size(header: &mut Header, pager: &impl PageSupplier) {
+    if header.size_in_pages == 0 {
+        header.size_in_pages = pager.db_size().unwrap_or(0) as u32;
+    }
 }
-impl Visit<QueryStep> for DbAccess {
-    fn visit_expr<'a>(&'a mut self, e: &sql::syntax::Expr) -> QueryStep {
-        //QueryStep::FilterStep(self.precompile_expr(e))
-        todo!()
+
+/// First page of a database with nothing in it: `header` followed by an empty sqlite_schema
+fn empty_database(header: &Header) -> Vec<u8> {
+    let mut page = vec![0; header.page_size as usize];
+    header.write(&mut page);
+    BTreeHeader {
+        page_type: BTreePageType::LeafTable,
+        freeblock_start: 0,
+        cell_count: 0,
+        cell_start: header.usable_size() as u32,
+        fragments: 0,
+        right_ptr: None,
     }
+    .write(&mut page[100..]);
+    page
+}
 
-    // TODO: restituire un Result e togliere tutti sti panics
-    fn visit_statement(&mut self, e: &sql::syntax::Statement) -> QueryStep {
-        match e {
-            sql::syntax::Statement::Create(_) => {
-                unimplemented!("execution of create statements not supported yet!")
-            }
-            sql::syntax::Statement::Select(SelectStatement {
-                from,
-                fields,
-                ref filter,
-            }) => {
-                let (rootpage, table_def) = self.get_table_def(from);
-                let table_def = &parse_sql(&table_def)[0];
-                let col_pos: HashMap<&String, usize>;
-                let columns;
-                if let Statement::Create(CreateStatement::Table { name: _name, cols }) = table_def {
-                    columns = cols;
-                    col_pos = HashMap::from_iter(cols.iter().map(|c| (&c.name, c.position)));
-                } else {
-                    unreachable!("we should be in a create statement at this point of our lives")
-                }
-                let table_reader = BTreeTableReader {};
-                let rows = table_reader
-                    .scan_table(rootpage as usize, self, &|_, _| true)
-                    .expect("sono morto male");
-
-                let rows = rows
-                    .iter()
-                    .map(|(i, r)| {
-                        Row::new(
-                            DbValue::Integer(*i),
-                            Record::read_row(r.as_slice())
-                                .unwrap()
-                                .into_iter()
-                                .map(|rec| match rec {
-                                    Record::Null => DbValue::Null,
-                                    Record::Integer(i) => DbValue::Integer(i),
-                                    Record::Float(f) => DbValue::Float(f),
-                                    Record::Blob(b) => DbValue::Blob(b),
-                                    Record::String(s) => DbValue::Text(s),
-                                    Record::Zero => DbValue::Integer(0),
-                                })
-                                .collect(),
-                        )
-                    })
-                    .collect();
-                let mut table = Table::new(columns, rows);
-                //SELECT *
-                //code generation I guess lol kill me
-                let empty;
-                let mut fields = fields;
-                if fields.is_empty() {
-                    empty = col_pos
-                        .iter()
-                        .sorted_by_key(|(_, &k)| k)
-                        .map(|(&k, _)| k)
-                        .map(|s| Expr::Identifier { value: s.clone() })
-                        .collect();
-                    fields = &empty;
-                }
-                if let Some(f) = filter {
-                    eprintln!("Found filter {filter:?}");
-                    table = table.apply(QueryFilter::new(f.clone()));
-                }
+/// Where the rows of a table live and what they hold
+struct TableLayout {
+    rootpage: usize,
+    columns: Vec<ColumnDefinition>,
+    /// primary key of a WITHOUT ROWID table, whose rows are the entries of an index btree
+    key: Option<Vec<IndexedColumn>>,
+}
 
-                // PERF: I should really avoid cloning come on
-                table = table.apply(QuerySelect::new(fields.to_vec()));
-                QueryStep::QueryResult(table)
-            }
+/// Value of a LIMIT or OFFSET clause, None when negative (which means no limit)
+fn row_count(expr: &Expr) -> Result<Option<usize>> {
+    match (expr.clone().precompile())(&HashMap::new()) {
+        DbValue::Integer(n) => Ok(usize::try_from(n).ok()),
+        _ => bail!("datatype mismatch"),
+    }
+}
+
+/// Where the values of the entries of an index come from
+struct IndexLayout {
+    root: usize,
+    /// positions of the indexed columns in the table
+    positions: Vec<usize>,
+    asc: Vec<bool>,
+    unique: bool,
+}
+
+impl IndexLayout {
+    /// Entry of the index for a row of the table: the indexed values followed by the rowid,
+    /// which also stands in for the INTEGER PRIMARY KEY at `alias`
+    fn entry(&self, row: &[DbValue], rowid: i64, alias: Option<usize>) -> Vec<DbValue> {
+        self.positions
+            .iter()
+            .map(|&i| match Some(i) == alias {
+                true => DbValue::Integer(rowid),
+                false => row[i].clone(),
+            })
+            .chain([DbValue::Integer(rowid)])
+            .collect()
+    }
+}
+
+/// A row rewritten by an UPDATE, with its entry in each index before and after
+struct RowChange {
+    old_rowid: i64,
+    old_entries: Vec<Vec<DbValue>>,
+    rowid: i64,
+    row: Vec<DbValue>,
+    entries: Vec<Vec<DbValue>>,
+}
+
+impl PageConsumer for DbAccess {
+    type Error = anyhow::Error;
+
+    fn write_page(&mut self, page_idx: usize, page: &[u8]) -> anyhow::Result<()> {
+        self.pager.write_page(page_idx, page)
+    }
+
+    fn sync(&mut self) -> anyhow::Result<()> {
+        self.pager.sync()
+    }
+}
+
+/// Converts a value to the type of the column it is stored in, when that can be done without
+/// losing anything
+fn with_affinity(value: DbValue, typ: &ColType) -> DbValue {
+    match (value, typ) {
+        (DbValue::Bool(b), _) => with_affinity(DbValue::Integer(b as i64), typ),
+        (DbValue::Text(s), ColType::INTEGER) => match (s.trim().parse(), s.trim().parse()) {
+            (Ok(i), _) => DbValue::Integer(i),
+            (_, Ok(f)) => with_affinity(DbValue::Float(f), typ),
+            _ => DbValue::Text(s),
+        },
+        (DbValue::Float(f), ColType::INTEGER) if f.fract() == 0.0 && f.abs() < 9.2e18 => {
+            DbValue::Integer(f as i64)
         }
+        (DbValue::Integer(i), ColType::TEXT) => DbValue::Text(i.to_string()),
+        (DbValue::Float(f), ColType::TEXT) => DbValue::Text(format!("{f:?}")),
+        (value, _) => value,
     }
+}
 
-    fn visit_column_definition(&mut self, e: &sql::syntax::ColumnDefinition) -> QueryStep {
-        todo!()
+/// Whether an index can be walked over `range` alone: UTF-16 text is kept in the order of its
+/// encoded bytes, which is not the order the filter compares strings with, so it can only be used
+/// to look up equal values
+fn seekable(range: &ColumnRange, encoding: TextEncoding) -> bool {
+    let is_text = |b: &Bound<DbValue>| {
+        matches!(
+            b,
+            Bound::Included(DbValue::Text(_)) | Bound::Excluded(DbValue::Text(_))
+        )
+    };
+    encoding == TextEncoding::UTF8
+        || !(is_text(&range.start) || is_text(&range.end))
+        || matches!((&range.start, &range.end), (Bound::Included(a), Bound::Included(b)) if a == b)
+}
+
+/// Columns of the primary key of a table in key order, either those listed after the columns or
+/// the single one declared PRIMARY KEY
+fn table_key(cols: &[ColumnDefinition], primary_key: &[IndexedColumn]) -> Vec<IndexedColumn> {
+    if !primary_key.is_empty() {
+        return primary_key.to_vec();
+    }
+    cols.iter()
+        .find_map(|c| {
+            c.constraint.iter().find_map(|constraint| match constraint {
+                ColumnConstraint::Pk { asc, .. } => Some(IndexedColumn {
+                    name: c.name.clone(),
+                    asc: *asc,
+                }),
+                _ => None,
+            })
+        })
+        .into_iter()
+        .collect()
+}
+
+/// Columns of the indices sqlite builds for the UNIQUE and PRIMARY KEY constraints of a table, in
+/// the order their `sqlite_autoindex_<table>_<N>` names are numbered: the constraints on the
+/// columns first, then the key listed after them. The key of a WITHOUT ROWID table takes a number
+/// too, but has no index of its own and is None
+fn automatic_indices(
+    cols: &[ColumnDefinition],
+    primary_key: &[IndexedColumn],
+    without_rowid: bool,
+) -> Vec<Option<Vec<IndexedColumn>>> {
+    let is_key = |c: &ColumnConstraint| matches!(c, ColumnConstraint::Pk { .. });
+    cols.iter()
+        .filter(|c| without_rowid || !is_rowid_alias(c))
+        .filter_map(|c| {
+            let constraint = c
+                .constraint
+                .iter()
+                .find(|c| matches!(c, ColumnConstraint::Pk { .. } | ColumnConstraint::Unique(_)))?;
+            let asc = match constraint {
+                ColumnConstraint::Pk { asc, .. } => *asc,
+                _ => true,
+            };
+            let indexed = vec![IndexedColumn {
+                name: c.name.clone(),
+                asc,
+            }];
+            Some((!(without_rowid && is_key(constraint))).then_some(indexed))
+        })
+        .chain((!primary_key.is_empty()).then(|| (!without_rowid).then(|| primary_key.to_vec())))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::header::SchemaFormat;
+    use super::ptrmap::PtrmapEntry;
+    use super::*;
+
+    fn rowids(db: &mut DbAccess, query: &str) -> Vec<i64> {
+        db.run_query(query)
+            .swap_remove(0)
+            .rows
+            .into_iter()
+            .map(|r| match r.id {
+                DbValue::Integer(id) => id,
+                id => panic!("unexpected rowid {id:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_index_lookup() -> Result<()> {
+        // see tree::test for the content of index.db
+        let mut db = DbAccess::new(File::open("fixtures/index.db")?)?;
+        let people =
+            |f: &dyn Fn(i64, i64) -> bool| (1..=2000).filter(|&i| f(i, i % 97)).collect::<Vec<_>>();
+        assert_eq!(
+            rowids(&mut db, "SELECT id FROM people WHERE age = 50"),
+            people(&|_, age| age == 50)
+        );
+        assert_eq!(
+            rowids(&mut db, "SELECT name FROM people WHERE name = 'name01234'"),
+            people(&|id, _| (id * 7919) % 2000 == 1234)
+        );
+        // index order, so rows come sorted by age first
+        let mut expected = people(&|id, age| age > 94 && (id * 7919) % 2000 >= 1000);
+        expected.sort_by_key(|id| id % 97);
+        assert_eq!(
+            rowids(
+                &mut db,
+                "SELECT id FROM people WHERE age > 94 AND name >= 'name01000'"
+            ),
+            expected
+        );
+        // table names are compared, never spliced into a query
+        assert_eq!(db.get_index_defs("people")?.len(), 2);
+        assert!(db.get_index_defs("x' OR tbl_name = 'people")?.is_empty());
+        assert!(db.execute("CREATE INDEX people_id ON people(id)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_index() -> Result<()> {
+        // partial.db is t(a INTEGER, b TEXT) with a from 1 to 20, and an index on a WHERE a > 5
+        let path = std::env::temp_dir().join(format!("partial_test_{}.db", std::process::id()));
+        fs::copy("fixtures/partial.db", &path)?;
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert!(db.get_index_defs("t")?.is_empty());
+        let b = |db: &mut DbAccess, query: &str| -> Result<Vec<Vec<DbValue>>> {
+            let table = db.execute(query)?.swap_remove(0);
+            Ok(table.rows.into_iter().map(|r| r.row).collect())
+        };
+        assert_eq!(
+            b(&mut db, "SELECT b FROM t WHERE a = 1")?,
+            [[DbValue::Text("b1".to_owned())]]
+        );
+        assert_eq!(b(&mut db, "SELECT b FROM t WHERE a < 4")?.len(), 3);
+        assert_eq!(db.integrity_check()?, []);
+        assert!(db.execute("INSERT INTO t VALUES (21, 'b21')").is_err());
+        assert!(db.execute("SELECT b FROM t WHERE a = 1 ORDER").is_err());
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_full_scan() -> Result<()> {
+        let mut db = DbAccess::new(File::open("fixtures/index.db")?)?;
+        assert_eq!(
+            rowids(&mut db, "SELECT id FROM people"),
+            (1..=2000).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            rowids(&mut db, "SELECT id FROM people WHERE age * 2 = 100"),
+            (1..=2000).filter(|i| i % 97 == 50).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_limit() -> Result<()> {
+        let mut db = DbAccess::new(File::open("fixtures/index.db")?)?;
+        assert_eq!(
+            rowids(&mut db, "SELECT id FROM people LIMIT 5 OFFSET 10"),
+            (11..=15).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            rowids(&mut db, "SELECT id FROM people WHERE age = 50 LIMIT 2"),
+            [50, 147]
+        );
+        // a negative limit means no limit at all
+        assert_eq!(
+            rowids(&mut db, "SELECT id FROM people LIMIT -1 OFFSET 1995"),
+            (1996..=2000).collect::<Vec<_>>()
+        );
+        let empty = db
+            .run_query("SELECT id, name FROM people LIMIT 0")
+            .swap_remove(0);
+        assert!(empty.rows.is_empty());
+        assert_eq!(empty.columns.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_utf16() -> Result<()> {
+        // words(id INTEGER PRIMARY KEY, word TEXT) indexed on word, a few words with characters
+        // from all over the place followed by 'wörd 0000'..'wörd 0499'
+        for path in ["fixtures/utf16le.db", "fixtures/utf16be.db"] {
+            let mut db = DbAccess::new(File::open(path)?)?;
+            let words = db.run_query("SELECT word FROM words").swap_remove(0).rows;
+            assert_eq!(words.len(), 508);
+            assert_eq!(words[1].row, [DbValue::Text("ärger".to_string())]);
+            assert_eq!(words[507].row, [DbValue::Text("wörd 0499".to_string())]);
+            // through the index
+            assert_eq!(
+                rowids(&mut db, "SELECT id FROM words WHERE word = '日本語'"),
+                [4]
+            );
+            assert_eq!(
+                rowids(&mut db, "SELECT id FROM words WHERE word = '😀 emoji'"),
+                [5]
+            );
+            assert_eq!(
+                rowids(&mut db, "SELECT id FROM words WHERE word = 'wörd 0042'"),
+                [51]
+            );
+            assert_eq!(
+                rowids(&mut db, "SELECT id FROM words WHERE word > 'zebra'"),
+                [2, 4, 5, 6, 7, 8]
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rowid_lookup() -> Result<()> {
+        let mut db = DbAccess::new(File::open("fixtures/index.db")?)?;
+        assert_eq!(
+            rowids(&mut db, "SELECT name FROM people WHERE id = 42"),
+            [42]
+        );
+        assert_eq!(
+            rowids(&mut db, "SELECT name FROM people WHERE id = 4242"),
+            []
+        );
+        assert_eq!(
+            rowids(
+                &mut db,
+                "SELECT name FROM people WHERE rowid BETWEEN 1000 AND 1500"
+            ),
+            (1000..=1500).collect::<Vec<_>>()
+        );
+        // the index on age would work too, but going through the rowid is cheaper
+        assert_eq!(
+            rowids(
+                &mut db,
+                "SELECT name FROM people WHERE age = 3 AND 1900 < id AND oid <= 2100"
+            ),
+            (1901..=2000).filter(|i| i % 97 == 3).collect::<Vec<_>>()
+        );
+
+        // see test_insert_constraints for unique.db
+        let path = std::env::temp_dir().join(format!("rowid_test_{}.db", std::process::id()));
+        fs::copy("fixtures/unique.db", &path)?;
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        db.execute("INSERT INTO users (id, name) VALUES (9223372036854775807, 'last')")?;
+        // the filter would drop the row anyway, the cursor must not get to it in the first place
+        let (root, _) = db.get_table_def("users");
+        let mut cursor = TableCursor::range(
+            &mut db,
+            root as usize,
+            Bound::Excluded(i64::MAX),
+            Bound::Unbounded,
+        );
+        assert!(cursor.next().is_none());
+        assert_eq!(
+            rowids(
+                &mut db,
+                "SELECT id FROM users WHERE id >= 9223372036854775807"
+            ),
+            [i64::MAX]
+        );
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_constraints() -> Result<()> {
+        // users(id INTEGER PRIMARY KEY, email TEXT UNIQUE, name TEXT NOT NULL, team INTEGER,
+        // rank INTEGER) with a unique index on (team, rank DESC), holding
+        // (1, 'ann@example.com', 'ann', 1, 1), (2, 'bob@example.com', 'bob', 1, 2),
+        // (3, NULL, 'carl', 2, 1)
+        let path = std::env::temp_dir().join(format!("insert_test_{}.db", std::process::id()));
+        fs::copy("fixtures/unique.db", &path)?;
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        let failure = |db: &mut DbAccess, sql: &str| match db.execute(sql) {
+            Ok(_) => panic!("{sql} should have failed"),
+            Err(e) => e.to_string(),
+        };
+        assert_eq!(
+            failure(&mut db, "INSERT INTO users VALUES (2, 'x', 'x', 3, 3)"),
+            "UNIQUE constraint failed: users.id"
+        );
+        assert_eq!(
+            failure(
+                &mut db,
+                "INSERT INTO users (email, name) VALUES ('bob@example.com', 'x')"
+            ),
+            "UNIQUE constraint failed: users.email"
+        );
+        assert_eq!(
+            failure(
+                &mut db,
+                "INSERT INTO users (name, team, rank) VALUES ('x', 1, 2)"
+            ),
+            "UNIQUE constraint failed: users.team, users.rank"
+        );
+        assert_eq!(
+            failure(&mut db, "INSERT INTO users (email) VALUES ('x')"),
+            "NOT NULL constraint failed: users.name"
+        );
+        // the second row conflicts with the first one, so neither is written
+        assert_eq!(
+            failure(
+                &mut db,
+                "INSERT INTO users (email, name) VALUES ('dan@example.com', 'dan'), ('dan@example.com', 'eve')"
+            ),
+            "UNIQUE constraint failed: users.email"
+        );
+        assert_eq!(rowids(&mut db, "SELECT id FROM users"), [1, 2, 3]);
+
+        // NULLs never conflict, and numbers given as text take the type of their column
+        db.execute("INSERT INTO users (name, team) VALUES ('dan', 2), ('eve', '2')")?;
+        db.execute("INSERT INTO users VALUES (10, 'fay@example.com', 'fay', 1, 3)")?;
+        assert_eq!(db.integrity_check()?, []);
+        assert_eq!(rowids(&mut db, "SELECT id FROM users"), [1, 2, 3, 4, 5, 10]);
+        let eve = db.execute("SELECT team FROM users WHERE name = 'eve'")?;
+        assert_eq!(eve[0].rows[0].row, [DbValue::Integer(2)]);
+        assert_eq!(
+            rowids(
+                &mut db,
+                "SELECT id FROM users WHERE email = 'fay@example.com'"
+            ),
+            [10]
+        );
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_database() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("new_test_{}.db", std::process::id()));
+        for (page_size, encoding) in [(512, TextEncoding::UTF8), (65536, TextEncoding::UTF16be)] {
+            let mut db = DbAccess::create(&path, page_size, encoding, &OpenOptions::default())?;
+            assert_eq!(db.header.page_size, page_size);
+            assert_eq!(db.header.size_in_pages, 1);
+            assert_eq!(db.number_of_tables()?, 0);
+            assert_eq!(db.integrity_check()?, []);
+            assert!(DbAccess::create(&path, page_size, encoding, &OpenOptions::default()).is_err());
+            db.execute("CREATE TABLE words(word TEXT); INSERT INTO words VALUES ('wörd')")?;
+            drop(db);
+
+            let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+            assert_eq!(db.header.text_encoding, encoding);
+            assert_eq!(db.header.schema_format_number, SchemaFormat::Format4);
+            assert_eq!(db.integrity_check()?, []);
+            assert_eq!(
+                db.execute("SELECT word FROM words")?[0].rows[0].row,
+                [DbValue::Text("wörd".to_owned())]
+            );
+            fs::remove_file(&path)?;
+        }
+        assert!(
+            DbAccess::create(&path, 1000, TextEncoding::UTF8, &OpenOptions::default()).is_err()
+        );
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_table() -> Result<()> {
+        // see test_insert_constraints, unique.db has 512 byte pages so sqlite_schema soon splits
+        let path = std::env::temp_dir().join(format!("create_test_{}.db", std::process::id()));
+        fs::copy("fixtures/unique.db", &path)?;
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        let cookie = db.header.schema_cookie;
+        db.execute(
+            "create  table pets (id INTEGER PRIMARY KEY, name TEXT UNIQUE NOT NULL, tag TEXT);
+            CREATE TABLE notes(body TEXT)",
+        )?;
+        for i in 0..8 {
+            db.execute(&format!(
+                "CREATE TABLE t{i}(a TEXT PRIMARY KEY, b INTEGER UNIQUE, c TEXT)"
+            ))?;
+        }
+        assert!(db.execute("CREATE TABLE NOTES(body TEXT)").is_err());
+        assert!(db.execute("CREATE TABLE sqlite_stat1(tbl TEXT)").is_err());
+        db.execute("INSERT INTO pets (name) VALUES ('rex'); INSERT INTO t7 VALUES ('a', 1, 'c')")?;
+        drop(db);
+
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(db.integrity_check()?, []);
+        assert_eq!(db.header.schema_cookie, cookie + 10);
+        let schema = db.execute("SELECT type, name, tbl_name, sql FROM sqlite_schema")?;
+        let text = |s: &str| DbValue::Text(s.to_owned());
+        assert_eq!(schema[0].rows.len(), 3 + 2 + 1 + 8 * 3);
+        assert_eq!(
+            schema[0].rows[3].row,
+            [
+                text("table"),
+                text("pets"),
+                text("pets"),
+                text("CREATE TABLE pets (id INTEGER PRIMARY KEY, name TEXT UNIQUE NOT NULL, tag TEXT)")
+            ]
+        );
+        assert_eq!(
+            schema[0].rows[4].row,
+            [
+                text("index"),
+                text("sqlite_autoindex_pets_1"),
+                text("pets"),
+                DbValue::Null
+            ]
+        );
+        assert_eq!(
+            rowids(&mut db, "SELECT id FROM pets WHERE name = 'rex'"),
+            [1]
+        );
+        let row = db.execute("SELECT c FROM t7 WHERE b = 1")?;
+        assert_eq!(row[0].rows[0].row, [text("c")]);
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_table_auto_vacuum() -> Result<()> {
+        // autovacuum.db has 512 byte pages, notes rows with overflow pages and 29 free pages, so
+        // the new roots take over pages used by the notes table and the freelist
+        let path = std::env::temp_dir().join(format!("autovacuum_test_{}.db", std::process::id()));
+        fs::copy("fixtures/autovacuum.db", &path)?;
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(db.integrity_check()?, []);
+        let notes = db.execute("SELECT * FROM notes")?.swap_remove(0).rows;
+        let largest_root = db.header.largest_root_btree_page;
+        db.execute("CREATE TABLE pets (id INTEGER PRIMARY KEY, name TEXT UNIQUE, tag TEXT)")?;
+        for i in 0..40 {
+            db.execute(&format!(
+                "INSERT INTO pets (name, tag) VALUES ('pet{i}', 'tag{i}')"
+            ))?;
+        }
+        drop(db);
+
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(db.integrity_check()?, []);
+        assert_eq!(db.header.largest_root_btree_page, largest_root + 2);
+        let rows = db.execute("SELECT * FROM notes")?.swap_remove(0).rows;
+        assert_eq!(
+            rows.iter().map(|r| &r.row).collect_vec(),
+            notes.iter().map(|r| &r.row).collect_vec()
+        );
+        assert_eq!(
+            rowids(&mut db, "SELECT id FROM pets WHERE name = 'pet7'"),
+            [8]
+        );
+        drop(db);
+
+        // the first entry on the pointer map, page 2, is the one of the notes root page
+        let mut bytes = fs::read(&path)?;
+        bytes[512] = 2;
+        fs::write(&path, bytes)?;
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(
+            db.integrity_check()?,
+            [Problem::PointerMap {
+                page: 3,
+                expected: PtrmapEntry::Root,
+                found: Some(PtrmapEntry::Free)
+            }]
+        );
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_vacuum() -> Result<()> {
+        // deleted.db is t(id INTEGER PRIMARY KEY, val TEXT) with 43 pages on its freelist, the
+        // page counts are the ones sqlite's own VACUUM comes to
+        let rows = |db: &mut DbAccess, query: &str| -> Result<Vec<Vec<DbValue>>> {
+            let table = db.execute(query)?.swap_remove(0);
+            Ok(table.rows.into_iter().map(|r| r.row).collect())
+        };
+        let path = std::env::temp_dir().join(format!("vacuum_test_{}.db", std::process::id()));
+        fs::copy("fixtures/deleted.db", &path)?;
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        let before = rows(&mut db, "SELECT * FROM t")?;
+        let cookie = db.header.schema_cookie;
+        db.execute("VACUUM")?;
+        assert_eq!(db.header.total_freelist, 0);
+        assert_eq!(db.header.size_in_pages, 56);
+        assert_eq!(fs::metadata(&path)?.len(), 56 * 512);
+        assert_eq!(rows(&mut db, "SELECT * FROM t")?, before);
+        drop(db);
+
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(db.integrity_check()?, []);
+        assert_eq!(db.header.schema_cookie, cookie + 1);
+        assert_eq!(rows(&mut db, "SELECT * FROM t")?, before);
+        assert!(db.execute("BEGIN; VACUUM").is_err());
+        db.execute("ROLLBACK")?;
+        fs::remove_file(&path)?;
+
+        // rows of WITHOUT ROWID tables and indices live in index b-trees
+        let mut db = DbAccess::new(File::open("fixtures/norowid.db")?)?;
+        db.execute(&format!("VACUUM INTO '{}'", path.display()))?;
+        assert!(db
+            .execute(&format!("VACUUM INTO '{}'", path.display()))
+            .is_err());
+        let mut copy = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(copy.integrity_check()?, []);
+        assert_eq!(copy.header.size_in_pages, 76);
+        assert_eq!(copy.read_schema()?.len(), db.read_schema()?.len());
+        for query in [
+            "SELECT * FROM pairs",
+            "SELECT n FROM pairs WHERE note = 'alpha number 42'",
+            "SELECT * FROM codes",
+        ] {
+            assert_eq!(rows(&mut copy, query)?, rows(&mut db, query)?);
+        }
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_without_rowid() -> Result<()> {
+        // pairs(word TEXT, n INTEGER, note TEXT UNIQUE, PRIMARY KEY (n, word)) holds 'alpha' and
+        // 'beta' for n in 1..=300, codes(name TEXT, code INTEGER PRIMARY KEY) c3, c1 and c2
+        let path = std::env::temp_dir().join(format!("norowid_test_{}.db", std::process::id()));
+        fs::copy("fixtures/norowid.db", &path)?;
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(db.integrity_check()?, []);
+        let text = |s: &str| DbValue::Text(s.to_owned());
+        let rows = |db: &mut DbAccess, query: &str| -> Vec<Vec<DbValue>> {
+            db.run_query(query)
+                .swap_remove(0)
+                .rows
+                .into_iter()
+                .map(|r| r.row)
+                .collect()
+        };
+        assert_eq!(rows(&mut db, "SELECT * FROM pairs").len(), 600);
+        assert_eq!(
+            rows(&mut db, "SELECT * FROM pairs WHERE n = 150"),
+            [
+                [
+                    text("alpha"),
+                    DbValue::Integer(150),
+                    text("alpha number 150")
+                ],
+                [text("beta"), DbValue::Integer(150), text("beta number 150")]
+            ]
+        );
+        assert_eq!(
+            rows(
+                &mut db,
+                "SELECT word FROM pairs WHERE n > 298 AND word = 'beta'"
+            ),
+            [[text("beta")], [text("beta")]]
+        );
+        assert_eq!(
+            rows(
+                &mut db,
+                "SELECT n FROM pairs WHERE note = 'alpha number 42'"
+            ),
+            [[DbValue::Integer(42)]]
+        );
+        assert_eq!(
+            rows(&mut db, "SELECT code, name FROM codes WHERE code >= 2"),
+            [
+                [DbValue::Integer(2), text("c2")],
+                [DbValue::Integer(3), text("c3")]
+            ]
+        );
+        assert!(db.execute("INSERT INTO codes VALUES ('c4', 4)").is_err());
+        assert!(db
+            .execute("CREATE TABLE nokey (a TEXT) WITHOUT ROWID")
+            .is_err());
+        db.execute(
+            "CREATE TABLE tags (tag TEXT UNIQUE, id INTEGER, label TEXT, PRIMARY KEY (id)) WITHOUT ROWID",
+        )?;
+        drop(db);
+
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(db.integrity_check()?, []);
+        // the key is numbered after the UNIQUE column, but only the latter has an index
+        assert_eq!(
+            rows(
+                &mut db,
+                "SELECT name FROM sqlite_schema WHERE tbl_name = 'tags'"
+            ),
+            [[text("tags")], [text("sqlite_autoindex_tags_1")]]
+        );
+        assert!(rows(&mut db, "SELECT * FROM tags").is_empty());
+        fs::remove_file(&path)?;
+        Ok(())
     }
 }
@@ -1,12 +1,48 @@
+use super::header::BTreePageType;
+
 pub(crate) trait ReadCell {
     type Error;
     fn read_cell<T>(&self, index: usize) -> Result<T, Self::Error>;
 }
 
-pub(crate) struct BTreeTableLeaf {
-    payload_size: usize,
-    rowid: u64,
-    /// the initial portion of the payload that does not spill to overflow pages
-    non_spilling: usize,
-    overflow_page: Option<usize>,
+/// Thresholds deciding how much of a cell payload lives on the btree page itself and how much
+/// spills onto the overflow chain, see section 1.6 of the file format docs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PayloadLimits {
+    pub(crate) usable_size: usize,
+    max_local: usize,
+    min_local: usize,
+    max_leaf: usize,
+    min_leaf: usize,
+}
+
+impl PayloadLimits {
+    pub(crate) fn new(usable_size: usize, max_frac: u8, min_frac: u8, leaf_frac: u8) -> Self {
+        let fraction = |f: u8| (usable_size - 12) * f as usize / 255 - 23;
+        Self {
+            usable_size,
+            max_local: fraction(max_frac),
+            min_local: fraction(min_frac),
+            max_leaf: usable_size - 35,
+            min_leaf: fraction(leaf_frac),
+        }
+    }
+
+    /// Number of payload bytes stored locally for a cell of the given page type, the rest (if
+    /// any) is found on overflow pages
+    pub(crate) fn local_size(&self, typ: &BTreePageType, payload_size: usize) -> usize {
+        let (max, min) = match typ {
+            BTreePageType::LeafTable => (self.max_leaf, self.min_leaf),
+            _ => (self.max_local, self.min_local),
+        };
+        if payload_size <= max {
+            return payload_size;
+        }
+        let surplus = min + (payload_size - min) % (self.usable_size - 4);
+        if surplus <= max {
+            surplus
+        } else {
+            min
+        }
+    }
 }
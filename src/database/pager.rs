@@ -0,0 +1,384 @@
+use std::{
+    ffi::{c_int, c_long, c_void},
+    fs::File,
+    io,
+    os::unix::{fs::FileExt, io::AsRawFd},
+    ptr,
+    rc::Rc,
+    slice,
+};
+
+use anyhow::{bail, Result};
+
+use super::{
+    page::PayloadLimits,
+    tree::{PageConsumer, PageSupplier, SharedPage},
+};
+
+/// Reads pages straight from the database file with a pread for every access, all of them end
+/// up in the same buffer
+pub(crate) struct FilePager {
+    // NOTE: Non so se sia meglio magari usare solo un Read, però tanto io su un file devo scrivere
+    dbfile: File,
+    page: Vec<u8>,
+    /// Holds the start offset of a specific page, only
+    /// useful for the first page start
+    start_offset: usize,
+    limits: PayloadLimits,
+}
+
+impl FilePager {
+    pub(crate) fn new(dbfile: File, page_size: usize, limits: PayloadLimits) -> Self {
+        Self {
+            dbfile,
+            page: vec![0; page_size],
+            start_offset: 0,
+            limits,
+        }
+    }
+}
+
+impl PageSupplier for FilePager {
+    type Error = anyhow::Error;
+
+    /// Reads required page into memory for later analysis
+    fn move_to_page(&mut self, page_idx: usize) -> Result<()> {
+        let offset = (page_idx as u64 - 1) * self.page.len() as u64;
+        self.dbfile.read_exact_at(&mut self.page, offset)?;
+        self.start_offset = if page_idx == 1 { 100 } else { 0 };
+        Ok(())
+    }
+
+    fn read_page(&mut self, page_idx: usize) -> Result<&[u8]> {
+        self.move_to_page(page_idx)?;
+        Ok(&self.page[self.start_offset..])
+    }
+
+    fn page(&self) -> &[u8] {
+        &self.page
+    }
+
+    fn payload_limits(&self) -> PayloadLimits {
+        self.limits
+    }
+
+    fn db_size(&self) -> Option<usize> {
+        let len = self.dbfile.metadata().ok()?.len();
+        Some(len as usize / self.page.len())
+    }
+}
+
+impl PageConsumer for FilePager {
+    type Error = anyhow::Error;
+
+    fn write_page(&mut self, page_idx: usize, page: &[u8]) -> Result<()> {
+        let offset = (page_idx as u64 - 1) * self.page.len() as u64;
+        self.dbfile.write_all_at(page, offset)?;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(self.dbfile.sync_all()?)
+    }
+
+    fn truncate(&mut self, pages: usize) -> Result<()> {
+        Ok(self.dbfile.set_len((pages * self.page.len()) as u64)?)
+    }
+}
+
+// NOTE: these would come from the libc crate, but we are stuck with the dependencies in
+// Cargo.toml. off_t is a long on the 64 bit targets and on 32 bit linux alike, and both flags have
+// the same value on linux, macOS and the BSDs
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: c_long,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+const PROT_READ: c_int = 1;
+const MAP_SHARED: c_int = 1;
+const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+/// Read only mapping of a whole file, unmapped on drop
+struct Mapping {
+    addr: *mut c_void,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(file: &File) -> io::Result<Self> {
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            // mmap refuses empty mappings anyway
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        // SAFETY: we ask for a fresh read only mapping, nothing else is aliasing it
+        let addr = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ,
+                MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if addr == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { addr, len })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // SAFETY: the mapping is valid for `len` bytes until we unmap it in drop
+        unsafe { slice::from_raw_parts(self.addr as *const u8, self.len) }
+    }
+}
+
+impl AsRef<[u8]> for Mapping {
+    fn as_ref(&self) -> &[u8] {
+        self.bytes()
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: addr and len come straight from a successful mmap
+        unsafe {
+            munmap(self.addr, self.len);
+        }
+    }
+}
+
+/// Maps the whole database file in memory and hands out slices of the mapping, so reading a page
+/// costs no copy at all
+/// NOTE: writes go through the file, the mapping is shared so it sees them, and it is taken again
+/// whenever the file grows past it. Shared pages keep the mapping they were taken from alive, so
+/// they see those writes as well.
+/// The file must not be modified by anyone else while it is mapped: their writes would change
+/// pages under a query reading them, and touching a page past the end of a file they shrank kills
+/// the process with SIGBUS. Our own truncation is refused while any page is still held
+pub(crate) struct MmapPager {
+    dbfile: File,
+    mapping: Rc<Mapping>,
+    page_size: usize,
+    current: usize,
+    limits: PayloadLimits,
+}
+
+impl MmapPager {
+    pub(crate) fn new(dbfile: &File, page_size: usize, limits: PayloadLimits) -> io::Result<Self> {
+        Ok(Self {
+            dbfile: dbfile.try_clone()?,
+            mapping: Rc::new(Mapping::new(dbfile)?),
+            page_size,
+            current: 1,
+            limits,
+        })
+    }
+
+    fn page_bytes(&self, page_idx: usize) -> Option<&[u8]> {
+        let start = page_idx.checked_sub(1)? * self.page_size;
+        self.mapping.bytes().get(start..start + self.page_size)
+    }
+}
+
+impl PageSupplier for MmapPager {
+    type Error = anyhow::Error;
+
+    fn move_to_page(&mut self, page_idx: usize) -> Result<()> {
+        if self.page_bytes(page_idx).is_none() {
+            bail!("page {page_idx} is past the end of the mapped file");
+        }
+        self.current = page_idx;
+        Ok(())
+    }
+
+    fn read_page(&mut self, page_idx: usize) -> Result<&[u8]> {
+        self.move_to_page(page_idx)?;
+        let offset = if page_idx == 1 { 100 } else { 0 };
+        Ok(&self.page()[offset..])
+    }
+
+    fn page(&self) -> &[u8] {
+        self.page_bytes(self.current).unwrap_or(&[])
+    }
+
+    fn payload_limits(&self) -> PayloadLimits {
+        self.limits
+    }
+
+    /// A view of the mapping rather than a copy
+    fn shared_page(&mut self, page_idx: usize) -> Result<SharedPage> {
+        self.move_to_page(page_idx)?;
+        let start = (page_idx - 1) * self.page_size;
+        Ok(SharedPage::View(
+            self.mapping.clone(),
+            start..start + self.page_size,
+        ))
+    }
+
+    fn db_size(&self) -> Option<usize> {
+        let len = self.dbfile.metadata().ok()?.len();
+        Some(len as usize / self.page_size)
+    }
+}
+
+impl PageConsumer for MmapPager {
+    type Error = anyhow::Error;
+
+    fn write_page(&mut self, page_idx: usize, page: &[u8]) -> Result<()> {
+        let offset = (page_idx - 1) * self.page_size;
+        self.dbfile.write_all_at(page, offset as u64)?;
+        if offset + self.page_size > self.mapping.len {
+            self.mapping = Rc::new(Mapping::new(&self.dbfile)?);
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(self.dbfile.sync_all()?)
+    }
+
+    fn truncate(&mut self, pages: usize) -> Result<()> {
+        // touching the mapping past the new end of the file would be fatal
+        if Rc::strong_count(&self.mapping) > 1 {
+            bail!("cannot shrink the database file while pages of its mapping are in use");
+        }
+        self.dbfile.set_len((pages * self.page_size) as u64)?;
+        self.mapping = Rc::new(Mapping::new(&self.dbfile)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::database::header::Header;
+
+    fn pagers(path: &str) -> Result<(FilePager, MmapPager)> {
+        let header = Header::new(File::open(path)?)?;
+        let page_size = header.page_size as usize;
+        let file = File::open(path)?;
+        let mmap = MmapPager::new(&file, page_size, header.payload_limits())?;
+        Ok((
+            FilePager::new(file, page_size, header.payload_limits()),
+            mmap,
+        ))
+    }
+
+    #[test]
+    fn test_mmap_pages() -> Result<()> {
+        let (mut file, mut mmap) = pagers("fixtures/index.db")?;
+        let pages = File::open("fixtures/index.db")?.metadata()?.len() as usize / 512;
+        for idx in 1..=pages {
+            assert_eq!(file.read_page(idx)?, mmap.read_page(idx)?);
+            assert_eq!(file.page(), mmap.page());
+            // straight out of the mapping
+            let shared = mmap.shared_page(idx)?;
+            assert_eq!(&shared[..], file.page());
+            assert_eq!(shared.as_ptr(), mmap.page().as_ptr());
+        }
+        assert!(mmap.read_page(pages + 1).is_err());
+        assert!(mmap.read_page(0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mmap_query() -> Result<()> {
+        use crate::database::{DbAccess, OpenOptions};
+        let options = OpenOptions {
+            mmap: true,
+            ..Default::default()
+        };
+        let query = "SELECT name FROM people WHERE age = 3 OR id > 1990";
+        let mut mapped = DbAccess::open(File::open("fixtures/index.db")?, &options)?;
+        let mut db = DbAccess::new(File::open("fixtures/index.db")?)?;
+        assert_eq!(
+            format!("{:?}", mapped.run_query(query)),
+            format!("{:?}", db.run_query(query))
+        );
+        // no cache in the way
+        assert_eq!(mapped.cache_stats(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mmap_truncate() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("mmap_truncate_{}.db", std::process::id()));
+        std::fs::copy("fixtures/index.db", &path)?;
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)?;
+        let mut mmap = MmapPager::new(&file, 512, Header::new(&file)?.payload_limits())?;
+        let pages = mmap.db_size().unwrap();
+        let shared = mmap.shared_page(pages)?;
+        // the view would point past the end of the file
+        assert!(mmap.truncate(pages - 1).is_err());
+        assert_eq!(mmap.db_size(), Some(pages));
+        drop(shared);
+        mmap.truncate(pages - 1)?;
+        assert_eq!(mmap.db_size(), Some(pages - 1));
+        assert!(mmap.read_page(pages).is_err());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    /// Run with `cargo test --release bench_ -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_pread_vs_mmap() -> Result<()> {
+        use crate::database::{DbAccess, OpenOptions};
+        fn bench(name: &str, pager: &mut impl PageSupplier<Error = anyhow::Error>, pages: usize) {
+            const ROUNDS: usize = 200;
+            let start = Instant::now();
+            let mut checksum = 0u64;
+            for _ in 0..ROUNDS {
+                for idx in 1..=pages {
+                    let page = pager.read_page(idx).unwrap();
+                    checksum += page[page.len() / 2] as u64;
+                }
+            }
+            let elapsed = start.elapsed();
+            println!(
+                "{name}: {:?} per page ({} reads, checksum {checksum})",
+                elapsed / (ROUNDS * pages) as u32,
+                ROUNDS * pages,
+            );
+        }
+        let (mut file, mut mmap) = pagers("fixtures/index.db")?;
+        let pages = File::open("fixtures/index.db")?.metadata()?.len() as usize / 512;
+        bench("read_exact_at", &mut file, pages);
+        bench("mmap", &mut mmap, pages);
+
+        // whole queries, where the cursors take their pages through shared_page
+        const QUERIES: usize = 200;
+        let query = "SELECT name FROM people WHERE age > 10";
+        for (name, mmap) in [("cached pread", false), ("mmap", true)] {
+            let options = OpenOptions {
+                mmap,
+                ..Default::default()
+            };
+            let mut db = DbAccess::open(File::open("fixtures/index.db")?, &options)?;
+            let start = Instant::now();
+            let mut rows = 0;
+            for _ in 0..QUERIES {
+                rows += db.execute(query)?.swap_remove(0).rows.len();
+            }
+            println!(
+                "{name}: {:?} per query ({rows} rows)",
+                start.elapsed() / QUERIES as u32
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt::Display;
+
+/// What a page of an auto-vacuum database is used for, as recorded in its pointer map (section
+/// 1.7 of the file format docs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PtrmapEntry {
+    /// root page of a b-tree
+    Root,
+    /// page on the freelist
+    Free,
+    /// first overflow page of a cell of the given b-tree page
+    FirstOverflow(u32),
+    /// overflow page that follows the given one in its chain
+    Overflow(u32),
+    /// b-tree page whose parent is the given one
+    Child(u32),
+}
+
+impl PtrmapEntry {
+    fn read(b: &[u8]) -> Option<Self> {
+        let page = u32::from_be_bytes([b[1], b[2], b[3], b[4]]);
+        Some(match b[0] {
+            1 => PtrmapEntry::Root,
+            2 => PtrmapEntry::Free,
+            3 => PtrmapEntry::FirstOverflow(page),
+            4 => PtrmapEntry::Overflow(page),
+            5 => PtrmapEntry::Child(page),
+            _ => return None,
+        })
+    }
+
+    fn bytes(self) -> [u8; 5] {
+        let (typ, page) = match self {
+            PtrmapEntry::Root => (1, 0),
+            PtrmapEntry::Free => (2, 0),
+            PtrmapEntry::FirstOverflow(page) => (3, page),
+            PtrmapEntry::Overflow(page) => (4, page),
+            PtrmapEntry::Child(page) => (5, page),
+        };
+        let [a, b, c, d] = page.to_be_bytes();
+        [typ, a, b, c, d]
+    }
+}
+
+impl Display for PtrmapEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PtrmapEntry::Root => write!(f, "root page"),
+            PtrmapEntry::Free => write!(f, "free page"),
+            PtrmapEntry::FirstOverflow(page) => write!(f, "first overflow page of page {page}"),
+            PtrmapEntry::Overflow(page) => write!(f, "overflow page after page {page}"),
+            PtrmapEntry::Child(page) => write!(f, "child of page {page}"),
+        }
+    }
+}
+
+/// Where the pointer map of an auto-vacuum database is: its first page is page 2, and each of
+/// its pages holds the entries of the pages that follow it, up to the next one
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PointerMap {
+    /// number of entries on a pointer map page
+    entries: usize,
+}
+
+impl PointerMap {
+    pub(crate) fn new(usable_size: usize) -> Self {
+        Self {
+            entries: usable_size / 5,
+        }
+    }
+
+    /// Whether `idx` is one of the pages of the pointer map
+    pub(crate) fn is_map_page(&self, idx: usize) -> bool {
+        idx >= 2 && (idx - 2).is_multiple_of(self.entries + 1)
+    }
+
+    /// The pages of the pointer map in a database of `size` pages
+    pub(crate) fn map_pages(&self, size: usize) -> impl Iterator<Item = usize> {
+        (2..=size).step_by(self.entries + 1)
+    }
+
+    /// The pages whose entries are on the pointer map page `map_page`, in a database of `size`
+    /// pages
+    pub(crate) fn covered(&self, map_page: usize, size: usize) -> impl Iterator<Item = usize> {
+        map_page + 1..=(map_page + self.entries).min(size)
+    }
+
+    /// Pointer map page holding the entry of page `idx`, and the offset of the entry on it
+    pub(crate) fn locate(&self, idx: usize) -> (usize, usize) {
+        let map_page = idx - (idx - 2) % (self.entries + 1);
+        (map_page, 5 * (idx - map_page - 1))
+    }
+
+    /// Entry of page `idx` on `map_page`, the pointer map page it belongs to
+    pub(crate) fn read(&self, map_page: &[u8], idx: usize) -> Option<PtrmapEntry> {
+        let (_, offset) = self.locate(idx);
+        PtrmapEntry::read(map_page.get(offset..offset + 5)?)
+    }
+
+    /// Sets the entry of page `idx` on `map_page`, the pointer map page it belongs to
+    pub(crate) fn write(&self, map_page: &mut [u8], idx: usize, entry: PtrmapEntry) {
+        let (_, offset) = self.locate(idx);
+        map_page[offset..offset + 5].copy_from_slice(&entry.bytes());
+    }
+}
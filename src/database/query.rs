@@ -1,14 +1,16 @@
-use std::{collections::HashMap, slice::SliceIndex, thread::current};
-
-use itertools::Itertools;
+use std::{
+    cmp::Ordering,
+    ops::{Bound, ControlFlow},
+};
 
 use crate::sql::syntax::{
-    AstPrinter, ColType, ColumnConstraint, ColumnDefinition, DbValue, Expr, Visit,
+    ColType, ColumnConstraint, ColumnDefinition, DbValue, Expr, FunctionArg, Operator,
 };
 
-use super::expression::{precompile_expr, Precompile, RowValue, RunnableExpr};
+use super::expression::{Precompile, RowValue, RunnableExpr};
+use super::tree::compare_values;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct Column {
     col: ColumnDefinition,
     active: bool,
@@ -37,16 +39,7 @@ impl Row {
     }
 }
 
-// PERF: this seems extremely inefficient but we'll fiddle with it later
-
-pub(crate) trait QueryOperation {
-    fn apply(&mut self, t: Table) -> Table;
-}
 impl Table {
-    pub(crate) fn apply<T: QueryOperation>(self, mut q: T) -> Self {
-        q.apply(self)
-    }
-
     pub(crate) fn new(columns: &[ColumnDefinition], rows: Vec<Row>) -> Self {
         Self {
             columns: columns
@@ -63,6 +56,7 @@ impl Table {
 
 pub(crate) struct QueryFilter {
     expr: Box<RunnableExpr>,
+    columns: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -74,59 +68,94 @@ impl<'a> RowValue for RowEntry<'a> {
     fn column(&self, name: &str) -> &DbValue {
         for col in self.cols {
             if col.name == name {
-                if col.typ == ColType::INTEGER
-                    && col
-                        .constraint
-                        .iter()
-                        .any(|c| matches!(c, ColumnConstraint::Pk { .. }))
-                {
+                if is_rowid_alias(col) {
                     return &self.row.id;
                 } else {
                     return &self.row.row[col.position];
                 }
             }
         }
+        if ROWID_NAMES.contains(&name) {
+            return &self.row.id;
+        }
         &DbValue::Null
     }
 }
 
+/// Names that refer to the rowid unless a column is declared with the same name
+pub(crate) const ROWID_NAMES: [&str; 3] = ["rowid", "oid", "_rowid_"];
+
+/// An INTEGER PRIMARY KEY column is just another name for the rowid
+pub(crate) fn is_rowid_alias(col: &ColumnDefinition) -> bool {
+    col.typ == ColType::INTEGER
+        && col
+            .constraint
+            .iter()
+            .any(|c| matches!(c, ColumnConstraint::Pk { .. }))
+}
+
 impl QueryFilter {
     pub(crate) fn new(expr: Expr) -> Self {
+        let mut columns = Vec::new();
+        referenced_columns(&expr, &mut columns);
         Self {
             expr: expr.precompile(),
+            columns,
         }
     }
+
+    /// Names of the columns the filter looks at, the others need not be decoded
+    pub(crate) fn columns(&self) -> &[String] {
+        &self.columns
+    }
 }
-impl QueryOperation for QueryFilter {
-    fn apply(&mut self, mut t: Table) -> Table {
-        let cols: Vec<&ColumnDefinition> = t.columns.iter().map(|c| &c.col).collect();
-        t.rows = t
-            .rows
-            .into_iter()
-            .filter(|row| {
-                let access = RowEntry {
-                    row: &row,
-                    cols: &cols,
-                };
-                if let DbValue::Bool(b) = (self.expr)(&access) {
-                    b
-                } else {
-                    false
-                }
-            })
-            .collect();
-        t
+
+/// Adds the names of the columns `expr` reads to `columns`
+fn referenced_columns(expr: &Expr, columns: &mut Vec<String>) {
+    match expr {
+        Expr::Identifier { value } => {
+            if !columns.contains(value) {
+                columns.push(value.clone())
+            }
+        }
+        Expr::Literal { .. }
+        | Expr::Function {
+            args: FunctionArg::Star,
+            ..
+        } => {}
+        Expr::Binary { left, right, .. } => {
+            referenced_columns(left, columns);
+            referenced_columns(right, columns);
+        }
+        Expr::Unary { expr, .. } | Expr::Grouping { expr } => referenced_columns(expr, columns),
+        Expr::Function {
+            args: FunctionArg::Args(args),
+            ..
+        } => args.iter().for_each(|arg| referenced_columns(arg, columns)),
+    }
+}
+impl QueryFilter {
+    /// Whether `row`, whose columns are `cols`, passes the filter
+    pub(crate) fn matches(&self, cols: &[&ColumnDefinition], row: &Row) -> bool {
+        let access = RowEntry { row, cols };
+        matches!((self.expr)(&access), DbValue::Bool(true))
     }
 }
 
 pub(crate) struct QuerySelect {
     col_extractors: Vec<Box<RunnableExpr>>,
     col_names: Vec<String>,
+    columns: Vec<String>,
 }
 
 impl QuerySelect {
     pub(crate) fn new(col_extractors: Vec<Expr>) -> Self {
+        let mut columns = Vec::new();
+        for e in &col_extractors {
+            referenced_columns(e, &mut columns);
+        }
         Self {
+            columns,
             col_names: col_extractors
                 .iter()
                 .map(|e| match e {
@@ -141,33 +170,29 @@ impl QuerySelect {
             col_extractors: col_extractors.into_iter().map(|e| e.precompile()).collect(),
         }
     }
-}
 
-impl QueryOperation for QuerySelect {
-    fn apply(&mut self, mut t: Table) -> Table {
-        let cols: Vec<&ColumnDefinition> = t.columns.iter().map(|c| &c.col).collect();
-        let rows: Vec<Row> = t
-            .rows
-            .into_iter()
-            .map(|row| {
-                let access = RowEntry {
-                    row: &row,
-                    cols: &cols,
-                };
-                Row {
-                    id: row.id.clone(),
-                    row: self
-                        .col_extractors
-                        .iter()
-                        .map(|ext| (ext)(&access))
-                        .collect(),
-                }
-            })
-            .collect();
+    /// Names of the columns the selected expressions are computed from
+    pub(crate) fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// The selected values out of `row`, whose columns are `cols`
+    pub(crate) fn project(&self, cols: &[&ColumnDefinition], row: &Row) -> Row {
+        let access = RowEntry { row, cols };
+        Row {
+            id: row.id.clone(),
+            row: self
+                .col_extractors
+                .iter()
+                .map(|ext| (ext)(&access))
+                .collect(),
+        }
+    }
 
+    /// Columns of the rows returned by [`QuerySelect::project`]
+    pub(crate) fn output_columns(&self) -> Vec<Column> {
         // NOTE: Sta roba non mi piace per nulla
-        t.columns = self
-            .col_names
+        self.col_names
             .iter()
             .enumerate()
             .map(|(i, c)| ColumnDefinition {
@@ -181,10 +206,112 @@ impl QueryOperation for QuerySelect {
                 col: c,
                 active: true,
             })
-            .collect();
-        eprintln!("{:?}", t.columns);
-        t.rows = rows;
-        t
+            .collect()
+    }
+}
+
+/// Range of values a single column is restricted to by a WHERE clause, only comparisons between
+/// the column and a literal joined by AND are taken into account
+#[derive(Debug, PartialEq)]
+pub(crate) struct ColumnRange {
+    pub start: Bound<DbValue>,
+    pub end: Bound<DbValue>,
+}
+
+impl ColumnRange {
+    pub(crate) fn from_filter(filter: &Expr, column: &str) -> Option<Self> {
+        match filter {
+            Expr::Grouping { expr } => Self::from_filter(expr, column),
+            Expr::Binary {
+                left,
+                right,
+                operator: Operator::And,
+            } => match (
+                Self::from_filter(left, column),
+                Self::from_filter(right, column),
+            ) {
+                (Some(l), Some(r)) => Some(l.intersect(r)),
+                (l, r) => l.or(r),
+            },
+            Expr::Binary {
+                left,
+                right,
+                operator,
+            } => {
+                let (value, operator) = match (left.as_ref(), right.as_ref()) {
+                    (Expr::Identifier { value: id }, Expr::Literal { value }) if id == column => {
+                        (value, operator.clone())
+                    }
+                    (Expr::Literal { value }, Expr::Identifier { value: id }) if id == column => {
+                        (value, operator.flip()?)
+                    }
+                    _ => return None,
+                };
+                // comparing with NULL never matches, better leave it to the filter
+                if matches!(value, DbValue::Null | DbValue::Bool(_)) {
+                    return None;
+                }
+                let value = value.clone();
+                let (start, end) = match operator {
+                    Operator::Equals => (Bound::Included(value.clone()), Bound::Included(value)),
+                    Operator::Less => (Bound::Unbounded, Bound::Excluded(value)),
+                    Operator::LessEq => (Bound::Unbounded, Bound::Included(value)),
+                    Operator::Greater => (Bound::Excluded(value), Bound::Unbounded),
+                    Operator::GreaterEq => (Bound::Included(value), Bound::Unbounded),
+                    _ => return None,
+                };
+                Some(Self { start, end })
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn intersect(self, other: Self) -> Self {
+        // the tighter bound wins, on equal values the excluded one is tighter
+        fn pick(a: Bound<DbValue>, b: Bound<DbValue>, tighter: Ordering) -> Bound<DbValue> {
+            match (&a, &b) {
+                (Bound::Unbounded, _) => b,
+                (_, Bound::Unbounded) => a,
+                (
+                    Bound::Included(x) | Bound::Excluded(x),
+                    Bound::Included(y) | Bound::Excluded(y),
+                ) => match compare_values(x, y) {
+                    Ordering::Equal if matches!(a, Bound::Excluded(_)) => a,
+                    Ordering::Equal => b,
+                    o if o == tighter => a,
+                    _ => b,
+                },
+            }
+        }
+        Self {
+            start: pick(self.start, other.start, Ordering::Greater),
+            end: pick(self.end, other.end, Ordering::Less),
+        }
+    }
+}
+
+/// Receives the results of the queries as they are computed, one row at a time
+pub(crate) trait RowSink {
+    /// A query starts, its rows will have these columns
+    fn columns(&mut self, columns: &[Column]);
+    /// Next row of the current query, breaking stops the query without reading any further
+    fn row(&mut self, row: Row) -> ControlFlow<()>;
+}
+
+/// Collects every result in memory, one table per query
+impl RowSink for Vec<Table> {
+    fn columns(&mut self, columns: &[Column]) {
+        self.push(Table {
+            columns: columns.to_vec(),
+            rows: Vec::new(),
+        });
+    }
+
+    fn row(&mut self, row: Row) -> ControlFlow<()> {
+        if let Some(table) = self.last_mut() {
+            table.rows.push(row);
+        }
+        ControlFlow::Continue(())
     }
 }
 
@@ -200,10 +327,69 @@ impl QueryOperation for QuerySelect {
 // in più sarà prerogativa di chi implementa cursor quella di fare la magia delle colonne eccetera
 // non del tutto vero perché la magia delle colonne serve ovunque
 pub(crate) trait RowCursor {
-    // NOTE: we do not support NOROWID tables
+    // NOTE: rows of WITHOUT ROWID tables come with a NULL rowid
     fn rowid(&self) -> &DbValue;
-    fn column(&self, colpos: usize) -> Option<&DbValue>;
+    /// Decodes column `colpos` of the current row
+    fn column(&mut self, colpos: usize) -> Option<DbValue>;
     fn next(&mut self) -> Option<()>;
 }
 
 struct QueryContext {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sql::parse_sql;
+    use crate::sql::syntax::{SelectStatement, Statement};
+
+    fn range(filter: &str, column: &str) -> Option<ColumnRange> {
+        match parse_sql(&format!("SELECT * FROM t WHERE {filter}"))
+            .unwrap()
+            .pop()
+        {
+            Some(Statement::Select(SelectStatement {
+                filter: Some(f), ..
+            })) => ColumnRange::from_filter(&f, column),
+            _ => panic!("could not parse {filter}"),
+        }
+    }
+
+    #[test]
+    fn test_column_range() {
+        let (i, e) = (
+            |v| Bound::Included(DbValue::Integer(v)),
+            |v| Bound::Excluded(DbValue::Integer(v)),
+        );
+        assert_eq!(
+            range("age = 10", "age"),
+            Some(ColumnRange {
+                start: i(10),
+                end: i(10)
+            })
+        );
+        assert_eq!(
+            range("10 < age AND age <= 20 AND name = 'x'", "age"),
+            Some(ColumnRange {
+                start: e(10),
+                end: i(20)
+            })
+        );
+        assert_eq!(
+            range("(age >= 10 AND age > 10) AND age < 30 AND 25 >= age", "age"),
+            Some(ColumnRange {
+                start: e(10),
+                end: i(25)
+            })
+        );
+        assert_eq!(
+            range("country = 'eritrea'", "country"),
+            Some(ColumnRange {
+                start: Bound::Included(DbValue::Text("eritrea".to_owned())),
+                end: Bound::Included(DbValue::Text("eritrea".to_owned()))
+            })
+        );
+        assert_eq!(range("age = 10 OR age = 20", "age"), None);
+        assert_eq!(range("age != 10", "age"), None);
+        assert_eq!(range("name = 'x'", "age"), None);
+    }
+}
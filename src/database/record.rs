@@ -1,18 +1,28 @@
-use std::{fmt::Display, io::Read};
+use std::{
+    fmt::Display,
+    io::{self, Read},
+    ops::Range,
+};
 
 use thiserror::Error;
 
-use crate::{database::varint::Varint, sql::syntax::DbValue};
+use crate::{
+    database::{
+        header::{SchemaFormat, TextEncoding},
+        varint::Varint,
+    },
+    sql::syntax::DbValue,
+};
 
 #[derive(Debug, Error)]
-pub(super) enum RecordError {
+pub(crate) enum RecordError {
     #[error("reading record failed")]
     RecordReadError,
     #[error("internal error: {0}")]
     InternalError(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) enum Record {
     Null,
     Integer(i64),
@@ -36,7 +46,11 @@ impl Display for Record {
 }
 
 impl Record {
-    fn read_type(t: RecordType, mut reader: impl Read) -> Result<Record, RecordError> {
+    fn read_type(
+        t: RecordType,
+        mut reader: impl Read,
+        encoding: TextEncoding,
+    ) -> Result<Record, RecordError> {
         match t {
             RecordType::Null => Ok(Record::Null),
             RecordType::I8 => {
@@ -58,7 +72,8 @@ impl Record {
                 reader
                     .read_exact(&mut b[1..])
                     .map_err(|e| RecordError::InternalError(e.to_string()))?;
-                Ok(Record::Integer(i32::from_be_bytes(b) as i64))
+                // shift the sign bit into place
+                Ok(Record::Integer((i32::from_be_bytes(b) << 8 >> 8) as i64))
             }
             RecordType::I32 => {
                 let mut b = [0; 4];
@@ -72,7 +87,7 @@ impl Record {
                 reader
                     .read_exact(&mut b[2..])
                     .map_err(|e| RecordError::InternalError(e.to_string()))?;
-                Ok(Record::Integer(i64::from_be_bytes(b)))
+                Ok(Record::Integer(i64::from_be_bytes(b) << 16 >> 16))
             }
             RecordType::I64 => {
                 let mut b = [0; 8];
@@ -102,13 +117,96 @@ impl Record {
                 reader
                     .read_exact(&mut b)
                     .map_err(|e| RecordError::InternalError(e.to_string()))?;
-                Ok(Record::String(
-                    String::from_utf8(b).map_err(|e| RecordError::InternalError(e.to_string()))?,
-                ))
+                Ok(Record::String(decode_text(b, encoding)?))
             }
         }
     }
-    pub(crate) fn read_row(mut reader: impl Read) -> Result<Vec<Self>, RecordError> {
+    /// Size of the record as described by its header, which should be the size of the payload
+    /// holding it
+    pub(crate) fn expected_size(mut payload: &[u8]) -> Result<usize, RecordError> {
+        let (header_size, first_size) = Varint::read_sized(&mut payload)
+            .map_err(|e| RecordError::InternalError(e.to_string()))?;
+        let header_size = header_size.0 as usize;
+        let rest = header_size
+            .checked_sub(first_size)
+            .and_then(|n| payload.get(..n))
+            .ok_or(RecordError::RecordReadError)?;
+        let mut rest = rest;
+        let mut size = header_size;
+        while !rest.is_empty() {
+            let serial_type = Varint::read(&mut rest)
+                .map_err(|e| RecordError::InternalError(e.to_string()))?
+                .0;
+            size += RecordType::from_serial(serial_type)?.size();
+        }
+        Ok(size)
+    }
+
+    /// Serial type of the value followed by its content, integers take the fewest bytes that
+    /// hold them. 0 and 1 take none at all in the schema format 4, older readers do not know
+    /// about those types
+    fn serial(&self, encoding: TextEncoding, format: SchemaFormat) -> (i64, Vec<u8>) {
+        let constants = format == SchemaFormat::Format4;
+        match self {
+            Record::Null => (0, Vec::new()),
+            Record::Integer(0) | Record::Zero if constants => (8, Vec::new()),
+            Record::Integer(1) if constants => (9, Vec::new()),
+            Record::Integer(i) => {
+                let (typ, len) = match i {
+                    -0x80..=0x7f => (1, 1),
+                    -0x8000..=0x7fff => (2, 2),
+                    -0x800000..=0x7fffff => (3, 3),
+                    -0x80000000..=0x7fffffff => (4, 4),
+                    -0x800000000000..=0x7fffffffffff => (5, 6),
+                    _ => (6, 8),
+                };
+                (typ, i.to_be_bytes()[8 - len..].to_vec())
+            }
+            Record::Zero => (1, vec![0]),
+            Record::Float(f) => (7, f.to_be_bytes().to_vec()),
+            Record::Blob(b) => (12 + 2 * b.len() as i64, b.clone()),
+            Record::String(s) => {
+                let b = encode_text(s, encoding);
+                (13 + 2 * b.len() as i64, b)
+            }
+        }
+    }
+
+    /// Encodes a row in the record format, the inverse of [`Record::read_row`]
+    pub(crate) fn write_row(
+        values: &[Record],
+        encoding: TextEncoding,
+        format: SchemaFormat,
+    ) -> Vec<u8> {
+        let mut types = Vec::new();
+        let mut content = Vec::new();
+        for value in values {
+            let (typ, bytes) = value.serial(encoding, format);
+            Varint(typ)
+                .write(&mut types)
+                .expect("writing to a vec cannot fail");
+            content.extend(bytes);
+        }
+        let varint_len = |n: usize| Varint(n as i64).write(io::sink()).unwrap_or(9);
+        // the size of the header includes the varint holding it
+        let mut header_size = types.len() + 1;
+        while types.len() + varint_len(header_size) != header_size {
+            header_size = types.len() + varint_len(header_size);
+        }
+        let mut record = Vec::with_capacity(header_size + content.len());
+        Varint(header_size as i64)
+            .write(&mut record)
+            .expect("writing to a vec cannot fail");
+        record.extend(types);
+        record.extend(content);
+        record
+    }
+
+    /// Decodes a record, TEXT values are stored in the `encoding` of the database
+    pub(crate) fn read_row(
+        mut reader: impl Read,
+        encoding: TextEncoding,
+    ) -> Result<Vec<Self>, RecordError> {
         let (header_size, first_size) = Varint::read_sized(&mut reader)
             .map_err(|e| RecordError::InternalError(e.to_string()))?;
         let mut rest_of_header = vec![0; header_size.0 as usize - first_size];
@@ -118,30 +216,42 @@ impl Record {
         let mut types = Vec::new();
         let mut rest_of_header = rest_of_header.as_slice();
         while let Ok(value) = Varint::read(&mut rest_of_header) {
-            let t = match value.0 {
-                0 => RecordType::Null,
-                1 => RecordType::I8,
-                2 => RecordType::I16,
-                3 => RecordType::I24,
-                4 => RecordType::I32,
-                5 => RecordType::I48,
-                6 => RecordType::I64,
-                7 => RecordType::F64,
-                8 => RecordType::Zero,
-                9 => RecordType::One,
-                10 | 11 => return Err(RecordError::RecordReadError),
-                v if v >= 12 && v % 2 == 0 => RecordType::Blob(((v - 12) / 2) as usize),
-                v if v >= 13 && v % 2 == 1 => RecordType::String(((v - 13) / 2) as usize),
-                _ => return Err(RecordError::RecordReadError),
-            };
-            types.push(t);
+            types.push(RecordType::from_serial(value.0)?);
         }
         types
             .into_iter()
-            .map(|t| Record::read_type(t, &mut reader))
+            .map(|t| Record::read_type(t, &mut reader, encoding))
             .collect()
     }
 }
+fn encode_text(s: &str, encoding: TextEncoding) -> Vec<u8> {
+    match encoding {
+        TextEncoding::UTF8 => s.as_bytes().to_vec(),
+        TextEncoding::UTF16le => s.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        TextEncoding::UTF16be => s.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+    }
+}
+fn decode_text(b: Vec<u8>, encoding: TextEncoding) -> Result<String, RecordError> {
+    let units = |to_u16: fn([u8; 2]) -> u16| {
+        if !b.len().is_multiple_of(2) {
+            return Err(RecordError::InternalError(
+                "odd number of bytes in UTF-16 text".to_string(),
+            ));
+        }
+        let units = b.chunks_exact(2).map(|c| to_u16([c[0], c[1]]));
+        String::from_utf16(&units.collect::<Vec<_>>())
+            .map_err(|e| RecordError::InternalError(e.to_string()))
+    };
+    match encoding {
+        TextEncoding::UTF8 => {
+            String::from_utf8(b).map_err(|e| RecordError::InternalError(e.to_string()))
+        }
+        TextEncoding::UTF16le => units(u16::from_le_bytes),
+        TextEncoding::UTF16be => units(u16::from_be_bytes),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 enum RecordType {
     Null,
     I8,
@@ -157,6 +267,95 @@ enum RecordType {
     String(usize),
 }
 
+impl RecordType {
+    fn from_serial(serial_type: i64) -> Result<Self, RecordError> {
+        Ok(match serial_type {
+            0 => RecordType::Null,
+            1 => RecordType::I8,
+            2 => RecordType::I16,
+            3 => RecordType::I24,
+            4 => RecordType::I32,
+            5 => RecordType::I48,
+            6 => RecordType::I64,
+            7 => RecordType::F64,
+            8 => RecordType::Zero,
+            9 => RecordType::One,
+            10 | 11 => return Err(RecordError::RecordReadError),
+            v if v >= 12 && v % 2 == 0 => RecordType::Blob(((v - 12) / 2) as usize),
+            v if v >= 13 && v % 2 == 1 => RecordType::String(((v - 13) / 2) as usize),
+            _ => return Err(RecordError::RecordReadError),
+        })
+    }
+
+    /// Bytes taken by the content of a value of this type
+    fn size(&self) -> usize {
+        match self {
+            RecordType::Null | RecordType::Zero | RecordType::One => 0,
+            RecordType::I8 => 1,
+            RecordType::I16 => 2,
+            RecordType::I24 => 3,
+            RecordType::I32 => 4,
+            RecordType::I48 => 6,
+            RecordType::I64 | RecordType::F64 => 8,
+            RecordType::Blob(n) | RecordType::String(n) => *n,
+        }
+    }
+}
+
+/// A record of which only the header has been read: the values are decoded one at a time when
+/// asked for, so the columns nobody looks at are never copied out of the page, nor are the
+/// overflow pages holding them read
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LazyRecord {
+    /// type of each column and the offset of its content from the start of the record
+    columns: Vec<(RecordType, usize)>,
+}
+
+impl LazyRecord {
+    /// Reads the header at the start of `payload`, which only needs to be long enough to hold it
+    pub(crate) fn new(mut payload: &[u8]) -> Result<Self, RecordError> {
+        let (header_size, first_size) = Varint::read_sized(&mut payload)
+            .map_err(|e| RecordError::InternalError(e.to_string()))?;
+        let header_size = header_size.0 as usize;
+        let mut header = header_size
+            .checked_sub(first_size)
+            .and_then(|n| payload.get(..n))
+            .ok_or(RecordError::RecordReadError)?;
+        let mut offset = header_size;
+        let mut columns = Vec::new();
+        while !header.is_empty() {
+            let serial_type = Varint::read(&mut header)
+                .map_err(|e| RecordError::InternalError(e.to_string()))?
+                .0;
+            let t = RecordType::from_serial(serial_type)?;
+            columns.push((t, offset));
+            offset += t.size();
+        }
+        Ok(Self { columns })
+    }
+
+    /// Where the content of column `col` lies in the record, None past the last column
+    pub(crate) fn span(&self, col: usize) -> Option<Range<usize>> {
+        let (t, offset) = self.columns.get(col)?;
+        Some(*offset..offset + t.size())
+    }
+
+    /// Decodes column `col` out of `payload`, which has to reach at least to the end of its span
+    pub(crate) fn read(
+        &self,
+        col: usize,
+        payload: &[u8],
+        encoding: TextEncoding,
+    ) -> Result<Record, RecordError> {
+        let (t, _) = self.columns.get(col).ok_or(RecordError::RecordReadError)?;
+        let content = self
+            .span(col)
+            .and_then(|span| payload.get(span))
+            .ok_or(RecordError::RecordReadError)?;
+        Record::read_type(*t, content, encoding)
+    }
+}
+
 impl From<Record> for DbValue {
     fn from(value: Record) -> Self {
         match value {
@@ -169,8 +368,120 @@ impl From<Record> for DbValue {
         }
     }
 }
+impl From<DbValue> for Record {
+    fn from(value: DbValue) -> Self {
+        match value {
+            DbValue::Null => Self::Null,
+            DbValue::Bool(b) => Self::Integer(b as i64),
+            DbValue::Integer(i) => Self::Integer(i),
+            DbValue::Float(f) => Self::Float(f),
+            DbValue::Text(s) => Self::String(s),
+            DbValue::Blob(b) => Self::Blob(b),
+        }
+    }
+}
 impl From<&Record> for DbValue {
     fn from(value: &Record) -> Self {
         value.clone().into()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::varint::test::Rng;
+
+    fn random_record(rng: &mut Rng) -> Record {
+        match rng.next() % 6 {
+            0 => Record::Null,
+            1 => Record::Integer(rng.next() as i64 % 3),
+            2 => Record::Integer(rng.int() * if rng.next().is_multiple_of(2) { 1 } else { -1 }),
+            3 => Record::Float(rng.int() as f64 / (rng.next() % 1000 + 1) as f64),
+            4 => Record::Blob((0..rng.next() % 300).map(|_| rng.next() as u8).collect()),
+            _ => Record::String(
+                (0..rng.next() % 100)
+                    .map(|_| ['a', 'Z', ' ', 'ä', '日', '😀'][rng.next() as usize % 6])
+                    .collect(),
+            ),
+        }
+    }
+
+    fn serial_types(mut record: &[u8]) -> Vec<i64> {
+        let (header_size, first) = Varint::read_sized(&mut record).unwrap();
+        let mut header = &record[..header_size.0 as usize - first];
+        let mut types = Vec::new();
+        while !header.is_empty() {
+            types.push(Varint::read(&mut header).unwrap().0);
+        }
+        types
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<(), RecordError> {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        for i in 0..3000 {
+            // wide enough rows for the header size to take two bytes
+            let len = if i % 10 == 0 { 200 } else { rng.next() % 12 } as usize;
+            let row: Vec<_> = (0..len).map(|_| random_record(&mut rng)).collect();
+            let encoding = [
+                TextEncoding::UTF8,
+                TextEncoding::UTF16le,
+                TextEncoding::UTF16be,
+            ][i % 3];
+            let format = [SchemaFormat::Format4, SchemaFormat::Format1][i % 2];
+            let bytes = Record::write_row(&row, encoding, format);
+            assert_eq!(Record::expected_size(&bytes)?, bytes.len());
+            let decoded: Vec<DbValue> = Record::read_row(bytes.as_slice(), encoding)?
+                .into_iter()
+                .map(DbValue::from)
+                .collect();
+            assert_eq!(decoded, row.iter().map(DbValue::from).collect::<Vec<_>>());
+
+            // integers take the smallest serial type that holds them
+            let sizes = [0, 1, 2, 3, 4, 6, 8];
+            let fits = |v: i64, bytes: usize| bytes == 8 || v >> (bytes * 8 - 1) == v >> 63;
+            for (value, typ) in row.iter().zip(serial_types(&bytes)) {
+                let Record::Integer(v) = value else {
+                    continue;
+                };
+                match (v, format) {
+                    (0, SchemaFormat::Format4) => assert_eq!(typ, 8),
+                    (1, SchemaFormat::Format4) => assert_eq!(typ, 9),
+                    _ => {
+                        assert!((1..=6).contains(&typ), "{v} stored as {typ}");
+                        assert!(fits(*v, sizes[typ as usize]), "{v} stored as {typ}");
+                        assert!(
+                            typ == 1 || !fits(*v, sizes[typ as usize - 1]),
+                            "{v} as {typ}"
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lazy_record() -> Result<(), RecordError> {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+        for i in 0..500 {
+            let row: Vec<_> = (0..rng.next() % 20)
+                .map(|_| random_record(&mut rng))
+                .collect();
+            let encoding = [TextEncoding::UTF8, TextEncoding::UTF16le][i % 2];
+            let bytes = Record::write_row(&row, encoding, SchemaFormat::Format4);
+            let lazy = LazyRecord::new(&bytes)?;
+            for (col, value) in row.iter().enumerate().rev() {
+                // only the bytes up to the end of the column are needed to decode it
+                let span = lazy.span(col).expect("column in the record");
+                let decoded = lazy.read(col, &bytes[..span.end], encoding)?;
+                assert_eq!(DbValue::from(decoded), DbValue::from(value));
+                if !span.is_empty() {
+                    assert!(lazy.read(col, &bytes[..span.end - 1], encoding).is_err());
+                }
+            }
+            assert!(lazy.span(row.len()).is_none());
+        }
+        Ok(())
+    }
+}
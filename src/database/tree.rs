@@ -1,45 +1,35 @@
+use std::cmp::Ordering;
 use std::collections::VecDeque;
 use std::io::Read;
+use std::ops::{Bound, Deref, Range};
 use std::rc::Rc;
-use std::usize;
 
 use crate::database::varint::ReadVarint;
 use crate::database::varint::Varint;
 use crate::sql::syntax::DbValue;
 
-use super::header::{BTreeHeader, BTreePageType};
+use super::cache::CacheStats;
+use super::header::{BTreeHeader, BTreePageType, TextEncoding};
+use super::page::PayloadLimits;
 use super::query::Row;
 use super::query::RowCursor;
-use super::record::Record;
-
-struct IndexedReader<R: Read> {
-    r: R,
-    pos: usize,
-}
-
-impl<R: Read> IndexedReader<R> {
-    fn new(r: R) -> Self {
-        Self { r, pos: 0 }
-    }
-}
-
-impl<R: Read> Read for IndexedReader<R> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let res = self.r.read(buf)?;
-        self.pos += res;
-        Ok(res)
-    }
-}
+use super::record::{LazyRecord, Record};
 
 pub(crate) struct Cell {
-    rowid: i64,
-    left_child: i32,
-    //no need for payload size
-    payload: Vec<u8>,
-    first_overflow: Option<usize>,
+    pub(crate) rowid: i64,
+    pub(crate) left_child: i32,
+    /// total size of the payload, including the part stored in overflow pages
+    pub(crate) payload_size: usize,
+    pub(crate) payload: Vec<u8>,
+    /// next page of the overflow chain whose content is not in `payload` yet
+    pub(crate) first_overflow: Option<usize>,
 }
 impl Cell {
-    fn read(typ: &BTreePageType, mut r: impl Read) -> Result<Cell, ()> {
+    pub(crate) fn read(
+        typ: &BTreePageType,
+        mut r: impl Read,
+        limits: &PayloadLimits,
+    ) -> Result<Cell, ()> {
         //the single match reduces branches but yields more code repetition
         match typ {
             BTreePageType::InteriorIndex => {
@@ -48,14 +38,15 @@ impl Cell {
                     .map_err(|e| eprintln!("{e}"))?;
                 let left_child = i32::from_be_bytes(page_number_buf);
                 let payload_size = Varint::read(&mut r).map_err(|e| eprintln!("{e}"))?;
-                let mut payload = vec![0; payload_size.0.try_into().map_err(|e| eprintln!("{e}"))?];
-                r.read_exact(&mut payload).map_err(|e| eprintln!("{e}"))?;
-                //TODO: read start of overflow page
+                let payload_size = payload_size.0.try_into().map_err(|e| eprintln!("{e}"))?;
+                let (payload, first_overflow) =
+                    Self::read_local_payload(typ, &mut r, payload_size, limits)?;
                 Ok(Cell {
                     rowid: 0,
                     left_child,
+                    payload_size,
                     payload,
-                    first_overflow: None,
+                    first_overflow,
                 })
             }
             BTreePageType::InteriorTable => {
@@ -67,6 +58,7 @@ impl Cell {
                 Ok(Cell {
                     rowid,
                     left_child,
+                    payload_size: 0,
                     payload: Vec::new(),
                     first_overflow: None,
                 })
@@ -74,31 +66,143 @@ impl Cell {
 
             BTreePageType::LeafIndex => {
                 let payload_size = Varint::read(&mut r).map_err(|e| eprintln!("{e}"))?;
-                let mut payload = vec![0; payload_size.0.try_into().map_err(|e| eprintln!("{e}"))?];
-                r.read_exact(&mut payload).map_err(|e| eprintln!("{e}"))?;
-                //TODO: read start of overflow page
+                let payload_size = payload_size.0.try_into().map_err(|e| eprintln!("{e}"))?;
+                let (payload, first_overflow) =
+                    Self::read_local_payload(typ, &mut r, payload_size, limits)?;
                 Ok(Cell {
                     rowid: 0,
                     left_child: 0,
+                    payload_size,
                     payload,
-                    first_overflow: None,
+                    first_overflow,
                 })
             }
             BTreePageType::LeafTable => {
                 let payload_size = Varint::read(&mut r).map_err(|e| eprintln!("{e}"))?;
+                let payload_size = payload_size.0.try_into().map_err(|e| eprintln!("{e}"))?;
                 let rowid = r.read_varint().map_err(|e| eprintln!("{e}"))?.0;
-                let mut payload = vec![0; payload_size.0.try_into().map_err(|e| eprintln!("{e}"))?];
-                r.read_exact(&mut payload).map_err(|e| eprintln!("{e}"))?;
-                //TODO: read start of overflow page
+                let (payload, first_overflow) =
+                    Self::read_local_payload(typ, &mut r, payload_size, limits)?;
                 Ok(Cell {
                     rowid,
                     left_child: 0,
+                    payload_size,
                     payload,
-                    first_overflow: None,
+                    first_overflow,
                 })
             }
         }
     }
+
+    /// Reads the portion of the payload stored on the btree page, followed by the number of the
+    /// first overflow page when the payload does not fit
+    fn read_local_payload(
+        typ: &BTreePageType,
+        mut r: impl Read,
+        payload_size: usize,
+        limits: &PayloadLimits,
+    ) -> Result<(Vec<u8>, Option<usize>), ()> {
+        let local = limits.local_size(typ, payload_size);
+        let mut payload = vec![0; local];
+        r.read_exact(&mut payload).map_err(|e| eprintln!("{e}"))?;
+        if local == payload_size {
+            return Ok((payload, None));
+        }
+        let mut page_number_buf = [0; 4];
+        r.read_exact(&mut page_number_buf)
+            .map_err(|e| eprintln!("{e}"))?;
+        Ok((payload, Some(u32::from_be_bytes(page_number_buf) as usize)))
+    }
+
+    /// Reads the cell at `pointer` without following its overflow chain, enough to get to the
+    /// rowid and child pointer of table cells
+    fn read_key(
+        supplier: &mut impl PageSupplier,
+        page_idx: usize,
+        typ: &BTreePageType,
+        pointer: usize,
+    ) -> Result<Cell, ()> {
+        let limits = supplier.payload_limits();
+        let offset = if page_idx == 1 { 100 } else { 0 };
+        let page = supplier
+            .read_page(page_idx)
+            .map_err(|_| eprintln!("page supplier error in read_page"))?;
+        Cell::read(typ, &page[pointer - offset..], &limits)
+    }
+
+    /// Follows the overflow chain (if any) and appends its content to the local payload, leaving
+    /// the cell with its complete payload
+    pub(crate) fn read_overflow(&mut self, supplier: &mut impl PageSupplier) -> Result<(), ()> {
+        self.read_overflow_to(supplier, self.payload_size)?;
+        if let Some(page_idx) = self.first_overflow {
+            // the chain is longer than the payload says it should be
+            eprintln!("overflow page {page_idx} is past the end of the payload");
+            return Err(());
+        }
+        if self.payload.len() != self.payload_size {
+            eprintln!("overflow chain ended before the payload was complete");
+            return Err(());
+        }
+        Ok(())
+    }
+
+    /// Like [`Cell::read_overflow`], but stops following the chain as soon as the payload holds
+    /// its first `len` bytes
+    pub(crate) fn read_overflow_to(
+        &mut self,
+        supplier: &mut impl PageSupplier,
+        len: usize,
+    ) -> Result<(), ()> {
+        let usable_size = supplier.payload_limits().usable_size;
+        let len = len.min(self.payload_size);
+        while self.payload.len() < len {
+            let Some(page_idx) = self.first_overflow.take() else {
+                break;
+            };
+            let missing = self.payload_size - self.payload.len();
+            let page = supplier
+                .read_page(page_idx)
+                .map_err(|_| eprintln!("page supplier error while reading overflow page"))?;
+            let mut page_number_buf = [0; 4];
+            page_number_buf.copy_from_slice(&page[..4]);
+            let content = &page[4..usable_size];
+            self.payload
+                .extend_from_slice(&content[..missing.min(content.len())]);
+            self.first_overflow = match u32::from_be_bytes(page_number_buf) {
+                0 => None,
+                n => Some(n as usize),
+            };
+        }
+        Ok(())
+    }
+}
+
+/// A whole page (page 1 included, header and all) that can be held onto while the supplier moves
+/// somewhere else
+#[derive(Clone)]
+pub(crate) enum SharedPage {
+    /// a copy of the page of its own
+    Owned(Rc<[u8]>),
+    /// `range` of a buffer holding more than one page, like the mapping of the whole file, which
+    /// is kept alive for as long as the page is
+    View(Rc<dyn AsRef<[u8]>>, Range<usize>),
+}
+
+impl Deref for SharedPage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            SharedPage::Owned(page) => page,
+            SharedPage::View(buf, range) => &(**buf).as_ref()[range.clone()],
+        }
+    }
+}
+
+impl From<&[u8]> for SharedPage {
+    fn from(page: &[u8]) -> Self {
+        SharedPage::Owned(Rc::from(page))
+    }
 }
 
 pub(crate) trait PageSupplier {
@@ -106,16 +210,117 @@ pub(crate) trait PageSupplier {
     fn move_to_page(&mut self, page_idx: usize) -> Result<(), Self::Error>;
     fn read_page(&mut self, page_idx: usize) -> Result<&[u8], Self::Error>;
     fn page(&self) -> &[u8];
+    fn payload_limits(&self) -> PayloadLimits;
+
+    /// Encoding of the TEXT values stored in the pages
+    fn text_encoding(&self) -> TextEncoding {
+        TextEncoding::UTF8
+    }
+
+    /// Returns a copy of the page that stays valid regardless of what the supplier reads next,
+    /// suppliers with a cache can hand out the same buffer to everyone
+    fn shared_page(&mut self, page_idx: usize) -> Result<SharedPage, Self::Error> {
+        self.move_to_page(page_idx)?;
+        Ok(SharedPage::from(self.page()))
+    }
+
+    /// Hit and miss counters of the page cache, if there is one
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    /// Size of the database in pages as far as the pages themselves go, regardless of what the
+    /// header says, for suppliers that can tell
+    fn db_size(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl<S: PageSupplier + ?Sized> PageSupplier for Box<S> {
+    type Error = S::Error;
+
+    fn move_to_page(&mut self, page_idx: usize) -> Result<(), Self::Error> {
+        (**self).move_to_page(page_idx)
+    }
+
+    fn read_page(&mut self, page_idx: usize) -> Result<&[u8], Self::Error> {
+        (**self).read_page(page_idx)
+    }
+
+    fn page(&self) -> &[u8] {
+        (**self).page()
+    }
+
+    fn payload_limits(&self) -> PayloadLimits {
+        (**self).payload_limits()
+    }
+
+    fn text_encoding(&self) -> TextEncoding {
+        (**self).text_encoding()
+    }
+
+    fn shared_page(&mut self, page_idx: usize) -> Result<SharedPage, Self::Error> {
+        (**self).shared_page(page_idx)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        (**self).cache_stats()
+    }
+
+    fn db_size(&self) -> Option<usize> {
+        (**self).db_size()
+    }
 }
 
+/// The write side of a [`PageSupplier`], pages are always written whole (page 1 includes the
+/// database header)
 pub(crate) trait PageConsumer {
-    // TODO:::
+    type Error;
+    fn write_page(&mut self, page_idx: usize, page: &[u8]) -> Result<(), Self::Error>;
+
+    /// Waits for the pages written so far to reach the disk, for consumers that have one
+    fn sync(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Drops every page past the first `pages` ones, for consumers that have a file to shrink
+    fn truncate(&mut self, _pages: usize) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<C: PageConsumer + ?Sized> PageConsumer for Box<C> {
+    type Error = C::Error;
+
+    fn write_page(&mut self, page_idx: usize, page: &[u8]) -> Result<(), Self::Error> {
+        (**self).write_page(page_idx, page)
+    }
+
+    fn sync(&mut self) -> Result<(), Self::Error> {
+        (**self).sync()
+    }
+
+    fn truncate(&mut self, pages: usize) -> Result<(), Self::Error> {
+        (**self).truncate(pages)
+    }
+}
+
+/// A layer of the pager stack, which pages can be both read from and written to
+pub(crate) trait Pager:
+    PageSupplier<Error = anyhow::Error> + PageConsumer<Error = anyhow::Error>
+{
+}
+
+impl<P: PageSupplier<Error = anyhow::Error> + PageConsumer<Error = anyhow::Error> + ?Sized> Pager
+    for P
+{
 }
 
 pub(crate) struct BTreeTableReader {}
 
 impl BTreeTableReader {
-    #[allow(unreachable_code)]
+    /// Collects the rows of the table btree rooted at `root_idx` that satisfy `predicate`, in
+    /// rowid order
     pub(crate) fn scan_table<F>(
         &self,
         root_idx: usize,
@@ -126,76 +331,57 @@ impl BTreeTableReader {
         F: Fn(i64, &[u8]) -> bool,
     {
         // TODO: mettere a posto gestione degli errori qui
-        supplier
-            .move_to_page(root_idx)
-            .map_err(|_| eprintln!("page supplier error "))?;
-        let r = supplier
-            .read_page(root_idx)
-            .map_err(|_| eprintln!("page supplier error in read_page"))?;
-        //println!("{r:?}");
-        let header = BTreeHeader::new(r).map_err(|e| eprintln!("{e}"))?;
-        println!("BTREEHEADER: {header:?}");
-        let cell_start = header.cell_start - if root_idx == 1 { 100 } else { 0 };
-        let r = &r[cell_start as usize..r.len()];
-        let mut r = IndexedReader::new(r);
-        // consume page cells based on page type
-        // TODO: the code to read cells should be moved outside
-        // FIXME: We should also add support for freeblock reading
-        // My idea is to keep this match but read cells in a separate function that returns
-        // the result vec and the number of bytes read, this way we can check whether we are about
-        // to read the next freeblock and consume that instead
-        let mut freeblock = header.freeblock_start as usize;
-        let mut cells = Vec::new();
-        loop {
-            //println!("Reading at {}", r.pos + header.cell_start as usize);
-            if freeblock != 0 && r.pos + header.cell_start as usize == freeblock {
-                println!("Encountered freeblock at {freeblock}");
-                let mut buf = [0, 0];
-                r.read_exact(&mut buf).map_err(|e| eprintln!("{e}"))?;
-                freeblock = u16::from_be_bytes(buf) as usize;
-                r.read_exact(&mut buf).map_err(|e| eprintln!("{e}"))?;
-                // freeblock size includes 4 byte-header
-                let size = u16::from_be_bytes(buf) as usize - 4;
-                //there has to be a better way
-                let mut v = vec![0; size];
-                r.read_exact(&mut v).map_err(|e| eprintln!("{e}"))?;
-            } else {
-                if let Ok(cell) = self.read_cell(&header.page_type, &mut r) {
-                    cells.push(cell);
-                } else {
-                    break;
-                }
-            }
-        }
-        match &header.page_type {
+        let (_, header, pointers) = read_page_layout(supplier, root_idx)?;
+        let typ = header.page_type;
+        // the cell pointer array is sorted by key, the cell content area is not
+        match typ {
             BTreePageType::InteriorIndex | BTreePageType::LeafIndex => {
-                unimplemented!("we do not support query by index yet!")
+                eprintln!("page {root_idx} belongs to an index, use an IndexCursor to read it");
+                Err(())
             }
             BTreePageType::InteriorTable => {
+                let mut children = Vec::with_capacity(pointers.len() + 1);
+                for pointer in pointers {
+                    let cell = Cell::read_key(supplier, root_idx, &typ, pointer)?;
+                    children.push(cell.left_child as usize);
+                }
+                // the right-most child holds the rows with the largest rowids
+                children.push(header.right_ptr.ok_or(())? as usize);
                 let mut res = Vec::new();
-                for cell in cells.iter().rev() {
-                    res.append(&mut self.scan_table(
-                        cell.left_child as usize,
-                        supplier,
-                        predicate,
-                    )?)
+                for child in children {
+                    res.append(&mut self.scan_table(child, supplier, predicate)?)
                 }
                 Ok(res)
             }
             BTreePageType::LeafTable => {
-                // TODO: we have to do the rowid magic so we'll later change this return type
-                Ok(cells
-                    .into_iter()
-                    .rev()
-                    .filter(|c| predicate(c.rowid, &c.payload))
-                    .map(|c| (c.rowid, c.payload))
-                    .collect())
+                let mut res = Vec::new();
+                for pointer in pointers {
+                    let cell = read_cell_at(supplier, root_idx, &typ, pointer)?;
+                    if predicate(cell.rowid, &cell.payload) {
+                        res.push((cell.rowid, cell.payload));
+                    }
+                }
+                Ok(res)
             }
         }
     }
 
-    fn read_cell(&self, typ: &BTreePageType, r: impl Read) -> Result<Cell, ()> {
-        Cell::read(typ, r)
+    /// Looks up a single row by descending the table btree, returns its payload if the rowid
+    /// exists
+    pub(crate) fn find_by_rowid(
+        &self,
+        root_idx: usize,
+        rowid: i64,
+        supplier: &mut impl PageSupplier,
+    ) -> Result<Option<Vec<u8>>, ()> {
+        let mut cursor = TableCursor::new(supplier, root_idx);
+        cursor.seek(rowid);
+        match cursor.next() {
+            Some(()) if cursor.rowid() == &DbValue::Integer(rowid) => {
+                Ok(Some(cursor.payload()?.to_vec()))
+            }
+            _ => Ok(None),
+        }
     }
 
     #[allow(unreachable_code)]
@@ -216,69 +402,75 @@ pub struct Cursor<'a, T> {
     /// we know we have to change page
     row: Row,
     page_idx: i64,
+    /// pages still to be visited, in rowid order
     pages: VecDeque<i64>,
     header: BTreeHeader,
     pointers: VecDeque<usize>,
 }
 
 impl<'a, T: PageSupplier> Cursor<'a, T> {
+    /// Moves to the next leaf page, descending into the interior pages found along the way
     fn load_page(&mut self) -> Option<()> {
-        let page_idx = self.pages.pop_front()?;
-        let mut p = self.supplier.read_page(page_idx as usize).ok()?;
-        let header = BTreeHeader::new(&mut p).ok()?;
-        let mut pointers = vec![0; header.cell_count as usize];
-        let mut b2 = [0, 0];
-        for pointer in &mut pointers {
-            p.read_exact(&mut b2).ok()?;
-            *pointer = u16::from_be_bytes(b2) as usize;
-        }
-        let offset = if self.page_idx == 1 { 100 } else { 0 };
-        match header.page_type {
-            BTreePageType::InteriorIndex | BTreePageType::LeafIndex => {
-                unimplemented!("indices: soon to be implemented!")
-            }
-            BTreePageType::InteriorTable => {
-                // main idea is to do dfs -> I built a reverse stack cuz yeah
-                for pointer in pointers.iter().rev() {
-                    let cell =
-                        Cell::read(&BTreePageType::InteriorTable, &p[pointer - offset..]).ok()?;
-                    self.pages.push_front(cell.left_child as i64);
+        loop {
+            let page_idx = self.pages.pop_front()?;
+            let (_, header, pointers) = read_page_layout(self.supplier, page_idx as usize).ok()?;
+            match header.page_type {
+                BTreePageType::InteriorIndex | BTreePageType::LeafIndex => {
+                    eprintln!("page {page_idx} belongs to an index, use an IndexCursor to read it");
+                    return None;
+                }
+                BTreePageType::InteriorTable => {
+                    // main idea is to do dfs -> I built a reverse stack cuz yeah
+                    // the right-most child comes after every other one
+                    self.pages.push_front(header.right_ptr? as i64);
+                    for pointer in pointers.iter().rev() {
+                        let cell = Cell::read_key(
+                            self.supplier,
+                            page_idx as usize,
+                            &header.page_type,
+                            *pointer,
+                        )
+                        .ok()?;
+                        self.pages.push_front(cell.left_child as i64);
+                    }
+                }
+                BTreePageType::LeafTable => {
+                    self.header = header;
+                    self.pointers = pointers.into();
+                    self.page_idx = page_idx;
+                    return Some(());
                 }
-                return self.load_page();
-            }
-            BTreePageType::LeafTable => {
-                self.header = header;
-                self.pointers = pointers.into();
-                self.page_idx = page_idx;
-                self.load_row()?;
             }
         }
-
-        Some(())
     }
     fn load_row(&mut self) -> Option<()> {
         let ptr = self.pointers.pop_front()?;
-        let offset = if self.page_idx == 1 { 100 } else { 0 };
-        let page = &self.supplier.page()[ptr - offset..];
-        let cell = Cell::read(&self.header.page_type, page).ok()?;
-        let row = Record::read_row(cell.payload.as_slice()).ok()?;
+        let cell = read_cell_at(
+            self.supplier,
+            self.page_idx as usize,
+            &self.header.page_type,
+            ptr,
+        )
+        .ok()?;
+        let row = Record::read_row(cell.payload.as_slice(), self.supplier.text_encoding()).ok()?;
         self.row = Row {
             id: DbValue::Integer(cell.rowid),
-            row: row.iter().map(Into::into).collect(),
+            row: row.into_iter().map(Into::into).collect(),
         };
 
         Some(())
     }
+    /// Creates a cursor positioned before the first row of the table rooted at `idx`
     pub fn new(supplier: &'a mut T, idx: i64) -> Self {
         // Un poco na merda ma amen
-        let mut s = Self {
+        Self {
             supplier,
             row: Row {
                 id: DbValue::Null,
                 row: Vec::new(),
             },
             page_idx: idx,
-            pages: VecDeque::new(),
+            pages: VecDeque::from([idx]),
             header: BTreeHeader {
                 page_type: BTreePageType::InteriorTable,
                 freeblock_start: 0,
@@ -288,27 +480,18 @@ impl<'a, T: PageSupplier> Cursor<'a, T> {
                 right_ptr: Some(0),
             },
             pointers: VecDeque::new(),
-        };
-        s.next();
-        s
+        }
     }
 }
 
-// NOTE: per adesso, per pigrizia, inizio ignorando il fatto che le cells all'interno di una pagina
-// siano al contrario (le celle vengono riempite a partire dal fondo per non cazzare gli spazi
-// vuoti)
-//
-
 impl<'a, T: PageSupplier> RowCursor for Cursor<'a, T> {
-    fn column(&self, colpos: usize) -> Option<&DbValue> {
-        self.row.row.get(colpos)
+    fn column(&mut self, colpos: usize) -> Option<DbValue> {
+        self.row.row.get(colpos).cloned()
     }
 
-    // WARNING: OCCHIO A QUESTA COSA:
-    // il btree header è immediatamente seguito da un'array di cell pointers che io fino ad oggi
-    // ho bellamente ignorato nessuno sa perché
     fn next(&mut self) -> Option<()> {
-        if let None = self.load_row() {
+        // empty leaves are possible (e.g. the root of an empty table), so keep going
+        while self.load_row().is_none() {
             self.load_page()?
         }
         Some(())
@@ -318,3 +501,885 @@ impl<'a, T: PageSupplier> RowCursor for Cursor<'a, T> {
         &self.row.id
     }
 }
+
+/// Reads the btree header of a page together with its cell pointer array, the page itself is
+/// returned as well so that its cells can be read without going through the supplier again
+pub(crate) fn read_page_layout(
+    supplier: &mut impl PageSupplier,
+    page_idx: usize,
+) -> Result<(SharedPage, BTreeHeader, Vec<usize>), ()> {
+    let shared = supplier
+        .shared_page(page_idx)
+        .map_err(|_| eprintln!("page supplier error in shared_page"))?;
+    let mut page = &shared[if page_idx == 1 { 100 } else { 0 }..];
+    let header = BTreeHeader::new(&mut page).map_err(|e| eprintln!("{e}"))?;
+    let mut pointers = vec![0; header.cell_count as usize];
+    let mut b2 = [0, 0];
+    for pointer in &mut pointers {
+        page.read_exact(&mut b2).map_err(|e| eprintln!("{e}"))?;
+        *pointer = u16::from_be_bytes(b2) as usize;
+    }
+    Ok((shared, header, pointers))
+}
+
+/// Reads the cell found at `pointer` (offset from the start of the page), overflow included
+fn read_cell_at(
+    supplier: &mut impl PageSupplier,
+    page_idx: usize,
+    typ: &BTreePageType,
+    pointer: usize,
+) -> Result<Cell, ()> {
+    let mut cell = Cell::read_key(supplier, page_idx, typ, pointer)?;
+    cell.read_overflow(supplier)?;
+    Ok(cell)
+}
+
+/// Orders values the way sqlite does with the BINARY collation:
+/// NULL < INTEGER/REAL < TEXT < BLOB
+pub(crate) fn compare_values(a: &DbValue, b: &DbValue) -> Ordering {
+    compare_values_in(a, b, TextEncoding::UTF8)
+}
+
+/// Same as [`compare_values`], but TEXT is compared byte by byte as stored with `encoding`, which
+/// is the order index entries are kept in
+pub(crate) fn compare_values_in(a: &DbValue, b: &DbValue, encoding: TextEncoding) -> Ordering {
+    fn class(v: &DbValue) -> u8 {
+        match v {
+            DbValue::Null => 0,
+            DbValue::Bool(_) | DbValue::Integer(_) | DbValue::Float(_) => 1,
+            DbValue::Text(_) => 2,
+            DbValue::Blob(_) => 3,
+        }
+    }
+    fn numeric(v: &DbValue) -> f64 {
+        match v {
+            DbValue::Bool(b) => *b as i64 as f64,
+            DbValue::Integer(i) => *i as f64,
+            DbValue::Float(f) => *f,
+            _ => unreachable!("only numeric values get here"),
+        }
+    }
+    match (a, b) {
+        (DbValue::Integer(a), DbValue::Integer(b)) => a.cmp(b),
+        (DbValue::Text(a), DbValue::Text(b)) => match encoding {
+            TextEncoding::UTF8 => a.as_bytes().cmp(b.as_bytes()),
+            TextEncoding::UTF16le => a
+                .encode_utf16()
+                .flat_map(u16::to_le_bytes)
+                .cmp(b.encode_utf16().flat_map(u16::to_le_bytes)),
+            TextEncoding::UTF16be => a
+                .encode_utf16()
+                .flat_map(u16::to_be_bytes)
+                .cmp(b.encode_utf16().flat_map(u16::to_be_bytes)),
+        },
+        (DbValue::Blob(a), DbValue::Blob(b)) => a.cmp(b),
+        _ => match class(a).cmp(&class(b)) {
+            Ordering::Equal if class(a) == 1 => numeric(a).total_cmp(&numeric(b)),
+            o => o,
+        },
+    }
+}
+
+/// Orders two index entries, columns sorted in descending order (`asc` is false for them) compare
+/// the other way around. When one is a prefix of the other the shorter one comes first
+pub(crate) fn compare_index_keys(
+    a: &[DbValue],
+    b: &[DbValue],
+    asc: &[bool],
+    encoding: TextEncoding,
+) -> Ordering {
+    a.iter()
+        .zip(b)
+        .enumerate()
+        .map(|(i, (a, b))| match compare_values_in(a, b, encoding) {
+            o if asc.get(i) == Some(&false) => o.reverse(),
+            o => o,
+        })
+        .find(|o| o.is_ne())
+        .unwrap_or(a.len().cmp(&b.len()))
+}
+
+/// Compares an index record against a (possibly partial) key, only the first `key.len()` columns
+/// take part in the comparison
+fn compare_key(record: &[DbValue], key: &[DbValue], encoding: TextEncoding) -> Ordering {
+    record
+        .iter()
+        .zip(key)
+        .map(|(r, k)| compare_values_in(r, k, encoding))
+        .find(|o| o.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// A page of the index btree the cursor is currently walking through
+struct IndexFrame {
+    page: SharedPage,
+    page_type: BTreePageType,
+    pointers: Vec<usize>,
+    right_ptr: Option<u32>,
+    /// next cell to be returned
+    pos: usize,
+    /// whether the subtree on the left of `pos` has already been visited
+    child_done: bool,
+}
+
+/// Walks an index btree in key order, each entry holds the indexed columns followed by the rowid
+/// of the row they belong to (or the whole row, for WITHOUT ROWID tables)
+pub(crate) struct IndexCursor<'a, T> {
+    supplier: &'a mut T,
+    root_idx: usize,
+    encoding: TextEncoding,
+    stack: Vec<IndexFrame>,
+    /// decoded record of the current entry, the rowid is the last column
+    record: Vec<DbValue>,
+    /// entries equal to this key are skipped, used for exclusive lower bounds
+    skip: Option<Vec<DbValue>>,
+    end: Bound<Vec<DbValue>>,
+}
+
+impl<'a, T: PageSupplier> IndexCursor<'a, T> {
+    /// Creates a cursor positioned before the first entry of the index
+    pub(crate) fn new(supplier: &'a mut T, root_idx: usize) -> Self {
+        Self {
+            encoding: supplier.text_encoding(),
+            supplier,
+            root_idx,
+            stack: Vec::new(),
+            record: Vec::new(),
+            skip: None,
+            end: Bound::Unbounded,
+        }
+        .rewind()
+    }
+
+    /// Creates a cursor that only walks the entries whose key falls within `start` and `end`
+    pub(crate) fn range(
+        supplier: &'a mut T,
+        root_idx: usize,
+        start: Bound<&[DbValue]>,
+        end: Bound<&[DbValue]>,
+    ) -> Self {
+        let mut cursor = Self::new(supplier, root_idx);
+        match start {
+            Bound::Included(key) => cursor.seek(key),
+            Bound::Excluded(key) => {
+                cursor.seek(key);
+                // seek stops before the first entry >= key, the equal ones are skipped in next
+                cursor.skip = Some(key.to_vec());
+            }
+            Bound::Unbounded => {}
+        }
+        cursor.end = end.map(|k| k.to_vec());
+        cursor
+    }
+
+    fn rewind(mut self) -> Self {
+        self.stack.clear();
+        if let Ok(frame) = self.load_frame(self.root_idx) {
+            self.stack.push(frame);
+        }
+        self
+    }
+
+    fn load_frame(&mut self, page_idx: usize) -> Result<IndexFrame, ()> {
+        let (page, header, pointers) = read_page_layout(self.supplier, page_idx)?;
+        if !matches!(
+            header.page_type,
+            BTreePageType::InteriorIndex | BTreePageType::LeafIndex
+        ) {
+            eprintln!("page {page_idx} is not an index page");
+            return Err(());
+        }
+        Ok(IndexFrame {
+            page,
+            page_type: header.page_type,
+            pointers,
+            right_ptr: header.right_ptr,
+            pos: 0,
+            child_done: false,
+        })
+    }
+
+    fn load_cell(&mut self, depth: usize, pos: usize) -> Result<Cell, ()> {
+        let frame = &self.stack[depth];
+        let limits = self.supplier.payload_limits();
+        let mut cell = Cell::read(
+            &frame.page_type,
+            &frame.page[frame.pointers[pos]..],
+            &limits,
+        )?;
+        cell.read_overflow(self.supplier)?;
+        Ok(cell)
+    }
+
+    fn decode(&self, cell: &Cell) -> Result<Vec<DbValue>, ()> {
+        Record::read_row(cell.payload.as_slice(), self.encoding)
+            .map(|r| r.into_iter().map(DbValue::from).collect())
+            .map_err(|e| eprintln!("{e}"))
+    }
+
+    /// Positions the cursor right before the first entry whose key is greater or equal to `key`,
+    /// so that the following call to `next` returns it
+    pub(crate) fn seek(&mut self, key: &[DbValue]) {
+        self.stack.clear();
+        let mut page_idx = self.root_idx;
+        loop {
+            let Ok(frame) = self.load_frame(page_idx) else {
+                self.stack.clear();
+                return;
+            };
+            self.stack.push(frame);
+            let depth = self.stack.len() - 1;
+            // binary search for the first cell >= key
+            let (mut lo, mut hi) = (0, self.stack[depth].pointers.len());
+            while lo < hi {
+                let mid = (lo + hi) / 2;
+                let Ok(record) = self.load_cell(depth, mid).and_then(|c| self.decode(&c)) else {
+                    self.stack.clear();
+                    return;
+                };
+                if compare_key(&record, key, self.encoding) == Ordering::Less {
+                    lo = mid + 1;
+                } else {
+                    hi = mid;
+                }
+            }
+            let frame = &mut self.stack[depth];
+            frame.pos = lo;
+            match frame.page_type {
+                BTreePageType::InteriorIndex => {
+                    // the left subtree of the cell (or the rightmost one) is the next to visit
+                    frame.child_done = true;
+                    page_idx = self.child_of(depth, lo);
+                    if page_idx == 0 {
+                        self.stack.clear();
+                        return;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Page number of the child on the left of cell `pos`, or the rightmost pointer when `pos`
+    /// is past the last cell
+    fn child_of(&mut self, depth: usize, pos: usize) -> usize {
+        if pos < self.stack[depth].pointers.len() {
+            self.load_cell(depth, pos)
+                .map(|c| c.left_child as usize)
+                .unwrap_or(0)
+        } else {
+            self.stack[depth].right_ptr.unwrap_or(0) as usize
+        }
+    }
+
+    fn advance(&mut self) -> Option<()> {
+        loop {
+            let depth = self.stack.len().checked_sub(1)?;
+            let frame = &mut self.stack[depth];
+            let n = frame.pointers.len();
+            match frame.page_type {
+                BTreePageType::InteriorIndex if !frame.child_done => {
+                    let pos = frame.pos;
+                    let child = self.child_of(depth, pos);
+                    let Ok(child) = self.load_frame(child) else {
+                        self.stack.clear();
+                        return None;
+                    };
+                    self.stack[depth].child_done = true;
+                    self.stack.push(child);
+                }
+                _ if frame.pos < n => {
+                    let pos = frame.pos;
+                    frame.pos += 1;
+                    frame.child_done = false;
+                    let Ok(record) = self.load_cell(depth, pos).and_then(|c| self.decode(&c))
+                    else {
+                        self.stack.clear();
+                        return None;
+                    };
+                    self.record = record;
+                    return Some(());
+                }
+                _ => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+
+    /// The indexed columns of the current entry
+    pub(crate) fn key(&self) -> &[DbValue] {
+        &self.record[..self.record.len().saturating_sub(1)]
+    }
+
+    /// Every column of the current entry, for WITHOUT ROWID tables these are the whole row
+    pub(crate) fn record(&self) -> &[DbValue] {
+        &self.record
+    }
+}
+
+impl<'a, T: PageSupplier> RowCursor for IndexCursor<'a, T> {
+    fn rowid(&self) -> &DbValue {
+        self.record.last().unwrap_or(&DbValue::Null)
+    }
+
+    fn column(&mut self, colpos: usize) -> Option<DbValue> {
+        self.key().get(colpos).cloned()
+    }
+
+    fn next(&mut self) -> Option<()> {
+        self.advance()?;
+        // bounds never cover more than the indexed columns, compare_key ignores what follows
+        while let Some(skip) = &self.skip {
+            if compare_key(&self.record, skip, self.encoding) != Ordering::Equal {
+                self.skip = None;
+            } else {
+                self.advance()?;
+            }
+        }
+        let past_end = match &self.end {
+            Bound::Included(end) => {
+                compare_key(&self.record, end, self.encoding) == Ordering::Greater
+            }
+            Bound::Excluded(end) => compare_key(&self.record, end, self.encoding) != Ordering::Less,
+            Bound::Unbounded => false,
+        };
+        if past_end {
+            self.stack.clear();
+            return None;
+        }
+        Some(())
+    }
+}
+
+/// A page of the table btree the cursor is currently walking through
+struct TableFrame {
+    page: SharedPage,
+    page_type: BTreePageType,
+    pointers: Vec<usize>,
+    right_ptr: Option<u32>,
+    /// next cell (or child, for interior pages) to visit
+    pos: usize,
+}
+
+/// Walks a table btree in rowid order, unlike [`Cursor`] it can jump straight to a rowid by
+/// descending the interior pages instead of visiting every leaf. The columns of the current row
+/// are only decoded when asked for
+pub(crate) struct TableCursor<'a, T> {
+    supplier: &'a mut T,
+    root_idx: usize,
+    stack: Vec<TableFrame>,
+    rowid: DbValue,
+    /// cell of the current row, its overflow pages are read as the columns need them
+    cell: Option<Cell>,
+    record: LazyRecord,
+    end: Bound<i64>,
+}
+
+impl<'a, T: PageSupplier> TableCursor<'a, T> {
+    /// Creates a cursor positioned before the first row of the table
+    pub(crate) fn new(supplier: &'a mut T, root_idx: usize) -> Self {
+        let mut cursor = Self {
+            supplier,
+            root_idx,
+            stack: Vec::new(),
+            rowid: DbValue::Null,
+            cell: None,
+            record: LazyRecord::default(),
+            end: Bound::Unbounded,
+        };
+        if let Ok(frame) = cursor.load_frame(root_idx) {
+            cursor.stack.push(frame);
+        }
+        cursor
+    }
+
+    /// Creates a cursor that only walks the rows whose rowid falls within `start` and `end`
+    pub(crate) fn range(
+        supplier: &'a mut T,
+        root_idx: usize,
+        start: Bound<i64>,
+        end: Bound<i64>,
+    ) -> Self {
+        let mut cursor = Self::new(supplier, root_idx);
+        match start {
+            Bound::Included(rowid) => cursor.seek(rowid),
+            Bound::Excluded(rowid) => match rowid.checked_add(1) {
+                Some(rowid) => cursor.seek(rowid),
+                // no rowid comes after the largest one
+                None => cursor.stack.clear(),
+            },
+            Bound::Unbounded => {}
+        }
+        cursor.end = end;
+        cursor
+    }
+
+    fn load_frame(&mut self, page_idx: usize) -> Result<TableFrame, ()> {
+        let (page, header, pointers) = read_page_layout(self.supplier, page_idx)?;
+        if !matches!(
+            header.page_type,
+            BTreePageType::InteriorTable | BTreePageType::LeafTable
+        ) {
+            eprintln!("page {page_idx} is not a table page");
+            return Err(());
+        }
+        Ok(TableFrame {
+            page,
+            page_type: header.page_type,
+            pointers,
+            right_ptr: header.right_ptr,
+            pos: 0,
+        })
+    }
+
+    fn read_key(&mut self, depth: usize, pos: usize) -> Result<Cell, ()> {
+        let frame = &self.stack[depth];
+        let limits = self.supplier.payload_limits();
+        Cell::read(
+            &frame.page_type,
+            &frame.page[frame.pointers[pos]..],
+            &limits,
+        )
+    }
+
+    /// Page number of the child on the left of cell `pos`, or the rightmost pointer when `pos`
+    /// is past the last cell
+    fn child_of(&mut self, depth: usize, pos: usize) -> Result<usize, ()> {
+        if pos < self.stack[depth].pointers.len() {
+            Ok(self.read_key(depth, pos)?.left_child as usize)
+        } else {
+            Ok(self.stack[depth].right_ptr.ok_or(())? as usize)
+        }
+    }
+
+    /// Positions the cursor right before the first row whose rowid is greater or equal to
+    /// `rowid`, so that the following call to `next` returns it
+    pub(crate) fn seek(&mut self, rowid: i64) {
+        self.stack.clear();
+        let mut page_idx = self.root_idx;
+        loop {
+            let Ok(frame) = self.load_frame(page_idx) else {
+                self.stack.clear();
+                return;
+            };
+            self.stack.push(frame);
+            let depth = self.stack.len() - 1;
+            // binary search for the first cell whose key is >= rowid, in interior pages that is
+            // the cell whose left subtree holds the rowid
+            let (mut lo, mut hi) = (0, self.stack[depth].pointers.len());
+            while lo < hi {
+                let mid = (lo + hi) / 2;
+                let Ok(cell) = self.read_key(depth, mid) else {
+                    self.stack.clear();
+                    return;
+                };
+                if cell.rowid < rowid {
+                    lo = mid + 1;
+                } else {
+                    hi = mid;
+                }
+            }
+            if self.stack[depth].page_type == BTreePageType::LeafTable {
+                self.stack[depth].pos = lo;
+                return;
+            }
+            self.stack[depth].pos = lo + 1;
+            match self.child_of(depth, lo) {
+                Ok(child) => page_idx = child,
+                Err(()) => {
+                    self.stack.clear();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn advance(&mut self) -> Option<()> {
+        loop {
+            let depth = self.stack.len().checked_sub(1)?;
+            let frame = &mut self.stack[depth];
+            let (pos, n) = (frame.pos, frame.pointers.len());
+            frame.pos += 1;
+            match frame.page_type {
+                BTreePageType::InteriorTable if pos <= n => {
+                    let frame = self.child_of(depth, pos).and_then(|c| self.load_frame(c));
+                    let Ok(frame) = frame else {
+                        self.stack.clear();
+                        return None;
+                    };
+                    self.stack.push(frame);
+                }
+                BTreePageType::LeafTable if pos < n => {
+                    let Ok((cell, record)) = self.read_key(depth, pos).and_then(|mut cell| {
+                        // the header is left in the cell unless it is huge
+                        let record = match LazyRecord::new(&cell.payload) {
+                            Ok(record) => record,
+                            Err(_) => {
+                                cell.read_overflow(self.supplier)?;
+                                LazyRecord::new(&cell.payload).map_err(|e| eprintln!("{e}"))?
+                            }
+                        };
+                        Ok((cell, record))
+                    }) else {
+                        self.stack.clear();
+                        return None;
+                    };
+                    self.rowid = DbValue::Integer(cell.rowid);
+                    self.cell = Some(cell);
+                    self.record = record;
+                    return Some(());
+                }
+                _ => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+
+    /// The raw record of the current row, overflow pages included
+    pub(crate) fn payload(&mut self) -> Result<&[u8], ()> {
+        let cell = self.cell.as_mut().ok_or(())?;
+        cell.read_overflow(self.supplier)?;
+        Ok(&cell.payload)
+    }
+
+    /// The current row, as wide as the `width` columns of the table, with only the columns in
+    /// `wanted` decoded (all of them when None) and NULL in place of the others
+    pub(crate) fn row(&mut self, width: usize, wanted: Option<&[usize]>) -> Row {
+        let row = (0..width)
+            .map(|col| match wanted {
+                Some(wanted) if !wanted.contains(&col) => DbValue::Null,
+                // columns added after the row was written are NULL
+                _ => self.column(col).unwrap_or(DbValue::Null),
+            })
+            .collect();
+        Row::new(self.rowid.clone(), row)
+    }
+}
+
+impl<'a, T: PageSupplier> RowCursor for TableCursor<'a, T> {
+    fn rowid(&self) -> &DbValue {
+        &self.rowid
+    }
+
+    fn column(&mut self, colpos: usize) -> Option<DbValue> {
+        let span = self.record.span(colpos)?;
+        let cell = self.cell.as_mut()?;
+        cell.read_overflow_to(self.supplier, span.end).ok()?;
+        let encoding = self.supplier.text_encoding();
+        match self.record.read(colpos, &cell.payload, encoding) {
+            Ok(value) => Some(value.into()),
+            Err(e) => {
+                eprintln!("{e}");
+                None
+            }
+        }
+    }
+
+    fn next(&mut self) -> Option<()> {
+        self.advance()?;
+        let rowid = match self.rowid {
+            DbValue::Integer(rowid) => rowid,
+            _ => unreachable!("table rows always have an integer rowid"),
+        };
+        let past_end = match self.end {
+            Bound::Included(end) => rowid > end,
+            Bound::Excluded(end) => rowid >= end,
+            Bound::Unbounded => false,
+        };
+        if past_end {
+            self.stack.clear();
+            return None;
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+
+    use itertools::Itertools;
+
+    use super::*;
+    use crate::{database::DbAccess, sql::syntax::DbValue};
+
+    #[test]
+    fn test_overflow_payload() -> Result<(), anyhow::Error> {
+        // 512 bytes pages, so both long bodies spill over a chain of overflow pages
+        let mut db = DbAccess::new(File::open("fixtures/overflow.db")?)?;
+        let rows = BTreeTableReader {}
+            .find_all_in_table(2, &mut db)
+            .map_err(|_| anyhow::anyhow!("could not scan table"))?;
+        let bodies: Vec<String> = rows
+            .iter()
+            .map(
+                |(_, payload)| match Record::read_row(payload.as_slice(), TextEncoding::UTF8) {
+                    Ok(mut r) => r.remove(1).to_string(),
+                    Err(e) => panic!("{e}"),
+                },
+            )
+            .collect();
+        assert_eq!(bodies.len(), 3);
+        assert_eq!(bodies[0], (1..=1000).join(","));
+        assert_eq!(bodies[1], "short");
+        assert_eq!(bodies[2], (1..=300).join("-"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_lazy_columns() -> Result<(), anyhow::Error> {
+        // same table as test_overflow_payload, the chains are only followed for the body
+        let mut db = DbAccess::new(File::open("fixtures/overflow.db")?)?;
+        let mut cursor = TableCursor::new(&mut db, 2);
+        let mut spilled = 0;
+        while cursor.next().is_some() {
+            // the id is an alias of the rowid, so the record holds NULL in its place
+            assert_eq!(cursor.column(0), Some(DbValue::Null));
+            let cell = cursor.cell.as_ref().unwrap();
+            if cell.payload.len() < cell.payload_size {
+                spilled += 1;
+                assert!(cell.first_overflow.is_some());
+                let body = cursor.column(1).unwrap();
+                assert!(
+                    matches!(body, DbValue::Text(s) if s.ends_with("300") || s.ends_with("1000"))
+                );
+                let cell = cursor.cell.as_ref().unwrap();
+                assert_eq!(
+                    (cell.payload.len(), cell.first_overflow),
+                    (cell.payload_size, None)
+                );
+            }
+        }
+        assert_eq!(spilled, 2);
+
+        let misses = |query: &str| -> Result<u64, anyhow::Error> {
+            let mut db = DbAccess::open(File::open("fixtures/overflow.db")?, &Default::default())?;
+            db.run_query(query);
+            Ok(db.cache_stats().unwrap().misses)
+        };
+        // the two long bodies leave 340 and 79 bytes in their cells, the rest takes 7 and 2
+        // overflow pages holding 508 bytes each
+        assert_eq!(
+            misses("SELECT body FROM docs")? - misses("SELECT id FROM docs")?,
+            9
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserved_bytes() -> Result<(), anyhow::Error> {
+        // reserved.db has 512 bytes pages with 32 of them reserved, and holds
+        // t(id INTEGER PRIMARY KEY, body TEXT) indexed on body, with 60 rows whose bodies are
+        // printf('%d ', id) followed by the first (id * 13) % 900 chars of 'abab...'
+        let mut db = DbAccess::new(File::open("fixtures/reserved.db")?)?;
+        assert_eq!(db.header.reserved_size, 32);
+        assert_eq!(db.header.usable_size(), 480);
+        let body = |i: usize| format!("{i} {}", "ab".repeat(450)[..(i * 13) % 900].to_owned());
+        let rows = db.run_query("SELECT body FROM t").swap_remove(0).rows;
+        let bodies: Vec<DbValue> = rows.into_iter().flat_map(|r| r.row).collect();
+        assert_eq!(
+            bodies,
+            (1..=60).map(|i| DbValue::Text(body(i))).collect::<Vec<_>>()
+        );
+        let query = format!("SELECT id FROM t WHERE body = '{}'", body(57));
+        let rows = db.run_query(&query).swap_remove(0).rows;
+        assert_eq!(rows.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_payload_size() {
+        let limits = PayloadLimits::new(512, 64, 32, 32);
+        assert_eq!(limits.local_size(&BTreePageType::LeafTable, 477), 477);
+        // the surplus does not fit, only the minimum stays on the page
+        assert_eq!(limits.local_size(&BTreePageType::LeafTable, 478), 39);
+        assert_eq!(
+            limits.local_size(&BTreePageType::LeafTable, 600),
+            39 + (600 - 39) % 508
+        );
+        assert_eq!(limits.local_size(&BTreePageType::LeafIndex, 102), 102);
+        assert_eq!(limits.local_size(&BTreePageType::LeafIndex, 3892), 39);
+    }
+
+    // fixtures/index.db holds 2000 rows in people(id INTEGER PRIMARY KEY, name TEXT, age INTEGER)
+    // with name = printf('name%05d', (id*7919)%2000) and age = id%97, indexed on age (page 3) and
+    // name (page 4), pages are 512 bytes so that the indices are a few levels deep
+    fn people() -> impl Iterator<Item = (i64, String, i64)> {
+        (1..=2000).map(|i| (i, format!("name{:05}", (i * 7919) % 2000), i % 97))
+    }
+
+    #[test]
+    fn test_index_scan() -> Result<(), anyhow::Error> {
+        let mut db = DbAccess::new(File::open("fixtures/index.db")?)?;
+        let mut cursor = IndexCursor::new(&mut db, 3);
+        let mut entries = Vec::new();
+        while cursor.next().is_some() {
+            entries.push((cursor.key().to_vec(), cursor.rowid().clone()));
+        }
+        let expected: Vec<_> = people()
+            .map(|(id, _, age)| (age, id))
+            .sorted()
+            .map(|(age, id)| (vec![DbValue::Integer(age)], DbValue::Integer(id)))
+            .collect();
+        assert_eq!(entries, expected);
+
+        let mut cursor = IndexCursor::new(&mut db, 4);
+        let mut names = Vec::new();
+        while cursor.next().is_some() {
+            names.push(cursor.column(0));
+        }
+        let expected: Vec<_> = people()
+            .map(|(_, name, _)| Some(DbValue::Text(name)))
+            .sorted_by(|a, b| a.partial_cmp(b).unwrap())
+            .collect();
+        assert_eq!(names, expected);
+        Ok(())
+    }
+
+    // fixtures/deleted.db: t(id INTEGER PRIMARY KEY, val TEXT) filled with printf('value %d', id*id)
+    // for ids up to 3000, then every id multiple of 3 and the ids between 1000 and 2000 were
+    // deleted, leaving freeblocks around and emptied pages on the freelist
+    fn survivors() -> impl Iterator<Item = (i64, String)> {
+        (1..=3000)
+            .filter(|i| i % 3 != 0 && !(1000..=2000).contains(i))
+            .map(|i| (i, format!("value {}", i * i)))
+    }
+
+    #[test]
+    fn test_scan_multilevel() -> Result<(), anyhow::Error> {
+        let reader = BTreeTableReader {};
+        let mut db = DbAccess::new(File::open("fixtures/index.db")?)?;
+        // three levels deep, the right-most pointers hold the last rows
+        let rows = reader
+            .find_all_in_table(2, &mut db)
+            .map_err(|_| anyhow::anyhow!("could not scan table"))?;
+        assert_eq!(
+            rows.iter().map(|r| r.0).collect_vec(),
+            (1..=2000).collect_vec()
+        );
+        let rows = reader
+            .scan_table(2, &mut db, &|rowid, _| rowid % 100 == 0)
+            .map_err(|_| anyhow::anyhow!("could not scan table"))?;
+        assert_eq!(rows.len(), 20);
+
+        let mut db = DbAccess::new(File::open("fixtures/deleted.db")?)?;
+        let rows = reader
+            .find_all_in_table(2, &mut db)
+            .map_err(|_| anyhow::anyhow!("could not scan table"))?;
+        let rows = rows
+            .into_iter()
+            .map(|(rowid, payload)| {
+                match Record::read_row(payload.as_slice(), TextEncoding::UTF8) {
+                    Ok(mut r) => (rowid, r.remove(1).to_string()),
+                    Err(e) => panic!("{e}"),
+                }
+            })
+            .collect_vec();
+        assert_eq!(rows, survivors().collect_vec());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cursor_multilevel() -> Result<(), anyhow::Error> {
+        let mut db = DbAccess::new(File::open("fixtures/index.db")?)?;
+        let mut cursor = Cursor::new(&mut db, 2);
+        let mut rows = Vec::new();
+        while cursor.next().is_some() {
+            rows.push((cursor.rowid().clone(), cursor.column(2)));
+        }
+        let expected = people()
+            .map(|(id, _, age)| (DbValue::Integer(id), Some(DbValue::Integer(age))))
+            .collect_vec();
+        assert_eq!(rows, expected);
+
+        let mut db = DbAccess::new(File::open("fixtures/deleted.db")?)?;
+        let mut cursor = Cursor::new(&mut db, 2);
+        let mut rows = Vec::new();
+        while cursor.next().is_some() {
+            rows.push((cursor.rowid().clone(), cursor.column(1)));
+        }
+        let expected = survivors()
+            .map(|(id, val)| (DbValue::Integer(id), Some(DbValue::Text(val))))
+            .collect_vec();
+        assert_eq!(rows, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_cursor() -> Result<(), anyhow::Error> {
+        let mut db = DbAccess::new(File::open("fixtures/index.db")?)?;
+        let mut cursor = TableCursor::new(&mut db, 2);
+        let mut rows = Vec::new();
+        while cursor.next().is_some() {
+            rows.push((cursor.rowid().clone(), cursor.column(1)));
+        }
+        let expected = people()
+            .map(|(id, name, _)| (DbValue::Integer(id), Some(DbValue::Text(name))))
+            .collect_vec();
+        assert_eq!(rows, expected);
+
+        let mut cursor = TableCursor::new(&mut db, 2);
+        cursor.seek(1999);
+        assert!(cursor.next().is_some());
+        assert_eq!(cursor.rowid(), &DbValue::Integer(1999));
+        cursor.seek(0);
+        assert!(cursor.next().is_some());
+        assert_eq!(cursor.rowid(), &DbValue::Integer(1));
+        cursor.seek(2001);
+        assert!(cursor.next().is_none());
+
+        let mut cursor = TableCursor::range(&mut db, 2, Bound::Excluded(10), Bound::Included(20));
+        let mut ids = Vec::new();
+        while cursor.next().is_some() {
+            ids.push(cursor.rowid().clone());
+        }
+        assert_eq!(ids, (11..=20).map(DbValue::Integer).collect_vec());
+        let reader = BTreeTableReader {};
+        assert!(reader.find_by_rowid(2, 1234, &mut db).unwrap().is_some());
+        assert!(reader.find_by_rowid(2, 0, &mut db).unwrap().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_seek() -> Result<(), anyhow::Error> {
+        let mut db = DbAccess::new(File::open("fixtures/index.db")?)?;
+        let key = [DbValue::Integer(50)];
+        let mut cursor = IndexCursor::new(&mut db, 3);
+        cursor.seek(&key);
+        assert!(cursor.next().is_some());
+        assert_eq!(cursor.key(), key);
+        assert_eq!(cursor.rowid(), &DbValue::Integer(50));
+
+        let (lo, hi) = ([DbValue::Integer(50)], [DbValue::Integer(52)]);
+        let collect = |mut cursor: IndexCursor<DbAccess>| {
+            let mut ids = Vec::new();
+            while cursor.next().is_some() {
+                if let DbValue::Integer(id) = cursor.rowid() {
+                    ids.push(*id);
+                }
+            }
+            ids.sort();
+            ids
+        };
+        let expected = |f: &dyn Fn(i64) -> bool| {
+            people()
+                .filter(|(_, _, age)| f(*age))
+                .map(|(id, _, _)| id)
+                .collect_vec()
+        };
+        let range = IndexCursor::range(&mut db, 3, Bound::Included(&lo), Bound::Included(&hi));
+        assert_eq!(collect(range), expected(&|a| (50..=52).contains(&a)));
+        let range = IndexCursor::range(&mut db, 3, Bound::Excluded(&lo), Bound::Excluded(&hi));
+        assert_eq!(collect(range), expected(&|a| a == 51));
+        let range = IndexCursor::range(&mut db, 3, Bound::Unbounded, Bound::Excluded(&lo));
+        assert_eq!(collect(range), expected(&|a| a < 50));
+
+        let name = [DbValue::Text("name01234".to_owned())];
+        let range = IndexCursor::range(&mut db, 4, Bound::Included(&name), Bound::Included(&name));
+        let ids = people().filter(|(_, n, _)| n == "name01234").map(|p| p.0);
+        assert_eq!(collect(range), ids.collect_vec());
+        Ok(())
+    }
+}
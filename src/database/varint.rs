@@ -1,4 +1,7 @@
-use std::{fmt::Display, io::Read};
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+};
 
 use thiserror::Error;
 
@@ -36,9 +39,14 @@ impl Varint {
         let mut counter = 1;
         while let Ok(()) = r.read_exact(&mut buf) {
             let ptr = &buf[0];
+            if counter == 9 {
+                // the ninth byte holds 8 bits instead of 7
+                res = (res << 8) | *ptr as u64;
+                return Ok((Varint(res as i64), counter));
+            }
             res <<= 7;
             res |= (*ptr & 0x7F) as u64;
-            if *ptr & 0x80 == 0 || counter == 9 {
+            if *ptr & 0x80 == 0 {
                 return Ok((Varint(res as i64), counter));
             }
             counter += 1;
@@ -48,10 +56,41 @@ impl Varint {
     pub(crate) fn read(r: impl Read) -> Result<Varint, VarintError> {
         Self::read_sized(r).map(|v| v.0)
     }
+
+    /// Writes the shortest encoding of the value, returns the number of bytes it took
+    pub(crate) fn write(&self, mut w: impl Write) -> io::Result<usize> {
+        let mut value = self.0 as u64;
+        let mut buf = [0; 9];
+        let len = if value >> 56 != 0 {
+            // the ninth byte holds 8 bits instead of 7
+            buf[8] = value as u8;
+            value >>= 8;
+            for b in buf[..8].iter_mut().rev() {
+                *b = (value & 0x7f) as u8 | 0x80;
+                value >>= 7;
+            }
+            9
+        } else {
+            let mut len = 0;
+            loop {
+                buf[len] = (value & 0x7f) as u8 | 0x80;
+                len += 1;
+                value >>= 7;
+                if value == 0 {
+                    break;
+                }
+            }
+            buf[..len].reverse();
+            buf[len - 1] &= 0x7f;
+            len
+        };
+        w.write_all(&buf[..len])?;
+        Ok(len)
+    }
 }
 
 #[cfg(test)]
-mod test {
+pub(super) mod test {
     use crate::database::varint::Varint;
 
     #[test]
@@ -70,4 +109,45 @@ mod test {
         assert_eq!(Varint(268435455), Varint::read(ar.as_slice())?);
         Ok(())
     }
+
+    /// Tiny xorshift generator, enough to throw a few thousand values at the encoder
+    pub(crate) struct Rng(pub u64);
+    impl Rng {
+        pub(crate) fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        /// A value whose magnitude is spread over every byte length
+        pub(crate) fn int(&mut self) -> i64 {
+            let bits = self.next() % 65;
+            (self.next().checked_shr(64 - bits as u32).unwrap_or(0)) as i64
+        }
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<(), anyhow::Error> {
+        let mut values = vec![0, 1, -1, 127, 128, 16383, 16384, i64::MIN, i64::MAX];
+        for shift in [7, 14, 56, 62] {
+            values.extend([(1 << shift) - 1, 1 << shift, -(1 << shift)]);
+        }
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+        values.extend((0..10000).map(|_| rng.int()));
+        for v in values {
+            let mut buf = Vec::new();
+            let len = Varint(v).write(&mut buf)?;
+            assert_eq!(len, buf.len());
+            // 7 bits per byte, except for the ninth byte which takes 8
+            let bits = 64 - (v as u64).leading_zeros() as usize;
+            let expected = if bits > 56 {
+                9
+            } else {
+                bits.div_ceil(7).max(1)
+            };
+            assert_eq!(len, expected, "{v}");
+            assert_eq!(Varint::read_sized(buf.as_slice())?, (Varint(v), len), "{v}");
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,327 @@
+use std::{collections::HashMap, fs::File, os::unix::fs::FileExt};
+
+use anyhow::{bail, Result};
+use thiserror::Error;
+
+use super::{
+    page::PayloadLimits,
+    tree::{PageConsumer, PageSupplier, SharedPage},
+};
+
+const WAL_HEADER_SIZE: usize = 32;
+const FRAME_HEADER_SIZE: usize = 24;
+
+#[derive(Error, Debug)]
+pub(crate) enum WalError {
+    #[error("invalid wal magic number {0:#x}")]
+    InvalidMagic(u32),
+    #[error("unsupported wal format version {0}")]
+    UnsupportedVersion(u32),
+    #[error("wal page size {wal} does not match the database page size {db}")]
+    PageSizeMismatch { wal: usize, db: usize },
+    #[error("wal header checksum mismatch")]
+    HeaderChecksum,
+}
+
+/// Running checksum used by both the wal header and the frames, see section 4.1 of the file
+/// format docs
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Checksum(u32, u32);
+
+impl Checksum {
+    fn update(self, data: &[u8], big_endian: bool) -> Self {
+        let Checksum(mut s0, mut s1) = self;
+        for words in data.chunks_exact(8) {
+            let word = |b: &[u8]| {
+                let b = [b[0], b[1], b[2], b[3]];
+                if big_endian {
+                    u32::from_be_bytes(b)
+                } else {
+                    u32::from_le_bytes(b)
+                }
+            };
+            s0 = s0.wrapping_add(word(&words[..4])).wrapping_add(s1);
+            s1 = s1.wrapping_add(word(&words[4..])).wrapping_add(s0);
+        }
+        Checksum(s0, s1)
+    }
+
+    fn read(b: &[u8]) -> Self {
+        Checksum(be_u32(&b[..4]), be_u32(&b[4..8]))
+    }
+}
+
+fn be_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+/// Serves the pages whose latest committed version lives in the write ahead log, everything
+/// else is read from the database file through `inner`
+pub(crate) struct WalReader<S> {
+    inner: S,
+    wal: File,
+    /// page number => offset of the page content of its latest committed frame
+    frames: HashMap<usize, u64>,
+    /// size of the database in pages after the last commit
+    db_size: usize,
+    page: Vec<u8>,
+    /// whether `page` holds the current page, or it has to be asked to `inner`
+    from_wal: bool,
+    /// why the whole wal was left out, when its header could not be trusted
+    ignored: Option<WalError>,
+    /// number of frames past the last commit, which were left out
+    uncommitted: usize,
+}
+
+impl<S: PageSupplier<Error = anyhow::Error>> WalReader<S> {
+    /// Reads the wal, keeping only the frames belonging to committed transactions. Frames are
+    /// valid as long as their salts match the header and the running checksum adds up, the first
+    /// one that does not ends the log. A wal without a valid header holds no frames at all, like
+    /// the empty one a checkpoint in TRUNCATE mode leaves behind
+    pub(crate) fn new(inner: S, wal: File, page_size: usize) -> Result<Self> {
+        let mut reader = Self {
+            inner,
+            wal,
+            frames: HashMap::new(),
+            db_size: 0,
+            page: vec![0; page_size],
+            from_wal: false,
+            ignored: None,
+            uncommitted: 0,
+        };
+        let wal_size = reader.wal.metadata()?.len();
+        if wal_size < WAL_HEADER_SIZE as u64 {
+            return Ok(reader);
+        }
+        let mut header = [0; WAL_HEADER_SIZE];
+        reader.wal.read_exact_at(&mut header, 0)?;
+        let magic = be_u32(&header);
+        if magic & !1 != 0x377f0682 {
+            reader.ignored = Some(WalError::InvalidMagic(magic));
+            return Ok(reader);
+        }
+        let big_endian = magic & 1 == 1;
+        let mut checksum = Checksum::default().update(&header[..24], big_endian);
+        if checksum != Checksum::read(&header[24..]) {
+            reader.ignored = Some(WalError::HeaderChecksum);
+            return Ok(reader);
+        }
+        let version = be_u32(&header[4..]);
+        if version != 3007000 {
+            bail!(WalError::UnsupportedVersion(version));
+        }
+        let wal_page_size = be_u32(&header[8..]) as usize;
+        if wal_page_size != page_size {
+            bail!(WalError::PageSizeMismatch {
+                wal: wal_page_size,
+                db: page_size
+            });
+        }
+        let salts = &header[16..24];
+
+        let frame_size = (FRAME_HEADER_SIZE + page_size) as u64;
+        let mut frame = vec![0; FRAME_HEADER_SIZE + page_size];
+        // frames of the transaction we are reading, they only count once it commits
+        let mut pending = Vec::new();
+        let mut offset = WAL_HEADER_SIZE as u64;
+        while offset + frame_size <= wal_size {
+            reader.wal.read_exact_at(&mut frame, offset)?;
+            if &frame[8..16] != salts {
+                break;
+            }
+            checksum = checksum
+                .update(&frame[..8], big_endian)
+                .update(&frame[FRAME_HEADER_SIZE..], big_endian);
+            if checksum != Checksum::read(&frame[16..]) {
+                break;
+            }
+            let page_number = be_u32(&frame) as usize;
+            pending.push((page_number, offset + FRAME_HEADER_SIZE as u64));
+            let commit_size = be_u32(&frame[4..]) as usize;
+            if commit_size != 0 {
+                reader.frames.extend(pending.drain(..));
+                reader.db_size = commit_size;
+            }
+            offset += frame_size;
+        }
+        reader.uncommitted = pending.len();
+        Ok(reader)
+    }
+
+    /// What was left out of the wal when it was read, for the caller to tell the user about
+    pub(crate) fn notice(&self) -> Option<String> {
+        match (&self.ignored, self.uncommitted) {
+            (Some(e), _) => Some(format!("ignoring the wal: {e}")),
+            (None, 0) => None,
+            (None, n) => Some(format!(
+                "ignoring {n} wal frames of an uncommitted transaction"
+            )),
+        }
+    }
+}
+
+impl<S: PageSupplier<Error = anyhow::Error>> PageSupplier for WalReader<S> {
+    type Error = anyhow::Error;
+
+    fn move_to_page(&mut self, page_idx: usize) -> Result<()> {
+        if self.db_size != 0 && page_idx > self.db_size {
+            bail!("page {page_idx} is past the end of the database");
+        }
+        match self.frames.get(&page_idx) {
+            Some(&offset) => {
+                self.wal.read_exact_at(&mut self.page, offset)?;
+                self.from_wal = true;
+            }
+            None => {
+                self.inner.move_to_page(page_idx)?;
+                self.from_wal = false;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_page(&mut self, page_idx: usize) -> Result<&[u8]> {
+        self.move_to_page(page_idx)?;
+        let offset = if page_idx == 1 { 100 } else { 0 };
+        Ok(&self.page()[offset..])
+    }
+
+    fn page(&self) -> &[u8] {
+        if self.from_wal {
+            &self.page
+        } else {
+            self.inner.page()
+        }
+    }
+
+    fn payload_limits(&self) -> PayloadLimits {
+        self.inner.payload_limits()
+    }
+
+    fn shared_page(&mut self, page_idx: usize) -> Result<SharedPage> {
+        if self.frames.contains_key(&page_idx) {
+            self.move_to_page(page_idx)?;
+            Ok(SharedPage::from(self.page.as_slice()))
+        } else {
+            self.inner.shared_page(page_idx)
+        }
+    }
+
+    /// The size recorded by the last commit in the wal, when there is one
+    fn db_size(&self) -> Option<usize> {
+        match self.db_size {
+            0 => self.inner.db_size(),
+            size => Some(size),
+        }
+    }
+}
+
+impl<S> PageConsumer for WalReader<S> {
+    type Error = anyhow::Error;
+
+    fn write_page(&mut self, _page_idx: usize, _page: &[u8]) -> Result<()> {
+        bail!("writing to a database in WAL mode is not supported")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, io::Write, path::PathBuf};
+
+    use super::*;
+    use crate::{
+        database::{DbAccess, OpenOptions},
+        sql::syntax::DbValue,
+    };
+
+    // wal.db holds kv(k INTEGER PRIMARY KEY, v TEXT) with rows 1..=200 set to 'old k', while
+    // wal.db-wal sets every tenth row to 'new k', adds rows 201..=400 and creates another table
+    fn values(db: &mut DbAccess, query: &str) -> Vec<DbValue> {
+        db.run_query(query)
+            .swap_remove(0)
+            .rows
+            .into_iter()
+            .flat_map(|r| r.row)
+            .collect()
+    }
+
+    #[test]
+    fn test_wal_overlay() -> Result<()> {
+        let mut db = DbAccess::open_path("fixtures/wal.db", &OpenOptions::default())?;
+        let rows = values(&mut db, "SELECT v FROM kv");
+        assert_eq!(rows.len(), 400);
+        assert_eq!(rows[9], DbValue::Text("new 10".to_string()));
+        assert_eq!(rows[10], DbValue::Text("old 11".to_string()));
+        assert_eq!(rows[399], DbValue::Text("new 400".to_string()));
+        assert_eq!(
+            values(&mut db, "SELECT x FROM later"),
+            [DbValue::Text("only in the wal".to_string())]
+        );
+        // without the wal only the checkpointed rows are there
+        let mut db = DbAccess::new(File::open("fixtures/wal.db")?)?;
+        assert_eq!(values(&mut db, "SELECT v FROM kv").len(), 200);
+        Ok(())
+    }
+
+    /// Copies wal.db somewhere else, along with the wal as modified by `f`
+    fn with_wal(name: &str, f: impl FnOnce(&mut Vec<u8>)) -> Result<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("wal_test_{}_{name}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("wal.db");
+        fs::copy("fixtures/wal.db", &path)?;
+        let mut wal = fs::read("fixtures/wal.db-wal")?;
+        f(&mut wal);
+        fs::File::create(dir.join("wal.db-wal"))?.write_all(&wal)?;
+        Ok(path)
+    }
+
+    #[test]
+    fn test_wal_invalid_frames() -> Result<()> {
+        // break the content of the last frame, the transaction it commits is lost
+        let path = with_wal("corrupted", |wal| {
+            let last = wal.len() - 1;
+            wal[last] ^= 0xff;
+        })?;
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(values(&mut db, "SELECT v FROM kv").len(), 400);
+        assert_eq!(values(&mut db, "SELECT x FROM later"), []);
+
+        // only the first transaction (the update) and part of the second one made it to disk
+        let path = with_wal("truncated", |wal| {
+            wal.truncate(WAL_HEADER_SIZE + 8 * (FRAME_HEADER_SIZE + 512))
+        })?;
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        let rows = values(&mut db, "SELECT v FROM kv");
+        assert_eq!(rows.len(), 200);
+        assert_eq!(rows[9], DbValue::Text("new 10".to_string()));
+        assert!(matches!(
+            db.notices(),
+            [notice] if notice.ends_with("wal frames of an uncommitted transaction")
+        ));
+
+        Ok(())
+    }
+
+    type Corrupt = fn(&mut Vec<u8>);
+
+    #[test]
+    fn test_wal_without_header() -> Result<()> {
+        // a wal whose header cannot be trusted holds no frames, the database file is all there is.
+        // Only a wal that is not empty yet not one sqlite wrote is worth telling about
+        let cases: [(&str, Corrupt, bool); 4] = [
+            ("empty", Vec::clear, false),
+            ("short", |wal| wal.truncate(WAL_HEADER_SIZE - 1), false),
+            ("magic", |wal| wal[0] ^= 0xff, true),
+            ("checksum", |wal| wal[20] ^= 0xff, true),
+        ];
+        for (name, f, notice) in cases {
+            let path = with_wal(name, f)?;
+            let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+            let rows = values(&mut db, "SELECT v FROM kv");
+            assert_eq!(rows.len(), 200, "{name}");
+            assert_eq!(rows[9], DbValue::Text("old 10".to_string()), "{name}");
+            assert_eq!(db.notices().len(), notice as usize, "{name}");
+        }
+        Ok(())
+    }
+}
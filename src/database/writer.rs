@@ -0,0 +1,1318 @@
+use std::{cmp::Ordering, collections::HashMap, mem};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::{
+    freelist::Freelist,
+    header::{BTreeHeader, BTreePageType, Header},
+    ptrmap::{PointerMap, PtrmapEntry},
+    record::Record,
+    tree::{compare_index_keys, read_page_layout, Cell, Pager},
+    varint::Varint,
+};
+use crate::sql::syntax::DbValue;
+
+/// How entries are ordered in the b-tree being modified
+pub(crate) enum Key<'k> {
+    Rowid(i64),
+    /// the whole index entry (rowid included), along with the sort order of its columns
+    Index(&'k [DbValue], &'k [bool]),
+}
+
+/// Index entries on either side of the place a key would take, see [`BTreeWriter::neighbours`]
+pub(crate) struct Neighbours {
+    pub(crate) before: Option<Vec<DbValue>>,
+    pub(crate) after: Option<Vec<DbValue>>,
+}
+
+/// A b-tree page taken apart into its cells, which is easier to modify than the page itself
+struct Node {
+    idx: usize,
+    typ: BTreePageType,
+    /// cells as stored on the page, without the left child pointer of interior pages
+    cells: Vec<Vec<u8>>,
+    /// left child of each cell followed by the right-most pointer, empty for leaves
+    children: Vec<u32>,
+}
+
+impl Node {
+    /// An empty page that does not have a page number yet
+    fn new(typ: BTreePageType) -> Self {
+        Self {
+            idx: 0,
+            typ,
+            cells: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        matches!(
+            self.typ,
+            BTreePageType::LeafIndex | BTreePageType::LeafTable
+        )
+    }
+
+    fn header_size(&self) -> usize {
+        if self.is_leaf() {
+            8
+        } else {
+            12
+        }
+    }
+
+    /// Bytes taken on the page by the cell, its pointer included
+    fn cell_size(&self, cell: &[u8]) -> usize {
+        2 + cell.len() + if self.is_leaf() { 0 } else { 4 }
+    }
+
+    fn fits(&self, usable_size: usize) -> bool {
+        let offset = if self.idx == 1 { 100 } else { 0 };
+        let cells: usize = self.cells.iter().map(|c| self.cell_size(c)).sum();
+        offset + self.header_size() + cells <= usable_size
+    }
+}
+
+/// A level of a b-tree built by [`BTreeWriter::build`]
+struct Level {
+    /// page being filled
+    node: Node,
+    /// the last page that filled up, with the cell that did not fit in it
+    full: Option<(Node, Vec<u8>)>,
+}
+
+impl Level {
+    fn new(typ: BTreePageType) -> Self {
+        Self {
+            node: Node::new(typ),
+            full: None,
+        }
+    }
+}
+
+/// Where a key belongs in a b-tree, as found by [`BTreeWriter::descend`]
+struct Position {
+    /// interior pages from the root down, each with the child that was followed
+    path: Vec<(Node, usize)>,
+    leaf: Node,
+    /// cell of the leaf the key goes before
+    pos: usize,
+    /// whether the key is already there
+    found: bool,
+    /// closest cells on either side of the key, wherever they are in the tree
+    before: Option<Vec<u8>>,
+    after: Option<Vec<u8>>,
+}
+
+/// Interior pages deeper than this mean the tree loops back on itself
+const MAX_DEPTH: usize = 64;
+
+/// Inserts, updates and deletes entries of table and index b-trees, splitting and merging pages
+/// as they fill up and empty. Pages are written as soon as they are modified, new ones come from
+/// the freelist or are appended to the file, all of it counted in `header`, which is up to the
+/// caller to write back
+pub(crate) struct BTreeWriter<'a, P> {
+    pager: &'a mut P,
+    header: &'a mut Header,
+}
+
+impl<'a, P: Pager> BTreeWriter<'a, P> {
+    pub(crate) fn new(pager: &'a mut P, header: &'a mut Header) -> Self {
+        Self { pager, header }
+    }
+
+    /// Starts a new, empty b-tree whose root is a page of type `typ`, returns its page number.
+    /// The roots of an auto-vacuum database all come before any other page, so the new one takes
+    /// the page right after the last of them, and whatever was there moves: `roots` are those of
+    /// every b-tree in the database, to find out what that is
+    pub(crate) fn create(&mut self, typ: BTreePageType, roots: &[usize]) -> Result<usize> {
+        let idx = match self.pointer_map() {
+            None => self.allocate()?,
+            Some(map) => {
+                let mut idx = self.header.largest_root_btree_page as usize + 1;
+                while map.is_map_page(idx) {
+                    idx += 1;
+                }
+                self.vacate(idx, roots)?;
+                self.header.largest_root_btree_page = idx as u32;
+                idx
+            }
+        };
+        let root = Node {
+            idx,
+            typ,
+            cells: Vec::new(),
+            children: Vec::new(),
+        };
+        self.store(&root)?;
+        Ok(root.idx)
+    }
+
+    /// Where the pointer map is, for auto-vacuum databases
+    fn pointer_map(&self) -> Option<PointerMap> {
+        (self.header.largest_root_btree_page != 0)
+            .then(|| PointerMap::new(self.header.usable_size()))
+    }
+
+    /// Makes page `idx` of an auto-vacuum database available: a free page leaves the freelist,
+    /// the content of a page in use is copied to a new one, which takes its place in the b-tree
+    /// or overflow chain it belongs to
+    fn vacate(&mut self, idx: usize, roots: &[usize]) -> Result<()> {
+        let size = self.header.size_in_pages as usize;
+        if idx > size {
+            // only pointer map pages come in between
+            for map_page in size + 1..idx {
+                self.pager
+                    .write_page(map_page, &vec![0; self.header.page_size as usize])?;
+            }
+            self.header.size_in_pages = idx as u32;
+            return Ok(());
+        }
+        let owners = self.page_owners(roots)?;
+        let Some(&entry) = owners.get(&idx) else {
+            // nobody uses it
+            return Ok(());
+        };
+        if entry == PtrmapEntry::Free {
+            let mut free: Vec<usize> = owners
+                .iter()
+                .filter(|&(&page, &entry)| entry == PtrmapEntry::Free && page != idx)
+                .map(|(&page, _)| page)
+                .collect();
+            free.sort_unstable();
+            // the freelist is laid out again without it
+            self.header.first_freelist_page = 0;
+            self.header.total_freelist = 0;
+            for page in free {
+                self.free(page)?;
+            }
+            return Ok(());
+        }
+        let moved = self.allocate()?;
+        self.pager.move_to_page(idx)?;
+        let content = self.pager.page().to_vec();
+        self.pager.write_page(moved, &content)?;
+        match entry {
+            PtrmapEntry::Child(parent) => {
+                let mut parent = self.load(parent as usize)?;
+                for child in parent.children.iter_mut().filter(|c| **c as usize == idx) {
+                    *child = moved as u32;
+                }
+                self.store(&parent)
+            }
+            PtrmapEntry::FirstOverflow(page) => {
+                let mut node = self.load(page as usize)?;
+                let limits = self.pager.payload_limits();
+                let typ = leaf_type(node.typ);
+                for cell in &mut node.cells {
+                    let first = Cell::read(&typ, cell.as_slice(), &limits)
+                        .map_err(|_| anyhow!("could not read cell"))?
+                        .first_overflow;
+                    if first == Some(idx) {
+                        // the page number of the chain ends the cell
+                        let len = cell.len();
+                        cell[len - 4..].copy_from_slice(&(moved as u32).to_be_bytes());
+                    }
+                }
+                self.store(&node)
+            }
+            PtrmapEntry::Overflow(previous) => {
+                let mut page = self.pager.read_page(previous as usize)?.to_vec();
+                page[..4].copy_from_slice(&(moved as u32).to_be_bytes());
+                self.pager.write_page(previous as usize, &page)
+            }
+            PtrmapEntry::Root | PtrmapEntry::Free => {
+                bail!("page {idx} comes after the last root page but is one itself")
+            }
+        }
+    }
+
+    /// What every page of the database is used for, walking the b-trees rooted at `roots` and
+    /// the freelist. Pointer map pages and pages nobody uses are left out
+    fn page_owners(&mut self, roots: &[usize]) -> Result<HashMap<usize, PtrmapEntry>> {
+        let mut owners = HashMap::new();
+        let mut pages: Vec<_> = roots.iter().map(|&r| (r, PtrmapEntry::Root)).collect();
+        while let Some((idx, entry)) = pages.pop() {
+            if owners.insert(idx, entry).is_some() {
+                bail!("page {idx} is used more than once");
+            }
+            let node = self.load(idx)?;
+            pages.extend(
+                node.children
+                    .iter()
+                    .map(|&child| (child as usize, PtrmapEntry::Child(idx as u32))),
+            );
+            if node.typ == BTreePageType::InteriorTable {
+                continue;
+            }
+            for cell in &node.cells {
+                let mut entry = PtrmapEntry::FirstOverflow(idx as u32);
+                for overflow in self.overflow_pages(leaf_type(node.typ), cell)? {
+                    if owners.insert(overflow, entry).is_some() {
+                        bail!("page {overflow} is used more than once");
+                    }
+                    entry = PtrmapEntry::Overflow(overflow as u32);
+                }
+            }
+        }
+        let freelist = Freelist::read(
+            self.pager,
+            self.header.first_freelist_page as usize,
+            self.header.total_freelist as usize,
+            self.header.size_in_pages as usize,
+        )?;
+        for page in freelist.trunks.into_iter().chain(freelist.leaves) {
+            if owners.insert(page, PtrmapEntry::Free).is_some() {
+                bail!("page {page} is used more than once");
+            }
+        }
+        Ok(owners)
+    }
+
+    /// Writes the pointer map of an auto-vacuum database again, from what the pages are used for
+    /// now: `roots` are those of every b-tree in the database. Does nothing for the others
+    pub(crate) fn update_pointer_map(&mut self, roots: &[usize]) -> Result<()> {
+        let Some(map) = self.pointer_map() else {
+            return Ok(());
+        };
+        let owners = self.page_owners(roots)?;
+        let size = self.header.size_in_pages as usize;
+        for map_page in map.map_pages(size) {
+            let mut page = vec![0; self.header.page_size as usize];
+            for idx in map.covered(map_page, size) {
+                if let Some(&entry) = owners.get(&idx) {
+                    map.write(&mut page, idx, entry);
+                }
+            }
+            // the pages that are right already stay out of the transaction
+            if self.pager.read_page(map_page).ok() != Some(page.as_slice()) {
+                self.pager.write_page(map_page, &page)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds a new b-tree out of `entries`, handed over in key order: the payload of each along
+    /// with its rowid for tables, None for indices. Pages are filled one after the other as far
+    /// as they go, rather than split in halves like inserts do, and written as soon as the next
+    /// one is started. `leaf` is the type of the leaves, returns the root page
+    pub(crate) fn build(
+        &mut self,
+        leaf: BTreePageType,
+        entries: impl IntoIterator<Item = Result<(Option<i64>, Vec<u8>)>>,
+    ) -> Result<usize> {
+        let mut levels = vec![Level::new(leaf)];
+        for entry in entries {
+            let (rowid, payload) = entry?;
+            let mut cell = Vec::new();
+            Varint(payload.len() as i64).write(&mut cell)?;
+            if let Some(rowid) = rowid {
+                Varint(rowid).write(&mut cell)?;
+            }
+            self.local_payload(&leaf, &payload, &mut cell)?;
+            self.append(&mut levels, 0, cell, None)?;
+        }
+        // what is left on each level becomes the right-most child of the page above
+        let mut child = None;
+        let mut level = 0;
+        while level < levels.len() {
+            if let Some((mut full, held)) = levels[level].full.take() {
+                // nothing came after the held cell, which ends the level instead of going up:
+                // the last cell of the full page takes its place, with its left child becoming
+                // the right-most pointer of the page
+                let left = full.children.pop();
+                let divider = full
+                    .cells
+                    .pop()
+                    .context("a page cannot be full of nothing")?;
+                full.idx = self.allocate()?;
+                self.store(&full)?;
+                self.append(&mut levels, level + 1, divider, Some(full.idx as u32))?;
+                levels[level].node.cells.push(held);
+                levels[level].node.children.extend(left);
+            }
+            let node = &mut levels[level].node;
+            node.children.extend(child);
+            node.idx = self.allocate()?;
+            self.store(node)?;
+            child = Some(node.idx as u32);
+            level += 1;
+        }
+        Ok(child.expect("there is at least the leaf level") as usize)
+    }
+
+    /// Adds `cell`, whose left child is `child` on interior pages, to the page being filled on
+    /// `level`. A table leaf that has no room left is written right away and a copy of its
+    /// largest rowid goes up, like [`BTreeWriter::split`] does. Any other page is held back
+    /// along with the cell that did not fit, which goes up once it is known not to be the last
+    fn append(
+        &mut self,
+        levels: &mut Vec<Level>,
+        level: usize,
+        cell: Vec<u8>,
+        child: Option<u32>,
+    ) -> Result<()> {
+        if levels.len() == level {
+            let typ = match levels[level - 1].node.typ {
+                BTreePageType::InteriorTable | BTreePageType::LeafTable => {
+                    BTreePageType::InteriorTable
+                }
+                BTreePageType::InteriorIndex | BTreePageType::LeafIndex => {
+                    BTreePageType::InteriorIndex
+                }
+            };
+            levels.push(Level::new(typ));
+        }
+        if let Some((mut full, held)) = levels[level].full.take() {
+            full.idx = self.allocate()?;
+            self.store(&full)?;
+            self.append(levels, level + 1, held, Some(full.idx as u32))?;
+        }
+        let usable_size = self.header.usable_size();
+        let node = &mut levels[level].node;
+        node.cells.push(cell);
+        node.children.extend(child);
+        if node.fits(usable_size) {
+            return Ok(());
+        }
+        let cell = node.cells.pop().expect("the cell was just pushed");
+        let mut full = mem::replace(node, Node::new(node.typ));
+        if full.typ == BTreePageType::LeafTable {
+            let mut divider = Vec::new();
+            let last = full
+                .cells
+                .last()
+                .context("a page cannot be full of nothing")?;
+            Varint(cell_rowid(full.typ, last)?).write(&mut divider)?;
+            full.idx = self.allocate()?;
+            self.store(&full)?;
+            levels[level].node.cells.push(cell);
+            return self.append(levels, level + 1, divider, Some(full.idx as u32));
+        }
+        // the left child of the held cell is the right-most pointer of the full page
+        levels[level].full = Some((full, cell));
+        Ok(())
+    }
+
+    /// Largest rowid of the table rooted at `root`, None when the table is empty
+    pub(crate) fn last_rowid(&mut self, root: usize) -> Result<Option<i64>> {
+        let mut node = self.load(root)?;
+        for _ in 0..MAX_DEPTH {
+            match node.children.last() {
+                Some(&right) => node = self.load(right as usize)?,
+                None => {
+                    return node
+                        .cells
+                        .last()
+                        .map(|cell| cell_rowid(node.typ, cell))
+                        .transpose()
+                }
+            }
+        }
+        bail!("b-tree rooted at page {root} is too deep")
+    }
+
+    /// Index entries right before and right after the place `key` would take
+    pub(crate) fn neighbours(&mut self, root: usize, key: &Key) -> Result<Neighbours> {
+        let position = self.descend(root, key)?;
+        let before = position
+            .before
+            .map(|cell| self.index_entry(&cell))
+            .transpose()?;
+        let after = position
+            .after
+            .map(|cell| self.index_entry(&cell))
+            .transpose()?;
+        Ok(Neighbours { before, after })
+    }
+
+    /// Adds an entry to the b-tree rooted at `root`: a row with the given rowid for tables, the
+    /// index entry `key` holds for indices. `payload` is the entry encoded as a record
+    pub(crate) fn insert(&mut self, root: usize, key: &Key, payload: &[u8]) -> Result<()> {
+        let position = self.descend(root, key)?;
+        if position.found {
+            bail!("the key is already in the b-tree rooted at page {root}");
+        }
+        let mut cell = Vec::new();
+        let typ = position.leaf.typ;
+        Varint(payload.len() as i64).write(&mut cell)?;
+        if let Key::Rowid(rowid) = key {
+            Varint(*rowid).write(&mut cell)?;
+        }
+        self.local_payload(&typ, payload, &mut cell)?;
+        let mut leaf = position.leaf;
+        leaf.cells.insert(position.pos, cell);
+        self.balance(position.path, leaf)
+    }
+
+    /// Gives the row with the given rowid of the table rooted at `root` a new payload, returns
+    /// whether it was there. The cell is rewritten where it is when its size does not change,
+    /// otherwise the page is laid out again, split or merged as its new size requires
+    pub(crate) fn update(&mut self, root: usize, rowid: i64, payload: &[u8]) -> Result<bool> {
+        let position = self.descend(root, &Key::Rowid(rowid))?;
+        if !position.found {
+            return Ok(false);
+        }
+        let mut leaf = position.leaf;
+        let typ = leaf.typ;
+        // the old overflow pages are freed first, so the new chain can take them again
+        self.free_overflow(typ, &leaf.cells[position.pos])?;
+        let mut cell = Vec::new();
+        Varint(payload.len() as i64).write(&mut cell)?;
+        Varint(rowid).write(&mut cell)?;
+        self.local_payload(&typ, payload, &mut cell)?;
+        if cell.len() == leaf.cells[position.pos].len() {
+            self.overwrite_in_place(leaf.idx, position.pos, &cell)?;
+        } else {
+            leaf.cells[position.pos] = cell;
+            self.rebalance(position.path, leaf)?;
+        }
+        Ok(true)
+    }
+
+    /// Writes `cell` over the `i`th cell of page `idx`, which takes exactly as many bytes
+    fn overwrite_in_place(&mut self, idx: usize, i: usize, cell: &[u8]) -> Result<()> {
+        let (page, _, pointers) = read_page_layout(self.pager, idx)
+            .map_err(|_| anyhow!("could not read b-tree page {idx}"))?;
+        let mut page = page.to_vec();
+        let ptr = pointers[i];
+        page.get_mut(ptr..ptr + cell.len())
+            .ok_or_else(|| anyhow!("cell pointer {ptr} of page {idx} is out of bounds"))?
+            .copy_from_slice(cell);
+        self.pager.write_page(idx, &page)
+    }
+
+    /// Walks from `root` down to the leaf `key` belongs to
+    fn descend(&mut self, root: usize, key: &Key) -> Result<Position> {
+        let mut path = Vec::new();
+        let (mut before, mut after) = (None, None);
+        let mut node = self.load(root)?;
+        loop {
+            let is_table = matches!(
+                node.typ,
+                BTreePageType::InteriorTable | BTreePageType::LeafTable
+            );
+            if is_table != matches!(key, Key::Rowid(_)) {
+                bail!(
+                    "page {} does not belong to the kind of b-tree expected",
+                    node.idx
+                );
+            }
+            let (pos, found) = self.position(&node, key)?;
+            if pos > 0 {
+                before = Some(node.cells[pos - 1].clone());
+            }
+            if pos < node.cells.len() {
+                after = Some(node.cells[pos].clone());
+            }
+            if node.is_leaf() {
+                return Ok(Position {
+                    path,
+                    leaf: node,
+                    pos,
+                    found,
+                    before,
+                    after,
+                });
+            }
+            if path.len() == MAX_DEPTH {
+                bail!("b-tree rooted at page {root} is too deep");
+            }
+            // a table interior cell holds the largest rowid of its left child, so equal keys go
+            // left too
+            let child = node.children[pos] as usize;
+            path.push((node, pos));
+            node = self.load(child)?;
+        }
+    }
+
+    /// Index of the first cell of `node` that does not come before `key`, and whether it is
+    /// equal to it
+    fn position(&mut self, node: &Node, key: &Key) -> Result<(usize, bool)> {
+        let (mut lo, mut hi) = (0, node.cells.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.compare(node, mid, key)? {
+                Ordering::Less => lo = mid + 1,
+                _ => hi = mid,
+            }
+        }
+        let found = lo < node.cells.len() && self.compare(node, lo, key)?.is_eq();
+        Ok((lo, found))
+    }
+
+    /// Compares the key of the `i`th cell of `node` against `key`
+    fn compare(&mut self, node: &Node, i: usize, key: &Key) -> Result<Ordering> {
+        Ok(match key {
+            Key::Rowid(rowid) => cell_rowid(node.typ, &node.cells[i])?.cmp(rowid),
+            Key::Index(key, asc) => {
+                let entry = self.index_entry(&node.cells[i])?;
+                compare_index_keys(&entry, key, asc, self.header.text_encoding)
+            }
+        })
+    }
+
+    /// Decodes the entry held by an index cell, following its overflow chain if needed
+    fn index_entry(&mut self, cell: &[u8]) -> Result<Vec<DbValue>> {
+        let limits = self.pager.payload_limits();
+        // interior cells without their child pointer look just like leaf ones
+        let mut cell = Cell::read(&BTreePageType::LeafIndex, cell, &limits)
+            .map_err(|_| anyhow!("could not read index cell"))?;
+        cell.read_overflow(self.pager)
+            .map_err(|_| anyhow!("could not read the overflow chain of an index cell"))?;
+        let record = Record::read_row(cell.payload.as_slice(), self.header.text_encoding)?;
+        Ok(record.into_iter().map(DbValue::from).collect())
+    }
+
+    /// Appends to `cell` the part of `payload` that is stored on the b-tree page, the rest goes
+    /// on a chain of overflow pages whose first page number follows
+    fn local_payload(
+        &mut self,
+        typ: &BTreePageType,
+        payload: &[u8],
+        cell: &mut Vec<u8>,
+    ) -> Result<()> {
+        let local = self.pager.payload_limits().local_size(typ, payload.len());
+        cell.extend_from_slice(&payload[..local]);
+        if local == payload.len() {
+            return Ok(());
+        }
+        let page_size = self.header.page_size as usize;
+        let chunks: Vec<&[u8]> = payload[local..]
+            .chunks(self.header.usable_size() - 4)
+            .collect();
+        let pages = chunks
+            .iter()
+            .map(|_| self.allocate())
+            .collect::<Result<Vec<_>>>()?;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut page = vec![0; page_size];
+            let next = pages.get(i + 1).copied().unwrap_or(0) as u32;
+            page[..4].copy_from_slice(&next.to_be_bytes());
+            page[4..4 + chunk.len()].copy_from_slice(chunk);
+            self.pager.write_page(pages[i], &page)?;
+        }
+        cell.extend_from_slice(&(pages[0] as u32).to_be_bytes());
+        Ok(())
+    }
+
+    /// Writes `node` back, splitting it (and then its ancestors in `path`) for as long as it
+    /// does not fit in a page
+    fn balance(&mut self, mut path: Vec<(Node, usize)>, mut node: Node) -> Result<()> {
+        let usable_size = self.header.usable_size();
+        loop {
+            if node.fits(usable_size) {
+                return self.store(&node);
+            }
+            let Some((mut parent, pos)) = path.pop() else {
+                // the root keeps its page number: its cells move to a new page, which becomes
+                // its only child and gets split like any other page
+                let child = Node {
+                    idx: self.allocate()?,
+                    typ: node.typ,
+                    cells: mem::take(&mut node.cells),
+                    children: mem::take(&mut node.children),
+                };
+                let root = Node {
+                    idx: node.idx,
+                    typ: match node.typ {
+                        BTreePageType::InteriorTable | BTreePageType::LeafTable => {
+                            BTreePageType::InteriorTable
+                        }
+                        BTreePageType::InteriorIndex | BTreePageType::LeafIndex => {
+                            BTreePageType::InteriorIndex
+                        }
+                    },
+                    cells: Vec::new(),
+                    children: vec![child.idx as u32],
+                };
+                if child.fits(usable_size) {
+                    // only page 1 gets here, its cells take less room anywhere else
+                    self.store(&child)?;
+                    return self.store(&root);
+                }
+                path.push((root, 0));
+                node = child;
+                continue;
+            };
+            self.split_into(&mut parent, pos, node)?;
+            node = parent;
+        }
+    }
+
+    /// Splits `node`, the child at `pos` of `parent`, adding the new pages and their dividers to
+    /// `parent`
+    fn split_into(&mut self, parent: &mut Node, pos: usize, node: Node) -> Result<()> {
+        let (groups, dividers) = self.split(node)?;
+        // the last group keeps the page, whose pointer in the parent is still right for it
+        let last = groups.len() - 1;
+        let mut pages = Vec::with_capacity(last);
+        for (i, mut group) in groups.into_iter().enumerate() {
+            if i != last {
+                group.idx = self.allocate()?;
+                pages.push(group.idx as u32);
+            }
+            self.store(&group)?;
+        }
+        parent.cells.splice(pos..pos, dividers);
+        parent.children.splice(pos..pos, pages);
+        Ok(())
+    }
+
+    /// Removes the entry with the given key from the b-tree rooted at `root`, returns whether it
+    /// was there. Its overflow pages go to the freelist, along with the pages emptied by merging
+    /// the ones it leaves underfull
+    pub(crate) fn delete(&mut self, root: usize, key: &Key) -> Result<bool> {
+        let mut path = Vec::new();
+        let mut node = self.load(root)?;
+        let pos = loop {
+            let is_table = matches!(
+                node.typ,
+                BTreePageType::InteriorTable | BTreePageType::LeafTable
+            );
+            if is_table != matches!(key, Key::Rowid(_)) {
+                bail!(
+                    "page {} does not belong to the kind of b-tree expected",
+                    node.idx
+                );
+            }
+            let (pos, found) = self.position(&node, key)?;
+            // table interior cells only hold a copy of the rowid, index ones hold the entry
+            if found && node.typ != BTreePageType::InteriorTable {
+                break pos;
+            }
+            if node.is_leaf() {
+                return Ok(false);
+            }
+            if path.len() == MAX_DEPTH {
+                bail!("b-tree rooted at page {root} is too deep");
+            }
+            let child = node.children[pos] as usize;
+            path.push((node, pos));
+            node = self.load(child)?;
+        };
+        let replaced = !node.is_leaf();
+        let (typ, removed) = if node.is_leaf() {
+            (node.typ, node.cells.remove(pos))
+        } else {
+            // the entry right before it, the last one of the subtree on its left, takes its place
+            let mut leaf = self.load(node.children[pos] as usize)?;
+            let interior = path.len();
+            path.push((node, pos));
+            while !leaf.is_leaf() {
+                if path.len() == MAX_DEPTH {
+                    bail!("b-tree rooted at page {root} is too deep");
+                }
+                let last = leaf.children.len() - 1;
+                let child = leaf.children[last] as usize;
+                path.push((leaf, last));
+                leaf = self.load(child)?;
+            }
+            let predecessor = leaf
+                .cells
+                .pop()
+                .ok_or_else(|| anyhow!("index leaf page {} is empty", leaf.idx))?;
+            let removed = mem::replace(&mut path[interior].0.cells[pos], predecessor);
+            node = leaf;
+            (BTreePageType::LeafIndex, removed)
+        };
+        self.free_overflow(typ, &removed)?;
+        if !replaced && (path.is_empty() || !self.underfull(&node)) {
+            // nothing moves but the cell, which leaves a hole in the page
+            self.remove_in_place(node.idx, pos)?;
+        } else {
+            self.rebalance(path, node)?;
+        }
+        Ok(true)
+    }
+
+    /// Whether `node` is so empty it should be merged with a sibling
+    fn underfull(&self, node: &Node) -> bool {
+        let used: usize = node.cells.iter().map(|c| node.cell_size(c)).sum();
+        used < (self.header.usable_size() - node.header_size()) / 3
+    }
+
+    /// Writes `node` and then each of its ancestors in `path` back, evening out underfull pages
+    /// with their siblings and splitting the ones that got too full on the way
+    fn rebalance(&mut self, mut path: Vec<(Node, usize)>, mut node: Node) -> Result<()> {
+        let usable_size = self.header.usable_size();
+        while let Some((mut parent, pos)) = path.pop() {
+            if !node.fits(usable_size) {
+                self.split_into(&mut parent, pos, node)?;
+            } else if self.underfull(&node) {
+                self.merge(&mut parent, pos, node)?;
+            } else {
+                self.store(&node)?;
+            }
+            node = parent;
+        }
+        // a root left with a single child takes its place, when there is room for it
+        while !node.is_leaf() && node.cells.is_empty() {
+            let child = self.load(node.children[0] as usize)?;
+            let shallower = Node {
+                idx: node.idx,
+                typ: child.typ,
+                cells: child.cells,
+                children: child.children,
+            };
+            if !shallower.fits(usable_size) {
+                break;
+            }
+            self.free(child.idx)?;
+            node = shallower;
+        }
+        self.balance(Vec::new(), node)
+    }
+
+    /// Evens out `node`, the underfull child at `pos` of `parent`, with one of its siblings: they
+    /// become a single page when there is room for it, otherwise their cells are spread over
+    /// both of them again
+    fn merge(&mut self, parent: &mut Node, pos: usize, node: Node) -> Result<()> {
+        if parent.children.len() < 2 {
+            return self.store(&node);
+        }
+        let (l, left, right) = match pos {
+            0 => {
+                let right = self.load(parent.children[1] as usize)?;
+                (0, node, right)
+            }
+            _ => (pos - 1, self.load(parent.children[pos - 1] as usize)?, node),
+        };
+        if left.typ != right.typ {
+            bail!(
+                "sibling pages {} and {} differ in type",
+                left.idx,
+                right.idx
+            );
+        }
+        let mut combined = Node {
+            idx: right.idx,
+            typ: left.typ,
+            cells: left.cells,
+            children: left.children,
+        };
+        // table leaves have no use for the divider, everyone else gets it back
+        if combined.typ != BTreePageType::LeafTable {
+            combined.cells.push(parent.cells[l].clone());
+        }
+        combined.cells.extend(right.cells);
+        combined.children.extend(right.children);
+        if combined.fits(self.header.usable_size()) {
+            parent.cells.remove(l);
+            parent.children.remove(l);
+            self.free(left.idx)?;
+            return self.store(&combined);
+        }
+        let (groups, dividers) = self.split(combined)?;
+        let mut pages = vec![left.idx, right.idx];
+        while pages.len() < groups.len() {
+            pages.insert(1, self.allocate()?);
+        }
+        for (mut group, &idx) in groups.into_iter().zip(&pages) {
+            group.idx = idx;
+            self.store(&group)?;
+        }
+        parent.cells.splice(l..l + 1, dividers);
+        parent
+            .children
+            .splice(l..l + 2, pages.into_iter().map(|idx| idx as u32));
+        Ok(())
+    }
+
+    /// Takes the `i`th cell off page `idx` leaving the others where they are: the space it took
+    /// becomes a freeblock, or fragmented bytes when it is too small to be one
+    fn remove_in_place(&mut self, idx: usize, i: usize) -> Result<()> {
+        let limits = self.pager.payload_limits();
+        let usable_size = limits.usable_size;
+        let (shared, mut header, pointers) = read_page_layout(self.pager, idx)
+            .map_err(|_| anyhow!("could not read b-tree page {idx}"))?;
+        let mut page = shared.to_vec();
+        let offset = if idx == 1 { 100 } else { 0 };
+        let array = offset + if header.right_ptr.is_some() { 12 } else { 8 };
+        let ptr = pointers[i];
+        let mut r = page
+            .get(ptr..usable_size)
+            .ok_or_else(|| anyhow!("cell pointer {ptr} of page {idx} is out of bounds"))?;
+        let len = r.len();
+        Cell::read(&header.page_type, &mut r, &limits)
+            .map_err(|_| anyhow!("could not read cell at {ptr} on page {idx}"))?;
+        let size = len - r.len();
+
+        let mut blocks = vec![(ptr, size)];
+        let mut next = header.freeblock_start as usize;
+        while next != 0 {
+            if next + 4 > usable_size || blocks.len() > usable_size / 4 {
+                bail!("the freeblock list of page {idx} is corrupted");
+            }
+            let block_size = u16::from_be_bytes([page[next + 2], page[next + 3]]) as usize;
+            blocks.push((next, block_size));
+            next = u16::from_be_bytes([page[next], page[next + 1]]) as usize;
+        }
+        blocks.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(blocks.len());
+        for (start, size) in blocks {
+            match merged.last_mut() {
+                Some((s, n)) if *s + *n == start => *n += size,
+                _ => merged.push((start, size)),
+            }
+        }
+        // free space right at the start of the cell content area just makes it smaller
+        if merged.first().map(|b| b.0) == Some(header.cell_start as usize) {
+            header.cell_start += merged.remove(0).1 as u32;
+        }
+        let (blocks, small): (Vec<_>, Vec<_>) = merged.into_iter().partition(|b| b.1 >= 4);
+        let fragments = header.fragments as usize + small.iter().map(|b| b.1).sum::<usize>();
+        if fragments > 60 {
+            // like sqlite, rather rewrite the page than lose track of that many bytes
+            let mut node = self.load(idx)?;
+            node.cells.remove(i);
+            return self.store(&node);
+        }
+
+        page.copy_within(
+            array + 2 * (i + 1)..array + 2 * pointers.len(),
+            array + 2 * i,
+        );
+        page[array + 2 * (pointers.len() - 1)..array + 2 * pointers.len()].fill(0);
+        for (k, &(start, size)) in blocks.iter().enumerate() {
+            let next = blocks.get(k + 1).map_or(0, |b| b.0 as u16);
+            page[start..start + 2].copy_from_slice(&next.to_be_bytes());
+            page[start + 2..start + 4].copy_from_slice(&(size as u16).to_be_bytes());
+        }
+        header.freeblock_start = blocks.first().map_or(0, |b| b.0 as u16);
+        header.cell_count -= 1;
+        header.fragments = fragments as u8;
+        header.write(&mut page[offset..]);
+        self.pager.write_page(idx, &page)
+    }
+
+    /// Gives the overflow pages of `cell`, if it has any, back to the freelist
+    fn free_overflow(&mut self, typ: BTreePageType, cell: &[u8]) -> Result<()> {
+        for idx in self.overflow_pages(typ, cell)? {
+            self.free(idx)?;
+        }
+        Ok(())
+    }
+
+    /// The overflow chain of `cell`, a cell of a page of type `typ` (leaf, for index cells)
+    fn overflow_pages(&mut self, typ: BTreePageType, cell: &[u8]) -> Result<Vec<usize>> {
+        let limits = self.pager.payload_limits();
+        let cell = Cell::read(&typ, cell, &limits).map_err(|_| anyhow!("could not read cell"))?;
+        let mut missing = cell.payload_size - cell.payload.len();
+        let mut next = cell.first_overflow;
+        let mut pages = Vec::new();
+        while let Some(idx) = next {
+            if missing == 0 {
+                bail!("overflow page {idx} is past the end of the payload");
+            }
+            next = match be_u32(self.pager.read_page(idx)?) {
+                0 => None,
+                n => Some(n),
+            };
+            missing = missing.saturating_sub(limits.usable_size - 4);
+            pages.push(idx);
+        }
+        Ok(pages)
+    }
+
+    /// Spreads the cells of `node` over as many pages as it takes. Each page but the last is
+    /// followed by a divider for the parent: a copy of its largest rowid for table leaves, the
+    /// cell right after it (which leaves the page) for every other kind of page
+    fn split(&self, node: Node) -> Result<(Vec<Node>, Vec<Vec<u8>>)> {
+        let capacity = self.header.usable_size() - node.header_size();
+        let sizes: Vec<usize> = node.cells.iter().map(|c| node.cell_size(c)).collect();
+        let total: usize = sizes.iter().sum();
+        let promote = node.typ != BTreePageType::LeafTable;
+        let n = sizes.len();
+        // aim for pages filled evenly, asking for more of them until every one fits
+        for pages in 2..=n + 1 {
+            let target = total / pages;
+            let mut bounds = Vec::new();
+            let (mut start, mut size, mut i) = (0, 0, 0);
+            while i < n {
+                let full = size + sizes[i] > capacity || size >= target;
+                // a promoted cell needs someone after it to form the next page
+                if i > start && full && (!promote || i + 1 < n) {
+                    bounds.push((start, i));
+                    start = if promote { i + 1 } else { i };
+                    i = start;
+                    size = 0;
+                    continue;
+                }
+                size += sizes[i];
+                i += 1;
+            }
+            bounds.push((start, n));
+            let fits = |&(start, end): &(usize, usize)| {
+                sizes[start..end].iter().sum::<usize>() <= capacity
+            };
+            if bounds.len() < 2 || !bounds.iter().all(fits) {
+                continue;
+            }
+            let mut groups = Vec::with_capacity(bounds.len());
+            let mut dividers = Vec::with_capacity(bounds.len() - 1);
+            for (g, &(start, end)) in bounds.iter().enumerate() {
+                groups.push(Node {
+                    idx: node.idx,
+                    typ: node.typ,
+                    cells: node.cells[start..end].to_vec(),
+                    // the child on the left of a promoted cell becomes the right-most pointer
+                    children: match node.is_leaf() {
+                        true => Vec::new(),
+                        false => node.children[start..=end].to_vec(),
+                    },
+                });
+                if g == bounds.len() - 1 {
+                    break;
+                }
+                dividers.push(match promote {
+                    true => node.cells[end].clone(),
+                    false => {
+                        let mut divider = Vec::new();
+                        let rowid = cell_rowid(node.typ, &node.cells[end - 1])?;
+                        Varint(rowid).write(&mut divider)?;
+                        divider
+                    }
+                });
+            }
+            return Ok((groups, dividers));
+        }
+        bail!(
+            "the cells of page {} do not fit in any number of pages",
+            node.idx
+        )
+    }
+
+    /// Reads a b-tree page and takes it apart into cells
+    fn load(&mut self, idx: usize) -> Result<Node> {
+        let limits = self.pager.payload_limits();
+        let (page, header, pointers) = read_page_layout(self.pager, idx)
+            .map_err(|_| anyhow!("could not read b-tree page {idx}"))?;
+        let mut node = Node {
+            idx,
+            typ: header.page_type,
+            cells: Vec::with_capacity(pointers.len()),
+            children: Vec::new(),
+        };
+        for ptr in pointers {
+            let content = page
+                .get(ptr..limits.usable_size)
+                .ok_or_else(|| anyhow!("cell pointer {ptr} of page {idx} is out of bounds"))?;
+            let mut r = content;
+            Cell::read(&node.typ, &mut r, &limits)
+                .map_err(|_| anyhow!("could not read cell at {ptr} on page {idx}"))?;
+            let cell = &content[..content.len() - r.len()];
+            if node.is_leaf() {
+                node.cells.push(cell.to_vec());
+            } else {
+                node.children
+                    .push(u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]));
+                node.cells.push(cell[4..].to_vec());
+            }
+        }
+        if let Some(right) = header.right_ptr {
+            node.children.push(right);
+        }
+        Ok(node)
+    }
+
+    /// Lays the cells of `node` out on a fresh page, packed at the end of it, and writes it
+    fn store(&mut self, node: &Node) -> Result<()> {
+        let mut page = vec![0; self.header.page_size as usize];
+        let offset = if node.idx == 1 {
+            self.header.write(&mut page);
+            100
+        } else {
+            0
+        };
+        let mut content = self.header.usable_size();
+        let mut pointer = offset + node.header_size();
+        for (i, cell) in node.cells.iter().enumerate() {
+            if node.is_leaf() {
+                content -= cell.len();
+            } else {
+                content -= cell.len() + 4;
+                page[content..content + 4].copy_from_slice(&node.children[i].to_be_bytes());
+            }
+            let start = content + if node.is_leaf() { 0 } else { 4 };
+            page[start..start + cell.len()].copy_from_slice(cell);
+            page[pointer..pointer + 2].copy_from_slice(&(content as u16).to_be_bytes());
+            pointer += 2;
+        }
+        let header = BTreeHeader {
+            page_type: node.typ,
+            freeblock_start: 0,
+            cell_count: node.cells.len() as u16,
+            cell_start: content as u32,
+            fragments: 0,
+            right_ptr: node.children.last().copied(),
+        };
+        header.write(&mut page[offset..]);
+        self.pager.write_page(node.idx, &page)
+    }
+
+    /// Hands out a page from the freelist, or a new one at the end of the file when it is empty
+    fn allocate(&mut self) -> Result<usize> {
+        let trunk = self.header.first_freelist_page as usize;
+        if trunk == 0 {
+            self.header.size_in_pages += 1;
+            let idx = self.header.size_in_pages as usize;
+            if self.pointer_map().is_some_and(|map| map.is_map_page(idx)) {
+                // its entries are filled in by update_pointer_map
+                self.pager
+                    .write_page(idx, &vec![0; self.header.page_size as usize])?;
+                self.header.size_in_pages += 1;
+            }
+            return Ok(self.header.size_in_pages as usize);
+        }
+        let mut page = self.pager.read_page(trunk)?.to_vec();
+        let count = be_u32(&page[4..]);
+        if count > (self.header.usable_size() - 8) / 4 {
+            bail!("freelist trunk page {trunk} claims {count} leaves");
+        }
+        self.header.total_freelist = self.header.total_freelist.saturating_sub(1);
+        if count == 0 {
+            // no leaves left, the trunk itself goes
+            self.header.first_freelist_page = be_u32(&page) as u32;
+            return Ok(trunk);
+        }
+        let leaf = be_u32(&page[4 + 4 * count..]);
+        page[4..8].copy_from_slice(&(count as u32 - 1).to_be_bytes());
+        self.pager.write_page(trunk, &page)?;
+        Ok(leaf)
+    }
+
+    /// Gives a page back to the freelist, as a leaf of the first trunk while it has room
+    fn free(&mut self, idx: usize) -> Result<()> {
+        let trunk = self.header.first_freelist_page as usize;
+        self.header.total_freelist += 1;
+        if trunk != 0 {
+            let mut page = self.pager.read_page(trunk)?.to_vec();
+            let count = be_u32(&page[4..]);
+            // versions of sqlite before 3.6.0 choke on trunks any fuller than this
+            if count < self.header.usable_size() / 4 - 8 {
+                page[8 + 4 * count..12 + 4 * count].copy_from_slice(&(idx as u32).to_be_bytes());
+                page[4..8].copy_from_slice(&(count as u32 + 1).to_be_bytes());
+                return self.pager.write_page(trunk, &page);
+            }
+        }
+        // the page becomes the first trunk, with no leaves yet
+        let mut page = vec![0; self.header.page_size as usize];
+        page[..4].copy_from_slice(&(trunk as u32).to_be_bytes());
+        self.header.first_freelist_page = idx as u32;
+        self.pager.write_page(idx, &page)
+    }
+}
+
+fn be_u32(b: &[u8]) -> usize {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize
+}
+
+/// Type of the leaves of the b-tree a page of type `typ` belongs to: the cells of a [`Node`] are
+/// laid out like theirs, child pointers aside
+fn leaf_type(typ: BTreePageType) -> BTreePageType {
+    match typ {
+        BTreePageType::InteriorTable | BTreePageType::LeafTable => BTreePageType::LeafTable,
+        BTreePageType::InteriorIndex | BTreePageType::LeafIndex => BTreePageType::LeafIndex,
+    }
+}
+
+/// Rowid held by a cell of a table b-tree page
+fn cell_rowid(typ: BTreePageType, mut cell: &[u8]) -> Result<i64> {
+    match typ {
+        BTreePageType::LeafTable => {
+            // skip the payload size
+            Varint::read(&mut cell)?;
+        }
+        BTreePageType::InteriorTable => {}
+        _ => bail!("index cells have no rowid"),
+    }
+    Ok(Varint::read(&mut cell)?.0)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{
+        database::{DbAccess, OpenOptions},
+        sql::syntax::DbValue,
+    };
+
+    #[test]
+    fn test_insert_splits() -> anyhow::Result<()> {
+        // see tree::test for the content of reserved.db, whose small pages split quickly
+        let path = std::env::temp_dir().join(format!("writer_test_{}.db", std::process::id()));
+        fs::copy("fixtures/reserved.db", &path)?;
+        let body = |i: usize| format!("{i} {}", "ba".repeat(1000)[..(i * 29) % 2000].to_owned());
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        for chunk in (61..=400).collect::<Vec<_>>().chunks(20) {
+            let values: Vec<_> = chunk.iter().map(|&i| format!("('{}')", body(i))).collect();
+            db.execute(&format!(
+                "INSERT INTO t (body) VALUES {}",
+                values.join(", ")
+            ))?;
+        }
+        // rowids that land between the existing ones
+        for i in (1000..1200).rev().step_by(3) {
+            db.execute(&format!("INSERT INTO t VALUES ({i}, '{}')", body(i)))?;
+        }
+        drop(db);
+
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(db.integrity_check()?, []);
+        let rows = db
+            .execute("SELECT id, body FROM t WHERE id > 60")?
+            .swap_remove(0)
+            .rows;
+        let expected: Vec<_> = (61..=400)
+            .chain((1000..1200).rev().step_by(3).rev())
+            .collect();
+        assert_eq!(rows.len(), expected.len());
+        for (row, i) in rows.iter().zip(expected) {
+            assert_eq!(
+                row.row,
+                [DbValue::Integer(i as i64), DbValue::Text(body(i))]
+            );
+        }
+        let query = format!("SELECT id FROM t WHERE body = '{}'", body(1043));
+        let rows = db.execute(&query)?.swap_remove(0).rows;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].id, DbValue::Integer(1043));
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_stale_header() -> anyhow::Result<()> {
+        // stale.db holds t(id INTEGER PRIMARY KEY, v TEXT) with rows 1..=100 on 5 pages of 512
+        // bytes, its version-valid-for number was zeroed so the size in the header cannot be
+        // trusted, as happens with files written by old versions of sqlite
+        let path = std::env::temp_dir().join(format!("stale_test_{}.db", std::process::id()));
+        fs::copy("fixtures/stale.db", &path)?;
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(db.header.size_in_pages, 5);
+        for i in 101..=160 {
+            db.execute(&format!("INSERT INTO t VALUES ({i}, 'row {i}')"))?;
+        }
+        drop(db);
+
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(db.integrity_check()?, []);
+        assert_eq!(
+            fs::metadata(&path)?.len(),
+            db.header.size_in_pages as u64 * 512
+        );
+        let rows = db.execute("SELECT v FROM t")?.swap_remove(0).rows;
+        assert_eq!(rows.len(), 160);
+        assert_eq!(rows[159].row, [DbValue::Text("row 160".to_string())]);
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_merges() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("delete_test_{}.db", std::process::id()));
+        fs::copy("fixtures/reserved.db", &path)?;
+        let body = |i: usize| format!("{i} {}", "ab".repeat(1000)[..(i * 37) % 1500].to_owned());
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        for chunk in (61..=400).collect::<Vec<_>>().chunks(20) {
+            let values: Vec<_> = chunk.iter().map(|&i| format!("('{}')", body(i))).collect();
+            db.execute(&format!(
+                "INSERT INTO t (body) VALUES {}",
+                values.join(", ")
+            ))?;
+        }
+        let pages = db.header.size_in_pages;
+        db.execute("DELETE FROM t WHERE id > 60 AND id < 390")?;
+        assert_eq!(db.integrity_check()?, []);
+        assert!(db.header.total_freelist > 0);
+        // the freed pages are used again before the file grows
+        for i in 100..=250 {
+            db.execute(&format!("INSERT INTO t VALUES ({i}, '{}')", body(i)))?;
+        }
+        drop(db);
+
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(db.integrity_check()?, []);
+        assert_eq!(db.header.size_in_pages, pages);
+        let rows = db
+            .execute("SELECT id, body FROM t WHERE id > 60")?
+            .swap_remove(0)
+            .rows;
+        let expected: Vec<_> = (100..=250).chain(390..=400).collect();
+        assert_eq!(rows.len(), expected.len());
+        for (row, i) in rows.iter().zip(expected) {
+            assert_eq!(
+                row.row,
+                [DbValue::Integer(i as i64), DbValue::Text(body(i))]
+            );
+        }
+        db.execute("DELETE FROM t")?;
+        assert_eq!(db.integrity_check()?, []);
+        assert!(db
+            .execute("SELECT id FROM t")?
+            .swap_remove(0)
+            .rows
+            .is_empty());
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_moves() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("update_test_{}.db", std::process::id()));
+        fs::copy("fixtures/reserved.db", &path)?;
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        for chunk in (61..=300).collect::<Vec<_>>().chunks(20) {
+            let values: Vec<_> = chunk.iter().map(|&i| format!("('{i}')")).collect();
+            db.execute(&format!(
+                "INSERT INTO t (body) VALUES {}",
+                values.join(", ")
+            ))?;
+        }
+        let long = "xy".repeat(700);
+        // rows grow past the page size, then shrink back into a few bytes
+        db.execute(&format!(
+            "UPDATE t SET body = '{long}' WHERE id > 100 AND id < 200"
+        ))?;
+        db.execute("UPDATE t SET body = 'short' WHERE id > 150 AND id < 200")?;
+        // same size, written where it is
+        db.execute("UPDATE t SET body = '299' WHERE id = 300")?;
+        // rowids that move past each other
+        db.execute("UPDATE t SET id = id + 50 WHERE id > 200")?;
+        drop(db);
+
+        let mut db = DbAccess::open_path(&path, &OpenOptions::default())?;
+        assert_eq!(db.integrity_check()?, []);
+        let rows = db
+            .execute("SELECT id, body FROM t WHERE id > 60")?
+            .swap_remove(0)
+            .rows;
+        let expected: Vec<_> = (61..=200)
+            .chain(251..=350)
+            .map(|i| {
+                let body = match i {
+                    101..=150 => long.clone(),
+                    151..=199 => "short".to_owned(),
+                    350 => "299".to_owned(),
+                    251.. => (i - 50).to_string(),
+                    _ => i.to_string(),
+                };
+                [DbValue::Integer(i), DbValue::Text(body)]
+            })
+            .collect();
+        assert_eq!(rows.len(), expected.len());
+        for (row, expected) in rows.iter().zip(expected) {
+            assert_eq!(row.row, expected);
+        }
+        let rows = db
+            .execute("SELECT id FROM t WHERE body = '299'")?
+            .swap_remove(0)
+            .rows;
+        let ids: Vec<_> = rows.into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, [DbValue::Integer(349), DbValue::Integer(350)]);
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -1,28 +1,95 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use itertools::Itertools;
-use std::fs::File;
 
-use crate::database::DbAccess;
+use std::ops::ControlFlow;
+
+use crate::database::{
+    cache::CacheLimit,
+    header::TextEncoding,
+    query::{Column, Row, RowSink},
+    DbAccess, OpenOptions,
+};
 
 mod database;
 mod sql;
 
 // I have made the very unwise decision of not using any kind of parsing library
 // parsing is fun and I want to do it on my own
+const USAGE: &str = "usage: sqlite-starter-rust [options] <database path> <command>
+
+options:
+  --cache-pages <n>    keep at most n pages in the page cache
+  --cache-bytes <n>    keep at most n bytes worth of pages in the page cache
+  --mmap               read pages straight from a memory mapping of the database file, which
+                       no other process may modify while it is open: changes would show up in
+                       the middle of a query, and shrinking the file crashes this one
+  --restore-journal    play a hot rollback journal back onto the database file
+  --stats              print the page cache counters once the command is done";
+
 fn main() -> Result<()> {
     // Parse arguments
-    let args = std::env::args().collect::<Vec<_>>();
+    let mut options = OpenOptions::default();
+    let mut stats = false;
+    let mut args = vec![];
+    let mut argv = std::env::args();
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--cache-pages" | "--cache-bytes" => {
+                let Some(size) = argv.next().and_then(|n| n.parse().ok()) else {
+                    bail!("{arg} expects a number");
+                };
+                options.cache = if arg == "--cache-pages" {
+                    CacheLimit::Pages(size)
+                } else {
+                    CacheLimit::Bytes(size)
+                };
+            }
+            "--stats" => stats = true,
+            "--mmap" => options.mmap = true,
+            "--restore-journal" => options.restore_journal = true,
+            "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ => args.push(arg),
+        }
+    }
     match args.len() {
-        0 | 1 => bail!("Missing <database path> and <command>"),
-        2 => bail!("Missing <command>"),
+        0 | 1 => bail!("Missing <database path> and <command>\n\n{USAGE}"),
+        2 => bail!("Missing <command>\n\n{USAGE}"),
         _ => {}
     }
 
     // Parse command and act accordingly
     let command = &args[2];
-    let file = File::open(&args[1])?;
-    let mut dbaccess = DbAccess::new(file)?;
+    let mut dbaccess = if command == ".create" {
+        // .create [page size] [encoding], with the same defaults as sqlite
+        let page_size = match args.get(3) {
+            Some(n) => n
+                .parse()
+                .with_context(|| format!("invalid page size {n}"))?,
+            None => 4096,
+        };
+        let encoding = match args.get(4).map(|e| e.to_ascii_uppercase()).as_deref() {
+            None | Some("UTF-8" | "UTF8") => TextEncoding::UTF8,
+            Some("UTF-16LE" | "UTF16LE") => TextEncoding::UTF16le,
+            Some("UTF-16BE" | "UTF16BE") => TextEncoding::UTF16be,
+            Some(e) => bail!("unknown encoding {e} (expected UTF-8, UTF-16le or UTF-16be)"),
+        };
+        DbAccess::create(&args[1], page_size, encoding, &options)
+            .with_context(|| format!("cannot create {}", args[1]))?
+    } else {
+        DbAccess::open_path(&args[1], &options)
+            .with_context(|| format!("cannot open {}", args[1]))?
+    };
+    for notice in dbaccess.notices() {
+        eprintln!("{notice}");
+    }
     match command.as_str() {
+        ".create" => println!(
+            "created {} with {} bytes pages",
+            args[1], dbaccess.header.page_size
+        ),
         ".dbinfo" => {
             let number_of_tables = dbaccess.number_of_tables()?;
 
@@ -34,21 +101,52 @@ fn main() -> Result<()> {
             println!("number of tables: {}", number_of_tables);
             //println!("number of tables: {}", header.size);
         }
+        ".freelist" => {
+            let freelist = dbaccess.freelist()?;
+            println!("trunk pages: {}", freelist.trunks.iter().join(" "));
+            println!("leaf pages: {}", freelist.leaves.iter().join(" "));
+            println!(
+                "free pages: {} ({} bytes reclaimable)",
+                freelist.page_count(),
+                freelist.page_count() * dbaccess.header.page_size as usize
+            );
+        }
+        ".check" => {
+            let problems = dbaccess.integrity_check()?;
+            if problems.is_empty() {
+                println!("ok");
+            }
+            for problem in &problems {
+                println!("{problem}");
+            }
+            if !problems.is_empty() {
+                std::process::exit(1);
+            }
+        }
         ".tables" => {
             let names = dbaccess.table_names()?.join(" ");
             println!("{names}");
         }
-        query => {
-            let tables = dbaccess.run_query(query);
-            for table in tables {
-                println!("{}", table.columns.iter().map(|c| c.name()).join("\t|\t"));
-                for row in table.rows {
-                    println!("{row:?}");
-                }
-            }
-        }
+        query => dbaccess.execute_with(query, &mut Printer)?,
         _ => bail!("Missing or invalid command passed: {}", command),
     }
+    if let (true, Some(cache_stats)) = (stats, dbaccess.cache_stats()) {
+        eprintln!("{cache_stats}");
+    }
 
     Ok(())
 }
+
+/// Prints the results of the queries as their rows come in
+struct Printer;
+
+impl RowSink for Printer {
+    fn columns(&mut self, columns: &[Column]) {
+        println!("{}", columns.iter().map(|c| c.name()).join("\t|\t"));
+    }
+
+    fn row(&mut self, row: Row) -> ControlFlow<()> {
+        println!("{row:?}");
+        ControlFlow::Continue(())
+    }
+}
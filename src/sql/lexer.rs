@@ -1,7 +1,7 @@
 use std::fmt::Display;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-pub(super) enum TokenType {
+pub(crate) enum TokenType {
     //Single char tokens
     OPENP,
     CLOSEP,
@@ -55,6 +55,25 @@ pub(super) enum TokenType {
     FAIL,
     IGNORE,
     REPLACE,
+    INDEX,
+    IF,
+    EXISTS,
+    BETWEEN,
+    PRAGMA,
+    INSERT,
+    INTO,
+    VALUES,
+    DELETE,
+    UPDATE,
+    SET,
+    BEGIN,
+    COMMIT,
+    END,
+    TRANSACTION,
+    WITHOUT,
+    LIMIT,
+    OFFSET,
+    VACUUM,
 }
 
 impl Display for TokenType {
@@ -108,6 +127,25 @@ impl Display for TokenType {
             TokenType::FAIL => "FAIL",
             TokenType::IGNORE => "IGNORE",
             TokenType::REPLACE => "REPLACE",
+            TokenType::INDEX => "INDEX",
+            TokenType::IF => "IF",
+            TokenType::EXISTS => "EXISTS",
+            TokenType::BETWEEN => "BETWEEN",
+            TokenType::PRAGMA => "PRAGMA",
+            TokenType::INSERT => "INSERT",
+            TokenType::INTO => "INTO",
+            TokenType::VALUES => "VALUES",
+            TokenType::DELETE => "DELETE",
+            TokenType::UPDATE => "UPDATE",
+            TokenType::SET => "SET",
+            TokenType::BEGIN => "BEGIN",
+            TokenType::COMMIT => "COMMIT",
+            TokenType::END => "END",
+            TokenType::TRANSACTION => "TRANSACTION",
+            TokenType::WITHOUT => "WITHOUT",
+            TokenType::LIMIT => "LIMIT",
+            TokenType::OFFSET => "OFFSET",
+            TokenType::VACUUM => "VACUUM",
         };
         write!(f, "{val}")
     }
@@ -140,6 +178,25 @@ fn map_token(s: &str) -> Option<TokenType> {
         "FAIL" => TokenType::FAIL,
         "IGNORE" => TokenType::IGNORE,
         "REPLACE" => TokenType::REPLACE,
+        "INDEX" => TokenType::INDEX,
+        "IF" => TokenType::IF,
+        "EXISTS" => TokenType::EXISTS,
+        "BETWEEN" => TokenType::BETWEEN,
+        "PRAGMA" => TokenType::PRAGMA,
+        "INSERT" => TokenType::INSERT,
+        "INTO" => TokenType::INTO,
+        "VALUES" => TokenType::VALUES,
+        "DELETE" => TokenType::DELETE,
+        "UPDATE" => TokenType::UPDATE,
+        "SET" => TokenType::SET,
+        "BEGIN" => TokenType::BEGIN,
+        "COMMIT" => TokenType::COMMIT,
+        "END" => TokenType::END,
+        "TRANSACTION" => TokenType::TRANSACTION,
+        "WITHOUT" => TokenType::WITHOUT,
+        "LIMIT" => TokenType::LIMIT,
+        "OFFSET" => TokenType::OFFSET,
+        "VACUUM" => TokenType::VACUUM,
 
         _ => return None,
     };
@@ -149,6 +206,7 @@ fn map_token(s: &str) -> Option<TokenType> {
 #[derive(Debug, PartialEq, Eq)]
 pub(super) struct Token<'a> {
     pub(super) typ: TokenType,
+    /// slice of the scanned text, only EOF has a lexeme of its own
     pub(super) lexeme: &'a str,
     // TODO: sarebbe carino tenere un conto delle righe per i messaggi di errore
 }
@@ -162,39 +220,39 @@ pub(crate) fn scan(s: &str) -> Vec<Token> {
         let tok = match c {
             '*' => Token {
                 typ: TokenType::ASTERISK,
-                lexeme: "*",
+                lexeme: &s[cur..=cur],
             },
             '+' => Token {
                 typ: TokenType::PLUS,
-                lexeme: "+",
+                lexeme: &s[cur..=cur],
             },
             '-' => Token {
                 typ: TokenType::MINUS,
-                lexeme: "-",
+                lexeme: &s[cur..=cur],
             },
             '(' => Token {
                 typ: TokenType::OPENP,
-                lexeme: "(",
+                lexeme: &s[cur..=cur],
             },
             ')' => Token {
                 typ: TokenType::CLOSEP,
-                lexeme: ")",
+                lexeme: &s[cur..=cur],
             },
             ',' => Token {
                 typ: TokenType::COMMA,
-                lexeme: ",",
+                lexeme: &s[cur..=cur],
             },
             ';' => Token {
                 typ: TokenType::SEMICOLON,
-                lexeme: ";",
+                lexeme: &s[cur..=cur],
             },
             '.' => Token {
                 typ: TokenType::DOT,
-                lexeme: ".",
+                lexeme: &s[cur..=cur],
             },
             '=' => Token {
                 typ: TokenType::EQUALS,
-                lexeme: "=",
+                lexeme: &s[cur..=cur],
             },
             '!' => match it.peek().expect("incomplete '!'") {
                 b'=' => {
@@ -202,12 +260,12 @@ pub(crate) fn scan(s: &str) -> Vec<Token> {
                     cur += 1;
                     Token {
                         typ: TokenType::NOTEQUALS,
-                        lexeme: "!=",
+                        lexeme: &s[cur - 1..=cur],
                     }
                 }
                 _ => Token {
                     typ: TokenType::BANG,
-                    lexeme: "!",
+                    lexeme: &s[cur..=cur],
                 },
             },
             '<' => match it.peek().expect("incomplete '='") {
@@ -216,12 +274,12 @@ pub(crate) fn scan(s: &str) -> Vec<Token> {
                     it.next();
                     Token {
                         typ: TokenType::LESSEQ,
-                        lexeme: "<=",
+                        lexeme: &s[cur - 1..=cur],
                     }
                 }
                 _ => Token {
                     typ: TokenType::LESS,
-                    lexeme: "<",
+                    lexeme: &s[cur..=cur],
                 },
             },
             '>' => match it.peek().expect("incomplete '='") {
@@ -230,17 +288,17 @@ pub(crate) fn scan(s: &str) -> Vec<Token> {
                     it.next();
                     Token {
                         typ: TokenType::GREATEREQ,
-                        lexeme: ">=",
+                        lexeme: &s[cur - 1..=cur],
                     }
                 }
                 _ => Token {
                     typ: TokenType::GREATER,
-                    lexeme: ">",
+                    lexeme: &s[cur..=cur],
                 },
             },
             '/' => Token {
                 typ: TokenType::SLASH,
-                lexeme: "/",
+                lexeme: &s[cur..=cur],
             },
             '\'' => {
                 let start = cur + 1;
@@ -1,11 +1,32 @@
-use self::{parser::Parser, syntax::Statement};
+use self::{
+    parser::{ParseError, Parser},
+    syntax::Statement,
+};
 
 mod lexer;
 mod parser;
 pub(crate) mod syntax;
 
-pub(crate) fn parse_sql(sql: &str) -> Vec<Statement> {
+pub(crate) fn parse_sql(sql: &str) -> Result<Vec<Statement>, ParseError> {
     let toks = lexer::scan(&sql);
     let mut p = Parser::new(&toks);
     p.scan()
 }
+
+/// Like [`parse_sql`], pairing each statement with the text it was parsed from
+pub(crate) fn parse_sql_with_text(sql: &str) -> Result<Vec<(Statement, &str)>, ParseError> {
+    let toks = lexer::scan(sql);
+    // lexemes point into `sql`, but EOF's
+    let offset = |i: usize| match toks[i].typ {
+        lexer::TokenType::EOF => sql.len(),
+        _ => toks[i].lexeme.as_ptr() as usize - sql.as_ptr() as usize,
+    };
+    Ok(Parser::new(&toks)
+        .scan_spans()?
+        .into_iter()
+        .map(|(stmt, span)| {
+            let text = sql[offset(span.start)..offset(span.end)].trim_end();
+            (stmt, text)
+        })
+        .collect())
+}
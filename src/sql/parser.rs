@@ -1,13 +1,30 @@
 //! Definition of our syntax
 //! sql-stmt-list : sql-stmt  ( ';',sql-stmt)*;
-//! sql-stms : create-table-stmt | select-stmt;
-//! create-table-stmt : 'CREATE TABLE'  IDENT  '('  column-def  (',' column-def)+ ')';
+//! sql-stms : create-table-stmt | create-index-stmt | select-stmt | insert-stmt | delete-stmt
+//!            | update-stmt | transaction-stmt | pragma-stmt | vacuum-stmt;
+//! create-table-stmt : 'CREATE TABLE'  IDENT  '('  column-def  (',' column-def)*
+//!                     (',' 'PRIMARY' 'KEY' '(' indexed-column (',' indexed-column)* ')')? ')'
+//!                     ('WITHOUT' 'ROWID')?;
+//! create-index-stmt : 'CREATE' 'UNIQUE'? 'INDEX' ('IF' 'NOT' 'EXISTS')? IDENT 'ON' IDENT
+//!                     '(' indexed-column (',' indexed-column)* ')';
+//! indexed-column : IDENT ('ASC' | 'DESC')?;
+//! select-stmt : 'SELECT' ('*' | expr (',' expr)*) 'FROM' IDENT ('WHERE' expr)?
+//!               ('LIMIT' expr ('OFFSET' expr)?)?;
+//! insert-stmt : 'INSERT' 'INTO' IDENT ('(' IDENT (',' IDENT)* ')')?
+//!                 'VALUES' '(' expr (',' expr)* ')' (',' '(' expr (',' expr)* ')')*;
+//! delete-stmt : 'DELETE' 'FROM' IDENT ('WHERE' expr)?;
+//! update-stmt : 'UPDATE' IDENT 'SET' IDENT '=' expr (',' IDENT '=' expr)* ('WHERE' expr)?;
+//! transaction-stmt : ('BEGIN' | 'COMMIT' | 'END' | 'ROLLBACK') 'TRANSACTION'?;
+//! pragma-stmt : 'PRAGMA' IDENT ('=' expr | '(' expr ')')?;
+//! vacuum-stmt : 'VACUUM' ('INTO' STRING)?;
 //! column-def: IDENT type-name column-constraint?;
 //! type-name : name ( '(' signed-number ')')?
 //! signed-number: ('+'|'-')? NUM
 //! column-constraint: 'PRIMARY' 'KEY'; // SI NON NE SUPPORTO MOLTI
 //!
 
+use std::ops::Range;
+
 use thiserror::Error;
 
 use super::syntax::{self, ColType, ColumnConstraint, ConflictClause, Expr, FunctionArg, Operator};
@@ -33,6 +50,8 @@ pub(crate) enum ParseError {
     ExpectedToken(TokenType),
     #[error("{0}")]
     CustomError(&'static str),
+    #[error("invalid statement terminating character {0}")]
+    InvalidTerminator(String),
 }
 
 impl<'a> Parser<'a> {
@@ -49,36 +68,47 @@ impl<'a> Parser<'a> {
         self.tokens.get(self.idx)
     }
 
-    pub(crate) fn scan(&mut self) -> Vec<syntax::Statement> {
+    pub(crate) fn scan(&mut self) -> Result<Vec<syntax::Statement>, ParseError> {
+        Ok(self
+            .scan_spans()?
+            .into_iter()
+            .map(|(stmt, _)| stmt)
+            .collect())
+    }
+
+    /// Like [`Parser::scan`], along with the tokens of each statement: from its first one to the
+    /// one ending it, excluded
+    pub(crate) fn scan_spans(
+        &mut self,
+    ) -> Result<Vec<(syntax::Statement, Range<usize>)>, ParseError> {
         let mut statements = Vec::new();
-        loop {
-            let stmt = match self.statement() {
-                Ok(v) => v,
-                Err(e) => {
-                    eprintln!("{e}");
-                    break;
-                }
-            };
-            //println!("{stmt:?}");
+        // a trailing semicolon leaves nothing but EOF behind it
+        while self.peek().is_some_and(|tok| tok.typ != TokenType::EOF) {
+            let start = self.idx;
+            let stmt = self.statement()?;
+            statements.push((stmt, start..self.idx));
             match self.step() {
-                Some(tok) if tok.typ == TokenType::SEMICOLON => {
-                    statements.push(stmt);
-                }
-                Some(tok) if tok.typ == TokenType::EOF => {
-                    statements.push(stmt);
-                    break;
-                }
-                Some(v) => panic!("invalid statement terminating character {}", v.lexeme),
-                None => panic!("invalid statement termination"),
+                Some(tok) if tok.typ == TokenType::SEMICOLON => {}
+                Some(tok) if tok.typ == TokenType::EOF => break,
+                Some(v) => return Err(ParseError::InvalidTerminator(v.lexeme.to_owned())),
+                None => return Err(ParseError::NoInput),
             }
         }
-        statements
+        Ok(statements)
     }
     pub(crate) fn statement(&mut self) -> Result<syntax::Statement, ParseError> {
         let first = self.step().ok_or(ParseError::NoInput)?;
         match first.typ {
             TokenType::CREATE => self.create(),
             TokenType::SELECT => Ok(syntax::Statement::Select(self.select()?)),
+            TokenType::INSERT => self.insert(),
+            TokenType::DELETE => self.delete(),
+            TokenType::UPDATE => self.update(),
+            TokenType::BEGIN => self.transaction(syntax::Statement::Begin),
+            TokenType::COMMIT | TokenType::END => self.transaction(syntax::Statement::Commit),
+            TokenType::ROLLBACK => self.transaction(syntax::Statement::Rollback),
+            TokenType::PRAGMA => self.pragma(),
+            TokenType::VACUUM => self.vacuum(),
             _ => Err(ParseError::InvalidKeyword(first.lexeme.to_owned())),
         }
     }
@@ -87,10 +117,136 @@ impl<'a> Parser<'a> {
         let first = self.step().ok_or(ParseError::NoInput)?;
         match first.typ {
             TokenType::TABLE => Ok(syntax::Statement::Create(self.create_table()?)),
+            TokenType::INDEX => Ok(syntax::Statement::Create(self.create_index(false)?)),
+            TokenType::UNIQUE => {
+                self.expect(TokenType::INDEX)?;
+                Ok(syntax::Statement::Create(self.create_index(true)?))
+            }
             _ => Err(ParseError::InvalidKeyword(first.lexeme.to_owned())),
         }
     }
 
+    fn insert(&mut self) -> Result<syntax::Statement, ParseError> {
+        self.expect(TokenType::INTO)?;
+        let table = self.expect(TokenType::IDENTIFIER)?.lexeme.to_owned();
+        let mut columns = Vec::new();
+        if self
+            .matches(|t| t.typ == TokenType::OPENP, "no columns")
+            .is_ok()
+        {
+            loop {
+                columns.push(self.expect(TokenType::IDENTIFIER)?.lexeme.to_owned());
+                let nxt = self.step().ok_or(ParseError::NoInput)?;
+                match nxt.typ {
+                    TokenType::CLOSEP => break,
+                    TokenType::COMMA => {}
+                    _ => return Err(ParseError::InvalidKeyword(nxt.lexeme.to_owned())),
+                }
+            }
+        }
+        self.expect(TokenType::VALUES)?;
+        let mut values = Vec::new();
+        loop {
+            self.expect(TokenType::OPENP)?;
+            let mut row = Vec::new();
+            loop {
+                row.push(self.expression()?);
+                let nxt = self.step().ok_or(ParseError::NoInput)?;
+                match nxt.typ {
+                    TokenType::CLOSEP => break,
+                    TokenType::COMMA => {}
+                    _ => return Err(ParseError::InvalidKeyword(nxt.lexeme.to_owned())),
+                }
+            }
+            values.push(row);
+            if self
+                .matches(|t| t.typ == TokenType::COMMA, "no more rows")
+                .is_err()
+            {
+                break;
+            }
+        }
+        Ok(syntax::Statement::Insert {
+            table,
+            columns,
+            values,
+        })
+    }
+
+    fn delete(&mut self) -> Result<syntax::Statement, ParseError> {
+        self.expect(TokenType::FROM)?;
+        let table = self.expect(TokenType::IDENTIFIER)?.lexeme.to_owned();
+        let mut filter = None;
+        if self
+            .matches(|t| t.typ == TokenType::WHERE, "no WHERE")
+            .is_ok()
+        {
+            filter = Some(self.expression()?);
+        }
+        Ok(syntax::Statement::Delete { table, filter })
+    }
+
+    fn update(&mut self) -> Result<syntax::Statement, ParseError> {
+        let table = self.expect(TokenType::IDENTIFIER)?.lexeme.to_owned();
+        self.expect(TokenType::SET)?;
+        let mut assignments = Vec::new();
+        loop {
+            let column = self.expect(TokenType::IDENTIFIER)?.lexeme.to_owned();
+            self.expect(TokenType::EQUALS)?;
+            assignments.push((column, self.expression()?));
+            if self
+                .matches(|t| t.typ == TokenType::COMMA, "no more assignments")
+                .is_err()
+            {
+                break;
+            }
+        }
+        let mut filter = None;
+        if self
+            .matches(|t| t.typ == TokenType::WHERE, "no WHERE")
+            .is_ok()
+        {
+            filter = Some(self.expression()?);
+        }
+        Ok(syntax::Statement::Update {
+            table,
+            assignments,
+            filter,
+        })
+    }
+
+    fn transaction(&mut self, stmt: syntax::Statement) -> Result<syntax::Statement, ParseError> {
+        // TRANSACTION is just noise
+        let _ = self.matches(|t| t.typ == TokenType::TRANSACTION, "no TRANSACTION");
+        Ok(stmt)
+    }
+
+    fn pragma(&mut self) -> Result<syntax::Statement, ParseError> {
+        let name = self.expect(TokenType::IDENTIFIER)?.lexeme.to_owned();
+        let value = match self.peek().map(|t| t.typ) {
+            Some(TokenType::EQUALS) => {
+                self.step();
+                Some(self.expression()?)
+            }
+            Some(TokenType::OPENP) => {
+                self.step();
+                let value = self.expression()?;
+                self.expect(TokenType::CLOSEP)?;
+                Some(value)
+            }
+            _ => None,
+        };
+        Ok(syntax::Statement::Pragma { name, value })
+    }
+
+    fn vacuum(&mut self) -> Result<syntax::Statement, ParseError> {
+        let into = match self.matches(|t| t.typ == TokenType::INTO, "no INTO") {
+            Ok(_) => Some(self.expect(TokenType::STRING)?.lexeme.to_owned()),
+            Err(_) => None,
+        };
+        Ok(syntax::Statement::Vacuum { into })
+    }
+
     fn select(&mut self) -> Result<syntax::SelectStatement, ParseError> {
         let mut col_names = Vec::new();
         if self.peek().ok_or(ParseError::NoInput)?.typ == TokenType::ASTERISK {
@@ -120,10 +276,25 @@ impl<'a> Parser<'a> {
             //println!("Yoo");
             filter = Some(self.expression()?);
         }
+        let (mut limit, mut offset) = (None, None);
+        if self
+            .matches(|t| t.typ == TokenType::LIMIT, "no LIMIT")
+            .is_ok()
+        {
+            limit = Some(self.expression()?);
+            if self
+                .matches(|t| t.typ == TokenType::OFFSET, "no OFFSET")
+                .is_ok()
+            {
+                offset = Some(self.expression()?);
+            }
+        }
         Ok(syntax::SelectStatement {
             from,
             fields: col_names,
             filter,
+            limit,
+            offset,
         })
     }
 
@@ -154,25 +325,92 @@ impl<'a> Parser<'a> {
         })?;
         self.expect(TokenType::OPENP)?;
         let mut cols = Vec::new();
-        let mut i = 0;
+        let mut primary_key = Vec::new();
         loop {
-            let col_def = self.col_def(i)?;
-            i += 1;
+            if self
+                .matches(|t| t.typ == TokenType::PRIMARY, "no PRIMARY")
+                .is_ok()
+            {
+                self.expect(TokenType::KEY)?;
+                self.expect(TokenType::OPENP)?;
+                primary_key = self.indexed_columns()?;
+                self.expect(TokenType::CLOSEP)?;
+                self.expect(TokenType::CLOSEP)?;
+                break;
+            }
+            cols.push(self.col_def(cols.len())?);
             let next = self.step().ok_or(ParseError::NoInput)?;
             match next.typ {
-                TokenType::CLOSEP => {
-                    cols.push(col_def);
-                    break;
-                }
-                TokenType::COMMA => cols.push(col_def),
+                TokenType::CLOSEP => break,
+                TokenType::COMMA => {}
                 _ => return Err(ParseError::InvalidKeyword(next.lexeme.to_string())),
             }
         }
+        if cols.is_empty() {
+            return Err(ParseError::CustomError("a table needs at least one column"));
+        }
+        let without_rowid = self.matches(|t| t.typ == TokenType::WITHOUT, "").is_ok();
+        if without_rowid {
+            self.matches(
+                |t| t.typ == TokenType::IDENTIFIER && t.lexeme.eq_ignore_ascii_case("rowid"),
+                "expected ROWID after WITHOUT",
+            )?;
+        }
+        // a lone INTEGER column given as the key aliases the rowid, exactly as if it had been
+        // declared `INTEGER PRIMARY KEY`
+        if let ([key], false) = (primary_key.as_slice(), without_rowid) {
+            if let Some(col) = cols
+                .iter_mut()
+                .find(|c| c.name == key.name && c.typ == ColType::INTEGER)
+            {
+                col.constraint.push(ColumnConstraint::Pk {
+                    asc: key.asc,
+                    autoinc: false,
+                    conflict: None,
+                });
+                primary_key.clear();
+            }
+        }
         Ok(syntax::CreateStatement::Table {
             name: name.to_owned(),
             cols,
+            primary_key,
+            without_rowid,
+        })
+    }
+    fn create_index(&mut self, unique: bool) -> Result<syntax::CreateStatement, ParseError> {
+        if self.matches(|t| t.typ == TokenType::IF, "no IF").is_ok() {
+            self.expect(TokenType::NOT)?;
+            self.expect(TokenType::EXISTS)?;
+        }
+        let name = self.expect(TokenType::IDENTIFIER)?.lexeme.to_owned();
+        self.expect(TokenType::ON)?;
+        let table = self.expect(TokenType::IDENTIFIER)?.lexeme.to_owned();
+        self.expect(TokenType::OPENP)?;
+        let cols = self.indexed_columns()?;
+        self.expect(TokenType::CLOSEP)?;
+        Ok(syntax::CreateStatement::Index {
+            name,
+            table,
+            unique,
+            cols,
         })
     }
+    /// The comma separated columns of an index or of a PRIMARY KEY, up to the closing parenthesis
+    fn indexed_columns(&mut self) -> Result<Vec<syntax::IndexedColumn>, ParseError> {
+        let mut cols = Vec::new();
+        loop {
+            let name = self.expect(TokenType::IDENTIFIER)?.lexeme.to_owned();
+            let mut asc = true;
+            if let Ok(t) = self.matches(|t| matches!(t.typ, TokenType::ASC | TokenType::DESC), "") {
+                asc = t.typ == TokenType::ASC;
+            }
+            cols.push(syntax::IndexedColumn { name, asc });
+            if self.matches(|t| t.typ == TokenType::COMMA, "").is_err() {
+                return Ok(cols);
+            }
+        }
+    }
     fn col_def(&mut self, pos: usize) -> Result<syntax::ColumnDefinition, ParseError> {
         let name = self.expect(TokenType::IDENTIFIER)?.lexeme.to_string();
         let typ = match self
@@ -330,6 +568,30 @@ impl<'a> Parser<'a> {
 
     fn comparison(&mut self) -> Result<syntax::Expr, ParseError> {
         let mut expr = self.term()?;
+        if self
+            .matches(|t| t.typ == TokenType::BETWEEN, "expected BETWEEN")
+            .is_ok()
+        {
+            // x BETWEEN a AND b is just x >= a AND x <= b
+            let low = self.term()?;
+            self.expect(TokenType::AND)?;
+            let high = self.term()?;
+            return Ok(Expr::Grouping {
+                expr: Box::new(Expr::Binary {
+                    left: Box::new(Expr::Binary {
+                        left: Box::new(expr.clone()),
+                        right: Box::new(low),
+                        operator: Operator::GreaterEq,
+                    }),
+                    right: Box::new(Expr::Binary {
+                        left: Box::new(expr),
+                        right: Box::new(high),
+                        operator: Operator::LessEq,
+                    }),
+                    operator: Operator::And,
+                }),
+            });
+        }
         while let Ok(o) = self.matches(
             |typ| {
                 matches!(
@@ -597,7 +859,7 @@ mod test {
                 lexeme: "",
             },
         ]);*/
-        let r = p.scan();
+        let r = p.scan().unwrap();
         println!("{r:?}");
         assert_eq!(
             r,
@@ -620,32 +882,34 @@ mod test {
                             conflict: None
                         }]
                     }
-                ]
+                ],
+                primary_key: Vec::new(),
+                without_rowid: false,
             })]
         );
     }
     #[test]
     fn test_simple_select() {
         let select = "SELECT * FROM gatito";
-        let p = Parser::new(&scan(select)).scan();
+        let p = Parser::new(&scan(select)).scan().unwrap();
         println!("{select}\n{p:?}");
         let count = "SELECT COUNT(*) FROM gatito";
-        let p = Parser::new(&scan(count)).scan();
+        let p = Parser::new(&scan(count)).scan().unwrap();
         println!("{count}\n{p:?}");
         let cols = "SELECT colore, nome FROM gatito";
-        let p = Parser::new(&scan(cols)).scan();
+        let p = Parser::new(&scan(cols)).scan().unwrap();
         println!("{cols}\n{p:?}");
         let filter = "SELECT colore, nome FROM gatito WHERE colore = 'black'";
-        let p = Parser::new(&scan(filter)).scan();
+        let p = Parser::new(&scan(filter)).scan().unwrap();
         println!("{filter}\n{p:?}");
         let filter_expr = "SELECT colore, nome FROM gatito WHERE colore = TRUE ";
-        let p = Parser::new(&scan(filter_expr)).scan();
+        let p = Parser::new(&scan(filter_expr)).scan().unwrap();
         println!("{filter_expr}\n{p:?}");
         let filter_expr_2 = "SELECT colore, nome FROM gatito WHERE colore = 12 + 2 ";
-        let p = Parser::new(&scan(filter_expr_2)).scan();
+        let p = Parser::new(&scan(filter_expr_2)).scan().unwrap();
         println!("{filter_expr_2}\n{p:?}");
         let filter_expr_3 = "SELECT colore, nome FROM gatito WHERE colore = 12 + 2 + (12 *2)/4 ";
-        let p = Parser::new(&scan(filter_expr_3)).scan();
+        let p = Parser::new(&scan(filter_expr_3)).scan().unwrap();
         println!("{filter_expr_3}\n{p:?}");
     }
     #[test]
@@ -657,7 +921,7 @@ mod test {
   rootpage integer,
   sql text
 );";
-        let p = Parser::new(&scan(schema_def)).scan();
+        let p = Parser::new(&scan(schema_def)).scan().unwrap();
         println!("{schema_def}\n{p:?}");
         let schema_def = "CREATE TABLE sqlite_schema(
   type text,
@@ -673,7 +937,327 @@ CREATE TABLE sqlite_schema(
   rootpage integer,
   sql text
 );";
-        let p = Parser::new(&scan(schema_def)).scan();
+        let p = Parser::new(&scan(schema_def)).scan().unwrap();
         println!("{schema_def}\n{p:?}");
     }
+    #[test]
+    fn test_between() {
+        let between = "SELECT id FROM gatito WHERE rowid BETWEEN 10 AND 20 AND nome = 'x'";
+        let p = Parser::new(&scan(between)).scan().unwrap();
+        let int = |i| {
+            Box::new(Expr::Literal {
+                value: DbValue::Integer(i),
+            })
+        };
+        let rowid = || {
+            Box::new(Expr::Identifier {
+                value: "rowid".to_owned(),
+            })
+        };
+        let Statement::Select(SelectStatement {
+            filter: Some(Expr::Binary { left, .. }),
+            ..
+        }) = &p[0]
+        else {
+            panic!("{p:?}")
+        };
+        assert_eq!(
+            left.as_ref(),
+            &Expr::Grouping {
+                expr: Box::new(Expr::Binary {
+                    left: Box::new(Expr::Binary {
+                        left: rowid(),
+                        right: int(10),
+                        operator: Operator::GreaterEq
+                    }),
+                    right: Box::new(Expr::Binary {
+                        left: rowid(),
+                        right: int(20),
+                        operator: Operator::LessEq
+                    }),
+                    operator: Operator::And
+                })
+            }
+        );
+    }
+    #[test]
+    fn test_create_index() {
+        let index = "CREATE INDEX idx_companies_country on companies (country)";
+        let p = Parser::new(&scan(index)).scan().unwrap();
+        assert_eq!(
+            p,
+            [Statement::Create(CreateStatement::Index {
+                name: "idx_companies_country".to_owned(),
+                table: "companies".to_owned(),
+                unique: false,
+                cols: vec![IndexedColumn {
+                    name: "country".to_owned(),
+                    asc: true
+                }]
+            })]
+        );
+        let index = "CREATE UNIQUE INDEX IF NOT EXISTS idx ON gatito(colore DESC, nome ASC)";
+        let p = Parser::new(&scan(index)).scan().unwrap();
+        assert_eq!(
+            p,
+            [Statement::Create(CreateStatement::Index {
+                name: "idx".to_owned(),
+                table: "gatito".to_owned(),
+                unique: true,
+                cols: vec![
+                    IndexedColumn {
+                        name: "colore".to_owned(),
+                        asc: false
+                    },
+                    IndexedColumn {
+                        name: "nome".to_owned(),
+                        asc: true
+                    }
+                ]
+            })]
+        );
+    }
+    #[test]
+    fn test_pragma() {
+        let p = Parser::new(&scan("PRAGMA integrity_check")).scan().unwrap();
+        assert_eq!(
+            p,
+            [Statement::Pragma {
+                name: "integrity_check".to_owned(),
+                value: None
+            }]
+        );
+        let p = Parser::new(&scan("pragma integrity_check(10)"))
+            .scan()
+            .unwrap();
+        assert_eq!(
+            p,
+            [Statement::Pragma {
+                name: "integrity_check".to_owned(),
+                value: Some(Expr::Literal {
+                    value: DbValue::Integer(10)
+                })
+            }]
+        );
+    }
+
+    #[test]
+    fn test_insert() {
+        let p = Parser::new(&scan(
+            "INSERT INTO people (name, age) VALUES ('a', 1), ('b', -2)",
+        ))
+        .scan()
+        .unwrap();
+        let lit = |value| Expr::Literal { value };
+        assert_eq!(
+            p,
+            [Statement::Insert {
+                table: "people".to_owned(),
+                columns: vec!["name".to_owned(), "age".to_owned()],
+                values: vec![
+                    vec![lit(DbValue::Text("a".to_owned())), lit(DbValue::Integer(1))],
+                    vec![
+                        lit(DbValue::Text("b".to_owned())),
+                        Expr::Unary {
+                            operator: Operator::Minus,
+                            expr: Box::new(lit(DbValue::Integer(2)))
+                        }
+                    ],
+                ]
+            }]
+        );
+        let p = Parser::new(&scan("insert into t values (NULL)"))
+            .scan()
+            .unwrap();
+        assert_eq!(
+            p,
+            [Statement::Insert {
+                table: "t".to_owned(),
+                columns: Vec::new(),
+                values: vec![vec![lit(DbValue::Null)]]
+            }]
+        );
+    }
+
+    #[test]
+    fn test_delete() {
+        let p = Parser::new(&scan("DELETE FROM people WHERE age > 3; delete from t"))
+            .scan()
+            .unwrap();
+        assert_eq!(
+            p,
+            [
+                Statement::Delete {
+                    table: "people".to_owned(),
+                    filter: Some(Expr::Binary {
+                        left: Box::new(Expr::Identifier {
+                            value: "age".to_owned()
+                        }),
+                        right: Box::new(Expr::Literal {
+                            value: DbValue::Integer(3)
+                        }),
+                        operator: Operator::Greater
+                    })
+                },
+                Statement::Delete {
+                    table: "t".to_owned(),
+                    filter: None
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_update() {
+        let p = Parser::new(&scan(
+            "UPDATE people SET age = age + 1, name = 'x' WHERE id = 2",
+        ))
+        .scan()
+        .unwrap();
+        let ident = |value: &str| Expr::Identifier {
+            value: value.to_owned(),
+        };
+        assert_eq!(
+            p,
+            [Statement::Update {
+                table: "people".to_owned(),
+                assignments: vec![
+                    (
+                        "age".to_owned(),
+                        Expr::Binary {
+                            left: Box::new(ident("age")),
+                            right: Box::new(Expr::Literal {
+                                value: DbValue::Integer(1)
+                            }),
+                            operator: Operator::Plus
+                        }
+                    ),
+                    (
+                        "name".to_owned(),
+                        Expr::Literal {
+                            value: DbValue::Text("x".to_owned())
+                        }
+                    )
+                ],
+                filter: Some(Expr::Binary {
+                    left: Box::new(ident("id")),
+                    right: Box::new(Expr::Literal {
+                        value: DbValue::Integer(2)
+                    }),
+                    operator: Operator::Equals
+                })
+            }]
+        );
+    }
+
+    #[test]
+    fn test_transaction() {
+        let p = Parser::new(&scan(
+            "BEGIN; COMMIT; begin transaction; ROLLBACK TRANSACTION; END",
+        ))
+        .scan()
+        .unwrap();
+        assert_eq!(
+            p,
+            [
+                Statement::Begin,
+                Statement::Commit,
+                Statement::Begin,
+                Statement::Rollback,
+                Statement::Commit
+            ]
+        );
+    }
+
+    #[test]
+    fn test_without_rowid() {
+        let p = Parser::new(&scan(
+            "CREATE TABLE t (a TEXT, b INTEGER, PRIMARY KEY (b DESC, a)) WITHOUT ROWID;
+             CREATE TABLE r (id INTEGER, PRIMARY KEY (id))",
+        ))
+        .scan()
+        .unwrap();
+        let [Statement::Create(CreateStatement::Table {
+            cols,
+            primary_key,
+            without_rowid,
+            ..
+        }), Statement::Create(CreateStatement::Table {
+            cols: alias_cols,
+            primary_key: alias_key,
+            without_rowid: false,
+            ..
+        })] = p.as_slice()
+        else {
+            panic!("unexpected statements {p:?}");
+        };
+        assert!(without_rowid);
+        assert_eq!(cols.len(), 2);
+        assert_eq!(
+            primary_key,
+            &[
+                IndexedColumn {
+                    name: "b".to_owned(),
+                    asc: false
+                },
+                IndexedColumn {
+                    name: "a".to_owned(),
+                    asc: true
+                }
+            ]
+        );
+        // a lone INTEGER key of a rowid table is the rowid itself
+        assert!(alias_key.is_empty());
+        assert!(matches!(
+            alias_cols[0].constraint.as_slice(),
+            [ColumnConstraint::Pk { asc: true, .. }]
+        ));
+        assert!(Parser::new(&scan("CREATE TABLE t (a TEXT) WITHOUT oid"))
+            .scan()
+            .is_err());
+    }
+
+    #[test]
+    fn test_limit() {
+        let p = Parser::new(&scan(
+            "SELECT id FROM gatito WHERE nome = 'x' LIMIT 5 OFFSET 10; SELECT * FROM gatito",
+        ))
+        .scan()
+        .unwrap();
+        let int = |i| Expr::Literal {
+            value: DbValue::Integer(i),
+        };
+        let [Statement::Select(SelectStatement {
+            filter: Some(_),
+            limit,
+            offset,
+            ..
+        }), Statement::Select(SelectStatement {
+            limit: None,
+            offset: None,
+            ..
+        })] = p.as_slice()
+        else {
+            panic!("unexpected statements {p:?}");
+        };
+        assert_eq!(limit, &Some(int(5)));
+        assert_eq!(offset, &Some(int(10)));
+    }
+
+    #[test]
+    fn test_vacuum() {
+        let p = Parser::new(&scan("VACUUM; vacuum INTO '/tmp/copy.db'"))
+            .scan()
+            .unwrap();
+        assert_eq!(
+            p,
+            [
+                Statement::Vacuum { into: None },
+                Statement::Vacuum {
+                    into: Some("/tmp/copy.db".to_owned())
+                }
+            ]
+        );
+        assert!(Parser::new(&scan("VACUUM INTO copy")).scan().is_err());
+    }
 }
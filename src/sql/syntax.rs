@@ -16,15 +16,58 @@ pub(crate) enum DbValue {
 pub(crate) enum Statement {
     Create(CreateStatement),
     Select(SelectStatement),
+    /// `columns` is empty when the values are given for every column of the table, in order
+    Insert {
+        table: String,
+        columns: Vec<String>,
+        values: Vec<Vec<Expr>>,
+    },
+    /// every row goes without a filter
+    Delete {
+        table: String,
+        filter: Option<Expr>,
+    },
+    /// columns paired with their new value, computed from the old row
+    Update {
+        table: String,
+        assignments: Vec<(String, Expr)>,
+        filter: Option<Expr>,
+    },
+    Begin,
+    Commit,
+    Rollback,
+    Pragma {
+        name: String,
+        value: Option<Expr>,
+    },
+    /// rebuilds the database in place, or into a new file at `into`
+    Vacuum {
+        into: Option<String>,
+    },
 }
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) enum CreateStatement {
     Table {
         name: String,
         cols: Vec<ColumnDefinition>,
+        /// given after the columns, empty when the key is declared on a column (or missing)
+        primary_key: Vec<IndexedColumn>,
+        /// the rows live in an index b-tree keyed by the primary key rather than by rowid
+        without_rowid: bool,
+    },
+    Index {
+        name: String,
+        table: String,
+        unique: bool,
+        cols: Vec<IndexedColumn>,
     },
 }
 #[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct IndexedColumn {
+    pub name: String,
+    pub asc: bool,
+}
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub(crate) struct ColumnDefinition {
     pub name: String,
     pub position: usize,
@@ -60,6 +103,9 @@ pub(crate) struct SelectStatement {
     pub from: String,
     pub fields: Vec<Expr>,
     pub filter: Option<Expr>,
+    /// at most this many rows are returned, after skipping the first `offset` ones
+    pub limit: Option<Expr>,
+    pub offset: Option<Expr>,
 }
 #[derive(Debug, PartialEq, Clone)]
 pub(crate) enum Expr {
@@ -106,12 +152,27 @@ pub(crate) enum Operator {
     And,
     Not,
 }
+impl Operator {
+    /// The operator to use when swapping the operands of a comparison
+    pub(crate) fn flip(&self) -> Option<Self> {
+        Some(match self {
+            Operator::Equals => Operator::Equals,
+            Operator::Notequals => Operator::Notequals,
+            Operator::Less => Operator::Greater,
+            Operator::Greater => Operator::Less,
+            Operator::LessEq => Operator::GreaterEq,
+            Operator::GreaterEq => Operator::LessEq,
+            _ => return None,
+        })
+    }
+}
 #[derive(Debug, PartialEq, Clone)]
 pub(crate) enum FunctionArg {
     Star,
     Args(Vec<Expr>),
 }
 
+#[cfg(test)]
 pub(crate) trait Visit<T> {
     fn visit_expr(&mut self, e: &Expr) -> T;
     // NOTE: non penso siano necessari
@@ -121,13 +182,16 @@ pub(crate) trait Visit<T> {
     fn visit_column_definition(&mut self, e: &ColumnDefinition) -> T;
 }
 
+#[cfg(test)]
 pub(crate) struct AstPrinter(pub String);
+#[cfg(test)]
 impl AstPrinter {
     pub fn print(&mut self, s: &Statement) {
         self.visit_statement(s);
     }
 }
 
+#[cfg(test)]
 impl Visit<()> for AstPrinter {
     fn visit_expr(&mut self, e: &Expr) -> () {
         match e {
@@ -177,12 +241,35 @@ impl Visit<()> for AstPrinter {
 
     fn visit_statement(&mut self, e: &Statement) -> () {
         match e {
-            Statement::Create(CreateStatement::Table { name, cols }) => {
-                println!("{}create table {name}", self.0);
+            Statement::Create(CreateStatement::Table {
+                name,
+                cols,
+                primary_key,
+                without_rowid,
+            }) => {
+                let without = if *without_rowid { " without rowid" } else { "" };
+                println!("{}create table {name}{without}", self.0);
                 self.0.push('\t');
                 for col in cols {
                     self.visit_column_definition(col);
                 }
+                for col in primary_key {
+                    println!("{}primary key: {} asc: {}", self.0, col.name, col.asc);
+                }
+                self.0.pop();
+            }
+            Statement::Create(CreateStatement::Index {
+                name,
+                table,
+                unique,
+                cols,
+            }) => {
+                let unique = if *unique { "unique " } else { "" };
+                println!("{}create {unique}index {name} on {table}", self.0);
+                self.0.push('\t');
+                for col in cols {
+                    println!("{}column: {} asc: {}", self.0, col.name, col.asc);
+                }
                 self.0.pop();
             }
 
@@ -190,6 +277,8 @@ impl Visit<()> for AstPrinter {
                 from,
                 fields,
                 filter,
+                limit,
+                offset,
             }) => {
                 println!("{}select from {from}:", self.0);
                 self.0.push('\t');
@@ -203,8 +292,74 @@ impl Visit<()> for AstPrinter {
                 if let Some(f) = filter {
                     self.visit_expr(f)
                 }
+                if let Some(limit) = limit {
+                    println!("{}limit:", self.0);
+                    self.visit_expr(limit);
+                }
+                if let Some(offset) = offset {
+                    println!("{}offset:", self.0);
+                    self.visit_expr(offset);
+                }
+                self.0.pop();
+            }
+            Statement::Insert {
+                table,
+                columns,
+                values,
+            } => {
+                println!("{}insert into {table} {columns:?}:", self.0);
+                self.0.push('\t');
+                for row in values {
+                    println!("{}row:", self.0);
+                    self.0.push('\t');
+                    for value in row {
+                        self.visit_expr(value);
+                    }
+                    self.0.pop();
+                }
                 self.0.pop();
             }
+            Statement::Delete { table, filter } => {
+                println!("{}delete from {table}:", self.0);
+                if let Some(f) = filter {
+                    self.0.push('\t');
+                    self.visit_expr(f);
+                    self.0.pop();
+                }
+            }
+            Statement::Update {
+                table,
+                assignments,
+                filter,
+            } => {
+                println!("{}update {table}:", self.0);
+                self.0.push('\t');
+                for (column, value) in assignments {
+                    println!("{}set {column}:", self.0);
+                    self.0.push('\t');
+                    self.visit_expr(value);
+                    self.0.pop();
+                }
+                if let Some(f) = filter {
+                    self.visit_expr(f);
+                }
+                self.0.pop();
+            }
+            Statement::Begin => println!("{}begin", self.0),
+            Statement::Commit => println!("{}commit", self.0),
+            Statement::Rollback => println!("{}rollback", self.0),
+            Statement::Pragma { name, value } => {
+                println!("{}pragma {name}", self.0);
+                if let Some(value) = value {
+                    self.0.push('\t');
+                    self.visit_expr(value);
+                    self.0.pop();
+                }
+            }
+            Statement::Vacuum { into } => match into {
+                Some(path) => println!("{}vacuum into {path}", self.0),
+                None => println!("{}vacuum", self.0),
+            },
         }
     }
 
@@ -255,6 +410,7 @@ impl Neg for DbValue {
     fn neg(self) -> Self::Output {
         match self {
             DbValue::Integer(i) => Self::Integer(-i),
+            DbValue::Float(f) => Self::Float(-f),
             _ => panic!("Nooo"),
         }
     }
@@ -302,12 +458,12 @@ mod test {
     fn test_print() {
         let mut printer = AstPrinter("".to_owned());
         let stmt = "CREATE TABLE ciao ( id INTEGER PRIMARY KEY, name TEXT)";
-        let tree = parser::Parser::new(&lexer::scan(stmt)).scan();
+        let tree = parser::Parser::new(&lexer::scan(stmt)).scan().unwrap();
         for s in &tree {
             printer.print(s);
         }
         let stmt = "SELECT name, sum(gatito, id), id from kitty where age * 2 - (15 - 3) >= 10";
-        let tree = parser::Parser::new(&lexer::scan(stmt)).scan();
+        let tree = parser::Parser::new(&lexer::scan(stmt)).scan().unwrap();
         for s in &tree {
             printer.print(s);
         }